@@ -0,0 +1,116 @@
+// Decode cache and block translator benchmark.
+//
+//   cargo run --release --example cpu_bench [disk.img] [millions of instructions]
+//
+// Runs a small arithmetic/memory loop from RAM, and with a disk image also
+// the Oberon boot: interpreted without and with the decode cache, then
+// translated.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use risc_emulator::devices::disk::DiskMode;
+use risc_emulator::devices::timer::TimerMode;
+use risc_emulator::translate::ExecMode;
+use risc_emulator::Machine;
+
+const MOV: u32 = 0;
+const LSL: u32 = 1;
+const XOR: u32 = 7;
+const ADD: u32 = 8;
+const MUL: u32 = 10;
+
+fn reg(op: u32, a: u32, b: u32, c: u32) -> u32 {
+    (a << 24) | (b << 20) | (op << 16) | c
+}
+
+fn reg_imm(op: u32, a: u32, b: u32, imm: u32) -> u32 {
+    0x4000_0000 | (a << 24) | (b << 20) | (op << 16) | (imm & 0xFFFF)
+}
+
+fn mem(a: u32, b: u32, off: u32, store: bool) -> u32 {
+    0x8000_0000 | if store { 0x2000_0000 } else { 0 } | (a << 24) | (b << 20) | (off & 0xF_FFFF)
+}
+
+fn loop_machine() -> Machine {
+    let prog = [
+        reg_imm(MOV, 0, 0, 0),
+        reg_imm(MOV, 1, 0, 0),
+        reg_imm(MOV, 3, 0, 0x1000),
+        // loop:
+        reg(ADD, 1, 1, 0),
+        reg(MUL, 2, 0, 0),
+        reg(XOR, 1, 1, 2),
+        mem(1, 3, 0, true),
+        mem(4, 3, 0, false),
+        reg_imm(ADD, 0, 0, 1),
+        reg_imm(LSL, 5, 0, 3),
+        0xE7FF_FFF8, // B loop
+    ];
+    Machine::new_for_tests_in_ram(&prog, 0x10000)
+}
+
+fn boot_machine(image: &Path) -> Machine {
+    let mut m = Machine::with_timer_mode(1024, 768, TimerMode::deterministic());
+    m.attach_disk(1, image, DiskMode::Overlay).expect("attach disk");
+    m
+}
+
+// stops early when the guest faults (e.g. an image without boot file)
+fn time(mut m: Machine, cached: bool, instructions: u64) -> (Duration, u64) {
+    m.bus.set_decode_cache(cached);
+    let t = Instant::now();
+    let mut done = 0;
+    while done < instructions && m.cpu.step(&mut m.bus).is_ok() {
+        done += 1;
+    }
+    (t.elapsed(), done)
+}
+
+fn time_translated(mut m: Machine, instructions: u64) -> Duration {
+    m.exec = ExecMode::Translate;
+    let t = Instant::now();
+    let mut done = 0;
+    while done < instructions {
+        let chunk = (instructions - done).min(1_000_000) as u32;
+        let out = m.run(chunk);
+        done += out.instructions as u64;
+        if out.fault().is_some() {
+            break;
+        }
+    }
+    t.elapsed()
+}
+
+fn report(name: &str, make: impl Fn() -> Machine, instructions: u64) {
+    let (plain, n) = time(make(), false, instructions);
+    let (cached, _) = time(make(), true, n);
+    let translated = time_translated(make(), n);
+    let mips = |d: Duration| n as f64 / d.as_secs_f64() / 1e6;
+    println!(
+        "{name:6} {:>6}M instr {:8.1} MIPS uncached {:8.1} MIPS cached (x{:.2}) {:8.1} MIPS translated (x{:.2})",
+        n / 1_000_000,
+        mips(plain),
+        mips(cached),
+        plain.as_secs_f64() / cached.as_secs_f64(),
+        mips(translated),
+        plain.as_secs_f64() / translated.as_secs_f64()
+    );
+}
+
+fn main() {
+    let mut image = None;
+    let mut millions = 50;
+    for arg in std::env::args().skip(1) {
+        match arg.parse() {
+            Ok(m) => millions = m,
+            Err(_) => image = Some(PathBuf::from(arg)),
+        }
+    }
+    let n = millions * 1_000_000;
+
+    report("loop", loop_machine, n);
+    if let Some(image) = image {
+        report("boot", || boot_machine(&image), n);
+    }
+}
@@ -1,3 +1,7 @@
+use risc_emulator::devices::disk::DiskMode;
+use risc_emulator::devices::serial::SerialSpec;
+use risc_emulator::devices::timer::TimerMode;
+use risc_emulator::translate::ExecMode;
 use risc_emulator::ui::app::EmuApp;
 use eframe::egui;
 use clap::Parser;
@@ -12,6 +16,38 @@ struct Args {
     /// Secondary disk image (mounts on SPI2)
     #[arg(long)]
     disk2: Option<PathBuf>,
+
+    /// How disk writes are stored: rw, ro, overlay (memory) or sidecar[:PATH]
+    #[arg(long, default_value = "rw")]
+    disk_mode: DiskMode,
+
+    /// How guest code runs: interp, translate or lockstep
+    #[arg(long, default_value = "interp")]
+    exec: ExecMode,
+
+    /// Drive the millisecond timer from executed instructions instead of host time
+    #[arg(long)]
+    virtual_clock: bool,
+
+    /// Serial line backend: stdio, pty, unix:PATH, tcp:PORT or file:IN,OUT
+    #[arg(long)]
+    serial: Option<SerialSpec>,
+
+    /// Record the last N instructions from the start, for Step back
+    #[arg(long, value_name = "N")]
+    history: Option<usize>,
+
+    /// Initial switch bank value (decimal or 0x-prefixed hex)
+    #[arg(long, value_parser = parse_u32)]
+    switches: Option<u32>,
+}
+
+fn parse_u32(s: &str) -> Result<u32, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("invalid number \"{s}\": {e}"))
 }
 
 fn main() -> eframe::Result<()> {
@@ -21,10 +57,28 @@ fn main() -> eframe::Result<()> {
     if let Some(path) = args.disk1 { disk1 = Some(path); }
     if let Some(path) = args.disk2 { disk2 = Some(path); }
 
+    let timer_mode = if args.virtual_clock { TimerMode::deterministic() } else { TimerMode::WallClock };
+
+    let mut app = EmuApp::new(1024, 768, disk1, disk2, args.disk_mode, timer_mode);
+    app.set_exec_mode(args.exec);
+    app.set_history(args.history);
+    if let Some(v) = args.switches {
+        app.set_switches(v);
+    }
+    if let Some(spec) = args.serial {
+        match spec.open() {
+            Ok(backend) => {
+                eprintln!("serial: {}", backend.name());
+                app.attach_serial(Box::new(backend));
+            }
+            Err(e) => eprintln!("serial: {e}"),
+        }
+    }
+
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "RISC Emulator",
         native_options,
-        Box::new(|_cc| Ok(Box::new(EmuApp::new(1024, 768, disk1, disk2)))),
+        Box::new(|_cc| Ok(Box::new(app))),
     )
 }
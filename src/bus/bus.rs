@@ -1,6 +1,6 @@
 pub type BusResult<T> = Result<T, BusError>;
 
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
 pub enum BusError {
     #[error("address out of bounds: 0x{0:08X}")]
     AddressOutOfBounds(u32),
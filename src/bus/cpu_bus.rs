@@ -1,7 +1,59 @@
 use crate::bus::BusResult;
+use crate::debug::DebugHit;
+use crate::decode::Decoded;
 
 /// CPU-bus er *progress-aware* på reads (så vi matcher din C progress-- heuristik).
 pub trait CpuBus {
     fn read_word_for_cpu(&mut self, addr: u32, progress: &mut u32) -> BusResult<u32>;
     fn write_word(&mut self, addr: u32, value: u32) -> BusResult<()>;
+
+    /// Byte load; reads the whole word.
+    #[inline]
+    fn read_byte_for_cpu(&mut self, addr: u32, progress: &mut u32) -> BusResult<u8> {
+        let w = self.read_word_for_cpu(addr & !3, progress)?;
+        Ok((w >> ((addr & 3) * 8)) as u8)
+    }
+
+    /// Byte store, done as read-modify-write of the word.
+    #[inline]
+    fn write_byte_for_cpu(&mut self, addr: u32, value: u8, progress: &mut u32) -> BusResult<()> {
+        let base = addr & !3;
+        let shift = (addr & 3) * 8;
+        let w = self.read_word_for_cpu(base, progress)?;
+        self.write_word(base, (w & !(0xFF << shift)) | ((value as u32) << shift))
+    }
+
+    /// Instruction fetch. Buses with a decode cache override this.
+    #[inline]
+    fn fetch_decoded(&mut self, addr: u32, progress: &mut u32) -> BusResult<Decoded> {
+        self.read_word_for_cpu(addr, progress).map(Decoded::new)
+    }
+
+    /// Instructions retired, drives the virtual-time millisecond counter.
+    fn advance_clock(&mut self, _instructions: u32) {}
+
+    /// Takes back `advance_clock` for reverse execution.
+    fn rewind_clock(&mut self, _instructions: u32) {}
+
+    /// Puts back a RAM word for reverse execution, without the checks and
+    /// events of a CPU store.
+    fn restore_word(&mut self, addr: u32, value: u32) -> BusResult<()> {
+        self.write_word(addr, value)
+    }
+
+    /// Interrupt line, sampled by the CPU between instructions.
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    /// Word at `addr` for the debugger, without side effects; `None` where
+    /// that isn't possible.
+    fn peek_word(&mut self, _addr: u32) -> Option<u32> {
+        None
+    }
+
+    /// Watchpoint or device breakpoint hit by the last instruction, if any.
+    fn take_debug_hit(&mut self) -> Option<DebugHit> {
+        None
+    }
 }
\ No newline at end of file
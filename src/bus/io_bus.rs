@@ -2,13 +2,15 @@ use crate::{
     bus::{BusError, BusResult},
     devices::IoDevice,
 };
+use crate::debug::DeviceEvent;
 use crate::devices::spi::SpiDevice;
+use crate::devices::timer::Timer;
 
 #[derive(Debug)]
 pub struct IoBus {
     io_start: u32,
 
-    pub timer: Box<dyn IoDevice>,
+    pub timer: Timer,
     pub switches: Box<dyn IoDevice>,
     pub serial: Option<Box<dyn IoDevice>>,
     pub spi: [Option<Box<dyn SpiDevice>>; 4],
@@ -17,12 +19,15 @@ pub struct IoBus {
     pub leds: Option<Box<dyn IoDevice>>,
 
     pub spi_selected: u32,
+
+    // last SD command or keyboard byte, for device breakpoints
+    event: Option<DeviceEvent>,
 }
 
 impl IoBus {
     pub fn new(
         io_start: u32,
-        timer: Box<dyn IoDevice>,
+        timer: Timer,
         switches: Box<dyn IoDevice>,
     ) -> Self {
         Self {
@@ -35,9 +40,19 @@ impl IoBus {
             clipboard: None,
             leds: None,
             spi_selected: 0,
+            event: None,
         }
     }
 
+    pub fn io_start(&self) -> u32 {
+        self.io_start
+    }
+
+    /// SD command or keyboard byte from the last access, if there was one.
+    pub fn take_event(&mut self) -> Option<DeviceEvent> {
+        self.event.take()
+    }
+
     #[inline]
     fn progress_dec(progress: &mut u32) {
         *progress = progress.saturating_sub(1);
@@ -77,7 +92,14 @@ impl IoBus {
                 }
                 Ok(v)
             }
-            28 => self.input.read(28),
+            28 => {
+                let key = self.input.key_pending();
+                let v = self.input.read(28)?;
+                if key {
+                    self.event = Some(DeviceEvent::Key(v as u8));
+                }
+                Ok(v)
+            }
 
             40 => self.clipboard.as_deref_mut().map(|d| d.read(40)).unwrap_or(Ok(0)),
             44 => self.clipboard.as_deref_mut().map(|d| d.read(44)).unwrap_or(Ok(0)),
@@ -98,9 +120,20 @@ impl IoBus {
 
             16 => {
                 let idx = (self.spi_selected & 3) as usize;
-                self.spi[idx].as_deref_mut()
-                    .map(|d| d.write_data(value))
-                    .unwrap_or(Ok(()))
+                let Some(dev) = self.spi[idx].as_deref_mut() else {
+                    return Ok(());
+                };
+                dev.write_data(value)?;
+                if let Some(c) = dev.take_command() {
+                    self.event = Some(DeviceEvent::SdCommand {
+                        spi: idx as u8,
+                        cmd: c.cmd,
+                        app: c.app,
+                        arg: c.arg,
+                        sector: c.sector,
+                    });
+                }
+                Ok(())
             }
             20 => {
                 self.spi_selected = value & 3;
@@ -114,6 +147,16 @@ impl IoBus {
         }
     }
 
+    /// OR of the interrupt lines of all attached devices.
+    pub fn irq_pending(&self) -> bool {
+        self.timer.irq_pending()
+            || self.switches.irq_pending()
+            || self.input.irq_pending()
+            || self.serial.as_deref().is_some_and(|d| d.irq_pending())
+            || self.clipboard.as_deref().is_some_and(|d| d.irq_pending())
+            || self.leds.as_deref().is_some_and(|d| d.irq_pending())
+    }
+
     pub fn set_spi(&mut self, index: usize, dev: Box<dyn SpiDevice>) -> BusResult<()> {
         if index == 1 || index == 2 {
             self.spi[index] = Some(dev);
@@ -1,5 +1,7 @@
 use crate::{
     bus::{Bus, BusError, BusResult, CpuBus},
+    debug::{Access, DebugHit, DeviceBreaks, DeviceEvent, WatchHit, Watchpoints},
+    decode::{DecodeCache, Decoded},
     memory::{framebuffer::Damage, ram::Ram, rom::Rom},
     bus::io_bus::IoBus,
 };
@@ -13,9 +15,27 @@ pub struct SystemBus {
     pub fb_height: i32,
     pub damage: Damage,
 
+    /// Writing `ram` directly bypasses the decode cache; call
+    /// `flush_decode_cache` afterwards.
     pub ram: Ram,
     pub rom: Rom,
     pub io: IoBus,
+
+    /// Checked on CPU loads and stores in RAM.
+    pub watchpoints: Watchpoints,
+    /// Checked on CPU IO and framebuffer accesses and on device events.
+    pub device_breaks: DeviceBreaks,
+    debug_hit: Option<DebugHit>,
+
+    // predecoded instructions, RAM writes invalidate
+    ram_code: DecodeCache,
+    rom_code: DecodeCache,
+    decode_cache: bool,
+
+    // RAM words that are part of translated blocks, one bit each; writing
+    // one bumps `code_epoch`
+    translated: Vec<u64>,
+    code_epoch: u64,
 }
 
 impl SystemBus {
@@ -29,6 +49,8 @@ impl SystemBus {
         rom: Rom,
         io: IoBus,
     ) -> Self {
+        let ram_code = DecodeCache::new(0, mem_size);
+        let rom_code = DecodeCache::new(rom.start(), rom.len_bytes());
         Self {
             mem_size,
             display_start,
@@ -38,7 +60,136 @@ impl SystemBus {
             ram,
             rom,
             io,
+            watchpoints: Watchpoints::default(),
+            device_breaks: DeviceBreaks::default(),
+            debug_hit: None,
+            ram_code,
+            rom_code,
+            decode_cache: true,
+            translated: vec![0; (mem_size as usize).div_ceil(4 * 64)],
+            code_epoch: 0,
+        }
+    }
+
+    /// Turns the decode cache on or off (it is on by default).
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled;
+        self.flush_decode_cache();
+    }
+
+    pub fn decode_cache_enabled(&self) -> bool {
+        self.decode_cache
+    }
+
+    /// Forgets all predecoded instructions.
+    pub fn flush_decode_cache(&mut self) {
+        self.ram_code.clear();
+        self.code_changed();
+    }
+
+    /// Swaps in new RAM and ROM (loading a save state); the framebuffer
+    /// geometry stays.
+    pub(crate) fn replace_memory(&mut self, ram: Ram, rom: Rom, display_start: u32) {
+        self.mem_size = ram.len();
+        self.display_start = display_start;
+        self.ram_code = DecodeCache::new(0, self.mem_size);
+        self.rom_code = DecodeCache::new(rom.start(), rom.len_bytes());
+        self.translated = vec![0; (self.mem_size as usize).div_ceil(4 * 64)];
+        self.ram = ram;
+        self.rom = rom;
+        self.damage = Damage::full(self.fb_width_words, self.fb_height);
+        self.code_changed();
+    }
+
+    /// Changes whenever translated code in RAM is overwritten.
+    pub fn code_epoch(&self) -> u64 {
+        self.code_epoch
+    }
+
+    pub(crate) fn mark_translated(&mut self, addr: u32) {
+        let w = (addr >> 2) as usize;
+        if let Some(bits) = self.translated.get_mut(w / 64) {
+            *bits |= 1 << (w % 64);
+        }
+    }
+
+    fn code_changed(&mut self) {
+        self.code_epoch += 1;
+        self.translated.fill(0);
+    }
+
+    /// True while watchpoints or device breakpoints are set; CPU accesses
+    /// are checked one by one then.
+    #[inline]
+    pub fn debug_checks(&self) -> bool {
+        !self.watchpoints.is_empty() || !self.device_breaks.is_empty()
+    }
+
+    // keeps the first hit of an instruction
+    fn debug_hit(&mut self, hit: DebugHit) {
+        self.debug_hit.get_or_insert(hit);
+    }
+
+    fn device_event(&mut self, event: DeviceEvent) {
+        if self.device_breaks.check(&event) {
+            self.debug_hit(DebugHit::Device(event));
+        }
+    }
+
+    // SD commands and key bytes seen by the IO devices
+    fn io_events(&mut self) {
+        if let Some(event) = self.io.take_event() {
+            self.device_event(event);
+        }
+    }
+
+    fn io_offset(&self, addr: u32) -> Option<u32> {
+        if self.is_in_ram(addr) || self.rom.contains(addr & !3) {
+            return None;
+        }
+        addr.checked_sub(self.io.io_start())
+    }
+
+    /// A CPU load of `size` bytes at `addr` that returned `value`.
+    fn check_load(&mut self, addr: u32, size: u8, value: u32) {
+        if self.is_in_ram(addr) {
+            if self.watchpoints.check(Access::Read, addr, size as u32, value, value) {
+                let access = Access::Read;
+                self.debug_hit(DebugHit::Watch(WatchHit { pc: 0, addr, size, access, old: value, new: value }));
+            }
+        } else if let Some(offset) = self.io_offset(addr) {
+            self.device_event(DeviceEvent::Io { offset, access: Access::Read, value });
+        }
+    }
+
+    /// A CPU store of `size` bytes at `addr`, `old` and `new` being those
+    /// bytes before and after.
+    fn check_store(&mut self, addr: u32, size: u8, old: u32, new: u32) {
+        if self.is_in_ram(addr) {
+            if self.watchpoints.check(Access::Write, addr, size as u32, old, new) {
+                let access = Access::Write;
+                self.debug_hit(DebugHit::Watch(WatchHit { pc: 0, addr, size, access, old, new }));
+            }
+            if self.is_in_fb(addr) {
+                let i = ((addr & !3) - self.display_start) / 4;
+                let w = self.fb_width_words as u32;
+                self.device_event(DeviceEvent::Framebuffer { x: i % w * 32, y: i / w });
+            }
+        } else if let Some(offset) = self.io_offset(addr) {
+            self.device_event(DeviceEvent::Io { offset, access: Access::Write, value: new });
+        }
+    }
+
+    fn read_for_cpu(&mut self, a: u32, progress: &mut u32) -> BusResult<u32> {
+        if self.is_in_ram(a) {
+            return self.ram.read_word_le(a);
+        }
+        if self.rom.contains(a) {
+            return self.rom.read_word(a);
         }
+        let w = self.io.read_word_with_progress(a, progress)?;
+        self.io_events();
+        Ok(w)
     }
 
     pub fn reset_damage(&mut self) -> Damage {
@@ -61,18 +212,93 @@ impl SystemBus {
 impl CpuBus for SystemBus {
     fn read_word_for_cpu(&mut self, addr: u32, progress: &mut u32) -> BusResult<u32> {
         let a = addr & !3;
-        if self.is_in_ram(a) {
-            return self.ram.read_word_le(a);
-        }
-        if self.rom.contains(a) {
-            return self.rom.read_word(a);
+        let w = self.read_for_cpu(a, progress)?;
+        if self.debug_checks() {
+            self.check_load(a, 4, w);
         }
-        self.io.read_word_with_progress(a, progress)
+        Ok(w)
     }
 
     fn write_word(&mut self, addr: u32, value: u32) -> BusResult<()> {
+        let a = addr & !3;
+        if !self.debug_checks() {
+            return <Self as Bus>::write_word(self, a, value);
+        }
+        let old = if self.is_in_ram(a) { self.ram.read_word_le(a)? } else { 0 };
+        <Self as Bus>::write_word(self, a, value)?;
+        self.check_store(a, 4, old, value);
+        Ok(())
+    }
+
+    fn read_byte_for_cpu(&mut self, addr: u32, progress: &mut u32) -> BusResult<u8> {
+        let w = self.read_for_cpu(addr & !3, progress)?;
+        let b = (w >> ((addr & 3) * 8)) as u8;
+        if self.debug_checks() {
+            self.check_load(addr, 1, b as u32);
+        }
+        Ok(b)
+    }
+
+    fn write_byte_for_cpu(&mut self, addr: u32, value: u8, progress: &mut u32) -> BusResult<()> {
+        let base = addr & !3;
+        let shift = (addr & 3) * 8;
+        if self.io_offset(base).is_some() {
+            // no read first: IO reads take data (the serial port's byte)
+            self.io.write_word(base, (value as u32) << shift)?;
+            self.io_events();
+            if self.debug_checks() {
+                self.check_store(addr, 1, 0, value as u32);
+            }
+            return Ok(());
+        }
+        let w = self.read_for_cpu(base, progress)?;
+        <Self as Bus>::write_word(self, base, (w & !(0xFF << shift)) | ((value as u32) << shift))?;
+        if self.debug_checks() {
+            self.check_store(addr, 1, (w >> shift) & 0xFF, value as u32);
+        }
+        Ok(())
+    }
+
+    fn peek_word(&mut self, addr: u32) -> Option<u32> {
+        self.peek_word_le(addr).ok()
+    }
+
+    fn take_debug_hit(&mut self) -> Option<DebugHit> {
+        self.debug_hit.take()
+    }
+
+    #[inline]
+    fn fetch_decoded(&mut self, addr: u32, progress: &mut u32) -> BusResult<Decoded> {
+        let a = addr & !3;
+        if self.decode_cache {
+            if self.is_in_ram(a) {
+                let ram = &self.ram;
+                return self.ram_code.fetch(a, || ram.read_word_le(a));
+            }
+            if self.rom.contains(a) {
+                let rom = &self.rom;
+                return self.rom_code.fetch(a, || rom.read_word(a));
+            }
+        }
+        self.read_for_cpu(a, progress).map(Decoded::new)
+    }
+
+    #[inline]
+    fn advance_clock(&mut self, instructions: u32) {
+        self.io.timer.advance(instructions);
+    }
+
+    fn rewind_clock(&mut self, instructions: u32) {
+        self.io.timer.rewind(instructions);
+    }
+
+    fn restore_word(&mut self, addr: u32, value: u32) -> BusResult<()> {
         <Self as Bus>::write_word(self, addr, value)
     }
+
+    fn irq_pending(&mut self) -> bool {
+        self.io.irq_pending()
+    }
 }
 
 impl Bus for SystemBus {
@@ -92,6 +318,11 @@ impl Bus for SystemBus {
 
         if self.is_in_ram(a) {
             self.ram.write_word_le(a, value)?;
+            self.ram_code.invalidate(a);
+            let w = (a >> 2) as usize;
+            if self.translated.get(w / 64).is_some_and(|bits| bits & (1 << (w % 64)) != 0) {
+                self.code_changed();
+            }
             if self.is_in_fb(a) {
                 let fb_word_index = ((a - self.display_start) as i32) / 4;
                 self.damage
@@ -104,7 +335,9 @@ impl Bus for SystemBus {
             return Err(BusError::Device("write to ROM".into()));
         }
 
-        self.io.write_word(a, value)
+        self.io.write_word(a, value)?;
+        self.io_events();
+        Ok(())
     }
 }
 
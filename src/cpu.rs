@@ -1,29 +1,115 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
 use crate::{
-    bus::{BusResult, CpuBus},
+    bus::{BusError, BusResult, CpuBus},
+    debug::{
+        history::Snapshot, Breakpoints, DebugHit, DeviceHit, History, MemAccess, Store, TraceRecord, Tracer,
+        Undone, WatchHit,
+    },
+    decode::{Decoded, Op, IMM},
     fp,
 };
 
 pub const ROM_START: u32 = 0xFFFF_F800;
 
-// opcode IDs (samme rækkefølge som i C)
-const MOV: u32 = 0;
-const LSL: u32 = 1;
-const ASR: u32 = 2;
-const ROR: u32 = 3;
-const AND: u32 = 4;
-const ANN: u32 = 5;
-const IOR: u32 = 6;
-const XOR: u32 = 7;
-const ADD: u32 = 8;
-const SUB: u32 = 9;
-const MUL: u32 = 10;
-const DIV: u32 = 11;
-const FAD: u32 = 12;
-const FSB: u32 = 13;
-const FML: u32 = 14;
-const FDV: u32 = 15;
-
-#[derive(Debug, Default)]
+/// Interrupt vector of the extended RISC5 (word 1).
+pub const INT_VECTOR: u32 = 4;
+
+const UBIT: u32 = 0x2000_0000;
+const VBIT: u32 = 0x1000_0000;
+
+/// A bus error raised by an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    /// Address of the instruction.
+    pub pc: u32,
+    /// The instruction word; `None` when fetching it failed.
+    pub ir: Option<u32>,
+    /// The address that could not be accessed.
+    pub addr: u32,
+    pub error: BusError,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ir {
+            Some(ir) => write!(f, "{} at PC {:08X} (IR {ir:08X}, address {:08X})", self.error, self.pc, self.addr),
+            None => write!(f, "{} fetching instruction at PC {:08X}", self.error, self.pc),
+        }
+    }
+}
+
+impl std::error::Error for Fault {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<Fault> for BusError {
+    fn from(f: Fault) -> Self {
+        BusError::Device(f.to_string())
+    }
+}
+
+/// Why a run stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// All requested instructions ran.
+    Budget,
+    /// The guest is polling IO (the `progress` heuristic).
+    Idle,
+    /// PC reached a breakpoint; the instruction there has not run.
+    Breakpoint(u32),
+    /// A watched data address was accessed; the instruction has run.
+    Watchpoint(WatchHit),
+    /// A device breakpoint matched; the instruction has run.
+    Device(DeviceHit),
+    Fault(Fault),
+    /// `Machine::reverse_continue` ran out of recorded instructions.
+    HistoryStart,
+}
+
+impl StopReason {
+    /// Stop for a hit reported by the bus after the instruction at `pc`.
+    pub(crate) fn debug_hit(hit: DebugHit, pc: u32) -> Self {
+        match hit {
+            DebugHit::Watch(w) => StopReason::Watchpoint(WatchHit { pc, ..w }),
+            DebugHit::Device(event) => StopReason::Device(DeviceHit { pc, event }),
+        }
+    }
+}
+
+/// Result of `Cpu::run` and `Machine::run`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutcome {
+    /// Instructions that completed (a faulting one does not count).
+    pub instructions: u32,
+    pub stop: StopReason,
+}
+
+impl RunOutcome {
+    pub fn new(instructions: u32, stop: StopReason) -> Self {
+        Self { instructions, stop }
+    }
+
+    pub fn fault(&self) -> Option<&Fault> {
+        match &self.stop {
+            StopReason::Fault(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    /// `Err` for a fault, so callers that only care about faults can use `?`.
+    pub fn into_result(self) -> Result<Self, Fault> {
+        match self.stop {
+            StopReason::Fault(f) => Err(f),
+            _ => Ok(self),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct Cpu {
     pub pc: u32, // bytes
     pub r: [u32; 16],
@@ -33,26 +119,111 @@ pub struct Cpu {
     pub c: bool,
     pub v: bool,
     pub progress: u32,
+
+    // interrupt extension (STI/CLI/RTI)
+    pub int_enabled: bool,
+    pub int_mode: bool,
+    pub spc: u32,
+    pub sflags: u32,
+
+    /// Checked by `run` before every instruction but the first.
+    pub breakpoints: Breakpoints,
+    /// Journal for `step_back`; instructions are recorded while set.
+    pub history: Option<Box<History>>,
+    /// Each gets a record of every instruction that completes.
+    pub tracers: Vec<Arc<Mutex<dyn Tracer>>>,
+    // data access of the current instruction, while tracing
+    mem_access: Option<MemAccess>,
 }
 
 impl Cpu {
     pub fn reset(&mut self) {
         self.pc = ROM_START;
+        self.int_enabled = false;
+        self.int_mode = false;
     }
 
-    pub fn run<B: CpuBus>(&mut self, bus: &mut B, cycles: u32) -> BusResult<()> {
+    /// Runs at most `cycles` instructions; stops early when the guest
+    /// idles, faults or reaches a breakpoint. A breakpoint at the starting
+    /// PC is passed over, so a stopped run can be continued.
+    pub fn run<B: CpuBus>(&mut self, bus: &mut B, cycles: u32) -> RunOutcome {
         self.progress = 20;
-        for _ in 0..cycles {
+        bus.take_debug_hit();
+        for done in 0..cycles {
+            // ahead of the idle stop: the next run passes over its first PC
+            if done > 0 && !self.breakpoints.is_empty() && self.breakpoint_hit(bus) {
+                return RunOutcome::new(done, StopReason::Breakpoint(self.pc));
+            }
             if self.progress == 0 {
-                break;
+                return RunOutcome::new(done, StopReason::Idle);
             }
-            self.step(bus)?;
+            let pc = self.pc;
+            if let Err(f) = self.try_step(bus) {
+                return RunOutcome::new(done, StopReason::Fault(f));
+            }
+            if let Some(hit) = bus.take_debug_hit() {
+                return RunOutcome::new(done + 1, StopReason::debug_hit(hit, pc));
+            }
+        }
+        RunOutcome::new(cycles, StopReason::Budget)
+    }
+
+    /// Counts a visit to a breakpoint at PC and says whether to stop.
+    #[inline]
+    pub(crate) fn breakpoint_hit<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        if !self.breakpoints.contains(self.pc) {
+            return false;
+        }
+        let view = self.view();
+        self.breakpoints.hit(&view, &mut |a| bus.peek_word(a))
+    }
+
+    /// True while instructions are journaled or traced; they must all go
+    /// through `try_step` then.
+    #[inline]
+    pub(crate) fn recording(&self) -> bool {
+        self.history.is_some() || !self.tracers.is_empty()
+    }
+
+    /// NZCV packed in the top nibble (same layout as `MOV a, flags`).
+    #[inline]
+    fn flags_word(&self) -> u32 {
+        ((self.n as u32) << 31)
+            | ((self.z as u32) << 30)
+            | ((self.c as u32) << 29)
+            | ((self.v as u32) << 28)
+    }
+
+    #[inline]
+    fn set_flags_word(&mut self, w: u32) {
+        self.n = (w & 0x8000_0000) != 0;
+        self.z = (w & 0x4000_0000) != 0;
+        self.c = (w & 0x2000_0000) != 0;
+        self.v = (w & 0x1000_0000) != 0;
+    }
+
+    /// Latch PC/flags and vector into the interrupt handler.
+    fn interrupt(&mut self) {
+        self.spc = self.pc;
+        self.sflags = self.flags_word();
+        self.int_mode = true;
+        self.pc = INT_VECTOR;
+    }
+
+    /// Register-branch forms with IR[5] (STI/CLI, enable := IR[0]) or IR[4] (RTI).
+    fn interrupt_control(&mut self, ir: u32) -> BusResult<()> {
+        if (ir & 0x20) != 0 {
+            self.int_enabled = (ir & 1) != 0;
+        } else {
+            self.pc = self.spc;
+            self.set_flags_word(self.sflags);
+            self.int_mode = false;
         }
         Ok(())
     }
 
     #[inline]
-    fn set_reg(&mut self, reg: usize, value: u32) {
+    pub(crate) fn set_reg(&mut self, reg: usize, value: u32) {
         self.r[reg] = value;
         self.z = value == 0;
         self.n = (value as i32) < 0;
@@ -60,209 +231,281 @@ impl Cpu {
 
     // progress-aware memory helpers
     #[inline]
-    fn load_word<B: CpuBus>(&mut self, bus: &mut B, addr: u32) -> BusResult<u32> {
-        bus.read_word_for_cpu(addr & !3, &mut self.progress)
+    pub(crate) fn load_word<B: CpuBus>(&mut self, bus: &mut B, addr: u32) -> BusResult<u32> {
+        let v = bus.read_word_for_cpu(addr & !3, &mut self.progress)?;
+        self.trace_access(addr & !3, v, false, false);
+        Ok(v)
     }
 
     #[inline]
-    fn load_byte<B: CpuBus>(&mut self, bus: &mut B, addr: u32) -> BusResult<u8> {
-        let w = self.load_word(bus, addr & !3)?;
-        let shift = (addr & 3) * 8;
-        Ok(((w >> shift) & 0xFF) as u8)
+    pub(crate) fn load_byte<B: CpuBus>(&mut self, bus: &mut B, addr: u32) -> BusResult<u8> {
+        let v = bus.read_byte_for_cpu(addr, &mut self.progress)?;
+        self.trace_access(addr, v as u32, false, true);
+        Ok(v)
     }
 
     #[inline]
-    fn store_word<B: CpuBus>(&mut self, bus: &mut B, addr: u32, value: u32) -> BusResult<()> {
-        bus.write_word(addr & !3, value)
+    fn trace_access(&mut self, addr: u32, value: u32, store: bool, byte: bool) {
+        if !self.tracers.is_empty() {
+            self.mem_access = Some(MemAccess { addr, value, store, byte });
+        }
     }
 
     #[inline]
-    fn store_byte<B: CpuBus>(&mut self, bus: &mut B, addr: u32, value: u8) -> BusResult<()> {
-        let base = addr & !3;
-        let mut w = self.load_word(bus, base)?;
-        let shift = (addr & 3) * 8;
-        w &= !(0xFFu32 << shift);
-        w |= (value as u32) << shift;
-        self.store_word(bus, base, w)
+    pub(crate) fn store_word<B: CpuBus>(&mut self, bus: &mut B, addr: u32, value: u32) -> BusResult<()> {
+        self.trace_access(addr & !3, value, true, false);
+        let Some(history) = self.history.as_deref_mut() else {
+            return bus.write_word(addr & !3, value);
+        };
+        let old = bus.peek_word(addr & !3);
+        bus.write_word(addr & !3, value)?;
+        if let Some(old) = old {
+            history.store(addr & !3, 4, old);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn store_byte<B: CpuBus>(&mut self, bus: &mut B, addr: u32, value: u8) -> BusResult<()> {
+        self.trace_access(addr, value as u32, true, true);
+        let Some(history) = self.history.as_deref_mut() else {
+            return bus.write_byte_for_cpu(addr, value, &mut self.progress);
+        };
+        let old = bus.peek_word(addr & !3);
+        bus.write_byte_for_cpu(addr, value, &mut self.progress)?;
+        if let Some(old) = old {
+            history.store(addr, 1, old);
+        }
+        Ok(())
     }
 
     pub fn step<B: CpuBus>(&mut self, bus: &mut B) -> BusResult<()> {
-        let ir = self.load_word(bus, self.pc)?;
+        self.try_step(bus).map_err(|f| f.error)
+    }
+
+    /// Like `step`, but says which instruction failed and where.
+    pub fn try_step<B: CpuBus>(&mut self, bus: &mut B) -> Result<(), Fault> {
+        if !self.recording() {
+            return self.step_unrecorded(bus).map(|_| ());
+        }
+        let state = self.snapshot();
+        let before = self.r;
+        self.mem_access = None;
+        let res = self.step_unrecorded(bus);
+        if let Some(history) = self.history.as_deref_mut() {
+            let clock = !matches!(res, Err(Fault { ir: None, .. }));
+            history.push(state, &before, &self.r, clock);
+        }
+        let (pc, d, linked) = res?;
+        if !self.tracers.is_empty() {
+            self.trace(pc, &d, linked);
+        }
+        Ok(())
+    }
+
+    /// `linked`: a `BL` whose condition held (it has changed the flags since).
+    fn trace(&mut self, pc: u32, d: &Decoded, linked: bool) {
+        let dest = match d.op {
+            Op::Stw | Op::Stb | Op::Br | Op::IntCtl => None,
+            Op::BrLink => linked.then_some((15, self.r[15])),
+            _ => Some((d.a, self.r[d.a as usize])),
+        };
+        let flags = (self.flags_word() >> 28) as u8;
+        let rec = TraceRecord { pc, ir: d.ir, dest, flags, mem: self.mem_access.take() };
+        for t in &self.tracers {
+            t.lock().unwrap().record(&rec);
+        }
+    }
+
+    /// Runs one instruction; says which one, and whether it was a taken `BL`.
+    fn step_unrecorded<B: CpuBus>(&mut self, bus: &mut B) -> Result<(u32, Decoded, bool), Fault> {
+        if self.int_enabled && !self.int_mode && bus.irq_pending() {
+            self.interrupt();
+        }
+
+        let pc = self.pc;
+        let d = bus
+            .fetch_decoded(pc, &mut self.progress)
+            .map_err(|error| Fault { pc, ir: None, addr: pc, error })?;
         self.pc = self.pc.wrapping_add(4);
+        bus.advance_clock(1);
+        let linked = d.op == Op::BrLink && self.condition(d.a);
+        self.execute(bus, &d).map_err(|error| self.fault(pc, &d, error))?;
+        Ok((pc, d, linked))
+    }
 
-        let pbit = 0x8000_0000;
-        let qbit = 0x4000_0000;
-        let ubit = 0x2000_0000;
-        let vbit = 0x1000_0000;
-
-        if (ir & pbit) == 0 {
-            // Register instructions
-            let a = ((ir >> 24) & 0xF) as usize;
-            let b = ((ir >> 20) & 0xF) as usize;
-            let op = (ir >> 16) & 0xF;
-            let im = (ir & 0xFFFF) as u32;
-            let c = (ir & 0xF) as usize;
-
-            let b_val = self.r[b];
-
-            let c_val = if (ir & qbit) == 0 {
-                self.r[c]
-            } else if (ir & vbit) == 0 {
-                im
-            } else {
-                0xFFFF_0000 | im
-            };
-
-            let mut a_val: u32;
-
-            match op {
-                MOV => {
-                    if (ir & ubit) == 0 {
-                        a_val = c_val;
-                    } else if (ir & qbit) != 0 {
-                        a_val = c_val << 16;
-                    } else if (ir & vbit) != 0 {
-                        a_val = 0xD0
-                            | ((self.n as u32) << 31)
-                            | ((self.z as u32) << 30)
-                            | ((self.c as u32) << 29)
-                            | ((self.v as u32) << 28);
-                    } else {
-                        a_val = self.h;
-                    }
-                }
+    /// Takes back the newest instruction in `history`: registers, flags,
+    /// RAM and the virtual timer are as they were before it ran. `None`
+    /// when nothing is recorded.
+    pub fn step_back<B: CpuBus>(&mut self, bus: &mut B) -> Option<Undone> {
+        let entry = self.history.as_deref_mut()?.pop()?;
+        let mut stores = Vec::with_capacity(entry.stores.len());
+        for (addr, size, old) in entry.stores {
+            let a = addr & !3;
+            let new = bus.peek_word(a).unwrap_or(old);
+            // RAM was writable when the store went through
+            let _ = bus.restore_word(a, old);
+            let shift = (addr & 3) * 8;
+            stores.push(match size {
+                1 => Store { addr, size, old: (old >> shift) & 0xFF, new: (new >> shift) & 0xFF },
+                _ => Store { addr, size, old, new },
+            });
+        }
+        for (i, v) in entry.regs {
+            self.r[i as usize] = v;
+        }
+        self.restore(&entry.state);
+        if entry.clock {
+            bus.rewind_clock(1);
+        }
+        Some(Undone { pc: self.pc, stores })
+    }
 
-                LSL => a_val = b_val << (c_val & 31),
-                ASR => a_val = ((b_val as i32) >> (c_val & 31)) as u32,
-                ROR => {
-                    let sh = (c_val & 31) as u32;
-                    a_val = (b_val >> sh) | (b_val << ((32 - sh) & 31));
-                }
+    fn snapshot(&self) -> Snapshot {
+        let flags = [self.n, self.z, self.c, self.v, self.int_enabled, self.int_mode]
+            .iter()
+            .enumerate()
+            .fold(0, |f, (i, &b)| f | (b as u8) << i);
+        Snapshot { pc: self.pc, h: self.h, spc: self.spc, sflags: self.sflags, flags }
+    }
 
-                AND => a_val = b_val & c_val,
-                ANN => a_val = b_val & !c_val,
-                IOR => a_val = b_val | c_val,
-                XOR => a_val = b_val ^ c_val,
+    fn restore(&mut self, s: &Snapshot) {
+        self.pc = s.pc;
+        self.h = s.h;
+        self.spc = s.spc;
+        self.sflags = s.sflags;
+        [self.n, self.z, self.c, self.v, self.int_enabled, self.int_mode] =
+            std::array::from_fn(|i| s.flags & (1 << i) != 0);
+    }
 
-                ADD => {
-                    a_val = b_val.wrapping_add(c_val);
-                    if (ir & ubit) != 0 {
-                        a_val = a_val.wrapping_add(self.c as u32);
-                    }
-                    self.c = a_val < b_val;
-                    self.v = (((a_val ^ c_val) & (a_val ^ b_val)) >> 31) != 0;
-                }
+    /// Fault record for `d` at `pc`. Failing instructions leave their
+    /// registers alone, so the data address can be worked out again.
+    pub(crate) fn fault(&self, pc: u32, d: &Decoded, error: BusError) -> Fault {
+        let addr = match d.op {
+            Op::Ldw | Op::Ldb | Op::Stw | Op::Stb => self.r[d.b as usize].wrapping_add(d.imm),
+            _ => pc,
+        };
+        Fault { pc, ir: Some(d.ir), addr, error }
+    }
 
-                SUB => {
-                    a_val = b_val.wrapping_sub(c_val);
-                    if (ir & ubit) != 0 {
-                        a_val = a_val.wrapping_sub(self.c as u32);
-                    }
-                    self.c = a_val > b_val;
-                    self.v = (((b_val ^ c_val) & (a_val ^ b_val)) >> 31) != 0;
+    /// Operand `c`: register or extended immediate.
+    #[inline]
+    fn operand(&self, d: &Decoded) -> u32 {
+        if d.c == IMM { d.imm } else { self.r[(d.c & 0xF) as usize] }
+    }
+
+    #[inline]
+    pub(crate) fn execute<B: CpuBus>(&mut self, bus: &mut B, d: &Decoded) -> BusResult<()> {
+        let ir = d.ir;
+        let a = d.a as usize;
+        let b_val = self.r[d.b as usize];
+        let c_val = self.operand(d);
+
+        let a_val = match d.op {
+            Op::Mov => c_val,
+            Op::MovH => self.h,
+            Op::MovFlags => 0xD0 | self.flags_word(),
+
+            Op::Lsl => b_val << (c_val & 31),
+            Op::Asr => ((b_val as i32) >> (c_val & 31)) as u32,
+            Op::Ror => b_val.rotate_right(c_val & 31),
+
+            Op::And => b_val & c_val,
+            Op::Ann => b_val & !c_val,
+            Op::Ior => b_val | c_val,
+            Op::Xor => b_val ^ c_val,
+
+            Op::Add | Op::Addc => {
+                let mut a_val = b_val.wrapping_add(c_val);
+                if d.op == Op::Addc {
+                    a_val = a_val.wrapping_add(self.c as u32);
                 }
+                self.c = a_val < b_val;
+                self.v = (((a_val ^ c_val) & (a_val ^ b_val)) >> 31) != 0;
+                a_val
+            }
 
-                MUL => {
-                    if (ir & ubit) == 0 {
-                        let tmp = (b_val as i32 as i64) * (c_val as i32 as i64);
-                        a_val = tmp as u32;
-                        self.h = ((tmp as u64) >> 32) as u32;
-                    } else {
-                        let tmp = (b_val as u64) * (c_val as u64);
-                        a_val = tmp as u32;
-                        self.h = (tmp >> 32) as u32;
-                    }
+            Op::Sub | Op::Subb => {
+                let mut a_val = b_val.wrapping_sub(c_val);
+                if d.op == Op::Subb {
+                    a_val = a_val.wrapping_sub(self.c as u32);
                 }
+                self.c = a_val > b_val;
+                self.v = (((b_val ^ c_val) & (a_val ^ b_val)) >> 31) != 0;
+                a_val
+            }
+
+            Op::Mul => {
+                let tmp = (b_val as i32 as i64) * (c_val as i32 as i64);
+                self.h = ((tmp as u64) >> 32) as u32;
+                tmp as u32
+            }
+            Op::Mulu => {
+                let tmp = (b_val as u64) * (c_val as u64);
+                self.h = (tmp >> 32) as u32;
+                tmp as u32
+            }
 
-                DIV => {
-                    if (c_val as i32) > 0 {
-                        if (ir & ubit) == 0 {
-                            let (q, r) = div_signed_c_positive(b_val as i32, c_val as i32);
-                            a_val = q as u32;
-                            self.h = r as u32;
-                        } else {
-                            if c_val == 0 {
-                                a_val = 0;
-                                self.h = b_val;
-                            } else {
-                                a_val = b_val / c_val;
-                                self.h = b_val % c_val;
-                            }
-                        }
+            Op::Div | Op::Divu => {
+                let unsigned = d.op == Op::Divu;
+                if (c_val as i32) > 0 {
+                    if !unsigned {
+                        let (q, r) = div_signed_c_positive(b_val as i32, c_val as i32);
+                        self.h = r as u32;
+                        q as u32
                     } else {
-                        let d = fp::idiv(b_val, c_val, (ir & ubit) != 0);
-                        a_val = d.quot;
-                        self.h = d.rem;
+                        self.h = b_val % c_val;
+                        b_val / c_val
                     }
+                } else {
+                    let q = fp::idiv(b_val, c_val, unsigned);
+                    self.h = q.rem;
+                    q.quot
                 }
-
-                FAD => a_val = fp::fp_add(b_val, c_val, (ir & ubit) != 0, (ir & vbit) != 0),
-                FSB => a_val = fp::fp_add(b_val, c_val ^ 0x8000_0000, (ir & ubit) != 0, (ir & vbit) != 0),
-                FML => a_val = fp::fp_mul(b_val, c_val),
-                FDV => a_val = fp::fp_div(b_val, c_val),
-
-                _ => unreachable!("invalid op"),
             }
 
-            self.set_reg(a, a_val);
-            Ok(())
-        } else if (ir & qbit) == 0 {
-            // Memory instructions
-            let a = ((ir >> 24) & 0xF) as usize;
-            let b = ((ir >> 20) & 0xF) as usize;
+            Op::Fad => fp::fp_add(b_val, c_val, (ir & UBIT) != 0, (ir & VBIT) != 0),
+            Op::Fsb => fp::fp_add(b_val, c_val ^ 0x8000_0000, (ir & UBIT) != 0, (ir & VBIT) != 0),
+            Op::Fml => fp::fp_mul(b_val, c_val),
+            Op::Fdv => fp::fp_div(b_val, c_val),
 
-            let mut off = (ir & 0x000F_FFFF) as i32;
-            off = (off ^ 0x0008_0000) - 0x0008_0000;
+            Op::Ldw => self.load_word(bus, b_val.wrapping_add(d.imm))?,
+            Op::Ldb => self.load_byte(bus, b_val.wrapping_add(d.imm))? as u32,
+            Op::Stw => return self.store_word(bus, b_val.wrapping_add(d.imm), self.r[a]),
+            Op::Stb => return self.store_byte(bus, b_val.wrapping_add(d.imm), self.r[a] as u8),
 
-            let addr = self.r[b].wrapping_add(off as u32);
-
-            if (ir & ubit) == 0 {
-                let a_val = if (ir & vbit) == 0 {
-                    self.load_word(bus, addr)?
-                } else {
-                    self.load_byte(bus, addr)? as u32
-                };
-                self.set_reg(a, a_val);
-            } else {
-                if (ir & vbit) == 0 {
-                    self.store_word(bus, addr, self.r[a])?;
-                } else {
-                    self.store_byte(bus, addr, self.r[a] as u8)?;
+            Op::Br | Op::BrLink => {
+                if self.condition(d.a) {
+                    if d.op == Op::BrLink {
+                        self.set_reg(15, self.pc);
+                    }
+                    self.pc = if d.c == IMM { self.pc.wrapping_add(d.imm) } else { self.r[d.c as usize] };
                 }
+                return Ok(());
             }
-            Ok(())
-        } else {
-            // Branch instructions
-            let mut t = ((ir >> 27) & 1) != 0;
-            match (ir >> 24) & 7 {
-                0 => t ^= self.n,
-                1 => t ^= self.z,
-                2 => t ^= self.c,
-                3 => t ^= self.v,
-                4 => t ^= self.c || self.z,
-                5 => t ^= self.n ^ self.v,
-                6 => t ^= (self.n ^ self.v) || self.z,
-                7 => t ^= true,
-                _ => unreachable!(),
-            }
+            Op::IntCtl => return self.interrupt_control(ir),
 
-            if t {
-                if (ir & vbit) != 0 {
-                    self.set_reg(15, self.pc);
-                }
+            Op::Empty => unreachable!("empty decode slot"),
+        };
 
-                if (ir & ubit) == 0 {
-                    let c = (ir & 0xF) as usize;
-                    self.pc = self.r[c];
-                } else {
-                    let mut off = (ir & 0x00FF_FFFF) as i32;
-                    off = (off ^ 0x0080_0000) - 0x0080_0000;
-                    let delta_bytes = (off as i64) * 4;
-                    self.pc = self.pc.wrapping_add(delta_bytes as u32);
-                }
-            }
-            Ok(())
-        }
+        self.set_reg(a, a_val);
+        Ok(())
+    }
+
+    /// Branch condition IR[27:24]; bit 3 inverts.
+    #[inline]
+    pub(crate) fn condition(&self, cond: u8) -> bool {
+        let t = match cond & 7 {
+            0 => self.n,
+            1 => self.z,
+            2 => self.c,
+            3 => self.v,
+            4 => self.c || self.z,
+            5 => self.n ^ self.v,
+            6 => (self.n ^ self.v) || self.z,
+            _ => true,
+        };
+        t ^ (cond & 8 != 0)
     }
 }
 
@@ -289,6 +532,8 @@ pub struct CpuView {
     pub n: bool,
     pub c: bool,
     pub v: bool,
+    pub int_enabled: bool,
+    pub int_mode: bool,
 }
 
 impl Cpu {
@@ -301,6 +546,8 @@ impl Cpu {
             n: self.n,
             c: self.c,
             v: self.v,
+            int_enabled: self.int_enabled,
+            int_mode: self.int_mode,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,217 @@
+// src/debug/breakpoints.rs
+//
+// Breakpoints live with the CPU so that `Cpu::run`, the translator, the GUI
+// and the headless runner all stop on the same list. `hit` is only called
+// for addresses in the map, so an empty list costs a single check per
+// instruction.
+//
+// A breakpoint may carry a condition (`expr.rs`), checked after the hit is
+// counted, and a log message; a breakpoint with a message is a logpoint,
+// which records the message instead of stopping.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::str::FromStr;
+
+use super::expr::{Env, Expr, LogMessage};
+use crate::cpu::CpuView;
+
+/// Logpoint lines kept until someone takes them.
+const MAX_LOG: usize = 10_000;
+
+/// A stop before the instruction at `addr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u32,
+    pub enabled: bool,
+    /// Times execution reached the address while enabled.
+    pub hits: u64,
+    /// Hits to pass over before stopping again.
+    pub ignore: u32,
+    /// Removed when it stops the CPU.
+    pub temporary: bool,
+    /// Stops (or logs) only where this is non-zero.
+    pub condition: Option<Expr>,
+    /// Makes it a logpoint.
+    pub log: Option<LogMessage>,
+}
+
+impl Breakpoint {
+    fn new(addr: u32, temporary: bool) -> Self {
+        Self { addr, enabled: true, hits: 0, ignore: 0, temporary, condition: None, log: None }
+    }
+}
+
+/// Breakpoints by address.
+#[derive(Debug, Default, Clone)]
+pub struct Breakpoints {
+    map: BTreeMap<u32, Breakpoint>,
+    generation: u64,
+    log: VecDeque<String>,
+}
+
+impl Breakpoints {
+    /// Adds a breakpoint, or returns the one already at `addr`.
+    pub fn insert(&mut self, addr: u32) -> &mut Breakpoint {
+        self.add(addr, false)
+    }
+
+    /// Like `insert`, but the breakpoint goes away once it stops the CPU
+    /// (run to cursor). An existing breakpoint is left as it is.
+    pub fn insert_temporary(&mut self, addr: u32) -> &mut Breakpoint {
+        self.add(addr, true)
+    }
+
+    fn add(&mut self, addr: u32, temporary: bool) -> &mut Breakpoint {
+        if !self.map.contains_key(&addr) {
+            self.generation += 1;
+        }
+        self.map.entry(addr).or_insert_with(|| Breakpoint::new(addr, temporary))
+    }
+
+    pub fn remove(&mut self, addr: u32) -> Option<Breakpoint> {
+        self.map.remove(&addr)
+    }
+
+    /// Adds a breakpoint at `addr` or removes the one that is there.
+    pub fn toggle(&mut self, addr: u32) {
+        if self.remove(addr).is_none() {
+            self.insert(addr);
+        }
+    }
+
+    pub fn set_enabled(&mut self, addr: u32, enabled: bool) -> bool {
+        self.map.get_mut(&addr).map(|bp| bp.enabled = enabled).is_some()
+    }
+
+    pub fn get(&self, addr: u32) -> Option<&Breakpoint> {
+        self.map.get(&addr)
+    }
+
+    pub fn get_mut(&mut self, addr: u32) -> Option<&mut Breakpoint> {
+        self.map.get_mut(&addr)
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        self.map.contains_key(&addr)
+    }
+
+    /// In address order.
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.map.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Changes whenever an address is added, so translated code that ran
+    /// past it can be dropped.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Execution reached `cpu.pc`: counts the hit and says whether to
+    /// stop. `peek` reads memory for the condition; a condition that can't
+    /// be evaluated stops.
+    pub fn hit(&mut self, cpu: &CpuView, peek: &mut dyn FnMut(u32) -> Option<u32>) -> bool {
+        let pc = cpu.pc;
+        let Some(bp) = self.map.get_mut(&pc) else {
+            return false;
+        };
+        if !bp.enabled {
+            return false;
+        }
+        bp.hits += 1;
+        let mut env = Env { cpu, hits: bp.hits, peek };
+        if bp.condition.as_ref().is_some_and(|c| c.eval(&mut env) == Ok(0)) {
+            return false;
+        }
+        if bp.ignore > 0 {
+            bp.ignore -= 1;
+            return false;
+        }
+        if let Some(msg) = &bp.log {
+            if self.log.len() == MAX_LOG {
+                self.log.pop_front();
+            }
+            self.log.push_back(format!("{pc:08X} {}", msg.format(&mut env)));
+            return false;
+        }
+        if bp.temporary {
+            self.map.remove(&pc);
+        }
+        true
+    }
+
+    /// Whether a breakpoint at `cpu.pc` would stop there, without counting
+    /// a hit or using up ignores; logpoints never do. Used going backwards.
+    pub fn stops_at(&self, cpu: &CpuView, peek: &mut dyn FnMut(u32) -> Option<u32>) -> bool {
+        let Some(bp) = self.map.get(&cpu.pc) else {
+            return false;
+        };
+        if !bp.enabled || bp.log.is_some() {
+            return false;
+        }
+        let mut env = Env { cpu, hits: bp.hits, peek };
+        bp.condition.as_ref().is_none_or(|c| c.eval(&mut env) != Ok(0))
+    }
+
+    /// Logpoint output since the last call, oldest first.
+    pub fn take_log(&mut self) -> Vec<String> {
+        self.log.drain(..).collect()
+    }
+}
+
+/// Command line form of a breakpoint: `ADDR[:IGNORE] [if COND] [log MSG]`,
+/// address in hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakSpec {
+    pub addr: u32,
+    pub ignore: u32,
+    pub condition: Option<Expr>,
+    pub log: Option<LogMessage>,
+}
+
+impl BreakSpec {
+    /// Gives `bp` this spec's ignore count, condition and message.
+    pub fn apply(&self, bp: &mut Breakpoint) {
+        bp.ignore = self.ignore;
+        bp.condition = self.condition.clone();
+        bp.log = self.log.clone();
+    }
+}
+
+impl FromStr for BreakSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (s, log) = match s.split_once(" log ") {
+            Some((s, msg)) => (s, Some(msg.trim().parse()?)),
+            None => (s, None),
+        };
+        let (s, condition) = match s.split_once(" if ") {
+            Some((s, cond)) => (s, Some(cond.parse()?)),
+            None => (s, None),
+        };
+        let s = s.trim();
+        let (addr, ignore) = match s.split_once(':') {
+            Some((a, n)) => (a, n.parse().map_err(|e| format!("invalid ignore count \"{n}\": {e}"))?),
+            None => (s, 0),
+        };
+        let hex = addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")).unwrap_or(addr);
+        let addr = u32::from_str_radix(hex, 16).map_err(|e| format!("invalid address \"{addr}\": {e}"))?;
+        if addr & 3 != 0 {
+            return Err(format!("address {addr:08X} is not word aligned"));
+        }
+        Ok(Self { addr, ignore, condition, log })
+    }
+}
@@ -0,0 +1,269 @@
+// src/debug/calls.rs
+//
+// Calls and returns. `CallStack` follows the trace records of `Cpu::tracers`
+// and keeps a shadow call stack the way Oberon code calls: a taken `BL`
+// enters its target with PC + 4 as return address, and a taken `B R15`
+// leaves every frame up to the one that returns there. An interrupt (a jump
+// to address 4 that no branch made) enters a frame that `RTI` leaves.
+// Returns to an address no frame returns to (the stack as it was before
+// tracing began) are ignored.
+//
+// `CallTracer` turns the calls into Chrome trace-event JSON ("B" and "E"
+// events, one per call and return) for chrome://tracing and Perfetto.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::decode::{Decoded, Op, IMM};
+use crate::devices::timer::DEFAULT_INSTRUCTIONS_PER_MS;
+
+use super::symbols::Symbols;
+use super::trace::{TraceRecord, Tracer};
+
+/// Deeper calls are not tracked; their instructions count in the deepest
+/// frame kept.
+pub const MAX_DEPTH: usize = 1024;
+
+pub const INTERRUPT_VECTOR: u32 = 4;
+
+/// A change of the shadow call stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallEvent {
+    /// A procedure was entered at this address.
+    Call(u32),
+    /// An interrupt handler was entered.
+    Interrupt,
+    /// The innermost frame was left.
+    Return,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: u32,
+    ret: Option<u32>,
+    interrupt: bool,
+}
+
+/// What the previous instruction does to the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pending {
+    None,
+    Call(u32),
+    Return,
+    Rti,
+}
+
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    pending: Pending,
+    /// Where the next instruction should be; `None` after a register jump.
+    expected: Option<u32>,
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self { frames: Vec::new(), pending: Pending::None, expected: None }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Entry addresses, outermost first (interrupt frames show the vector).
+    pub fn entries(&self) -> impl Iterator<Item = u32> + '_ {
+        self.frames.iter().map(|f| f.entry)
+    }
+
+    /// Applies what the instruction before `rec` did to the stack, now that
+    /// `rec` says where it went, and reports the changes. Call it for every
+    /// record before looking at `depth` or `entries`.
+    pub fn update(&mut self, rec: &TraceRecord, event: &mut dyn FnMut(CallEvent)) {
+        let pc = rec.pc;
+        let interrupted = pc == INTERRUPT_VECTOR && self.expected.is_some_and(|e| e != pc);
+        match self.pending {
+            Pending::None => {}
+            Pending::Call(ret) => {
+                let entry = if interrupted { self.expected.unwrap_or(pc) } else { pc };
+                self.push(Frame { entry, ret: Some(ret), interrupt: false }, event);
+            }
+            Pending::Return if !interrupted => self.pop_to(|f| f.ret == Some(pc), event),
+            Pending::Return => {}
+            Pending::Rti => self.pop_to(|f| f.interrupt, event),
+        }
+        if interrupted {
+            self.push(Frame { entry: INTERRUPT_VECTOR, ret: None, interrupt: true }, event);
+        }
+
+        let d = Decoded::new(rec.ir);
+        let next = pc.wrapping_add(4);
+        self.pending = Pending::None;
+        self.expected = Some(next);
+        match d.op {
+            Op::Br | Op::BrLink if branch_taken(&d, rec) => {
+                self.expected = (d.c == IMM).then(|| next.wrapping_add(d.imm));
+                if d.op == Op::BrLink {
+                    self.pending = Pending::Call(next);
+                } else if d.c == 15 {
+                    self.pending = Pending::Return;
+                }
+            }
+            Op::IntCtl if rec.ir & 0x20 == 0 => {
+                self.pending = Pending::Rti;
+                self.expected = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, frame: Frame, event: &mut dyn FnMut(CallEvent)) {
+        if self.frames.len() >= MAX_DEPTH {
+            return;
+        }
+        self.frames.push(frame);
+        event(if frame.interrupt { CallEvent::Interrupt } else { CallEvent::Call(frame.entry) });
+    }
+
+    /// Leaves the innermost frame matching `is_target` and all inside it.
+    fn pop_to(&mut self, is_target: impl Fn(&Frame) -> bool, event: &mut dyn FnMut(CallEvent)) {
+        if let Some(i) = self.frames.iter().rposition(is_target) {
+            for _ in i..self.frames.len() {
+                event(CallEvent::Return);
+            }
+            self.frames.truncate(i);
+        }
+    }
+}
+
+/// Whether the branch of a trace record was taken. `BL` sets the flags from
+/// R15, so its record tells by `dest`; `B` leaves them as they were.
+pub fn branch_taken(d: &Decoded, rec: &TraceRecord) -> bool {
+    if d.op == Op::BrLink {
+        return rec.dest.is_some();
+    }
+    let f = rec.flags;
+    let (n, z, c, v) = (f & 8 != 0, f & 4 != 0, f & 2 != 0, f & 1 != 0);
+    let t = match d.a & 7 {
+        0 => n,
+        1 => z,
+        2 => c,
+        3 => v,
+        4 => c || z,
+        5 => n ^ v,
+        6 => (n ^ v) || z,
+        _ => true,
+    };
+    t ^ (d.a & 8 != 0)
+}
+
+/// Time stamps of a `CallTracer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timebase {
+    /// One microsecond per instruction, so the viewer shows instruction
+    /// counts.
+    Instructions,
+    /// The time the virtual timer would show.
+    Virtual { instructions_per_ms: u32 },
+}
+
+impl FromStr for Timebase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instructions" => Ok(Timebase::Instructions),
+            "virtual" => Ok(Timebase::Virtual { instructions_per_ms: DEFAULT_INSTRUCTIONS_PER_MS }),
+            _ => match s.strip_prefix("virtual:").map(str::parse) {
+                Some(Ok(n)) if n > 0 => Ok(Timebase::Virtual { instructions_per_ms: n }),
+                _ => Err(format!("invalid timebase \"{s}\" (instructions, virtual or virtual:N)")),
+            },
+        }
+    }
+}
+
+/// Collects calls and returns for a Chrome trace-event file. Events are kept
+/// in memory, so names can come from symbols read when the run is over.
+#[derive(Debug, Clone)]
+pub struct CallTracer {
+    stack: CallStack,
+    /// Instruction count of the next record.
+    clock: u64,
+    timebase: Timebase,
+    events: Vec<(u64, CallEvent)>,
+}
+
+impl CallTracer {
+    /// `clock` is the instruction count of the first record, so time stamps
+    /// match the machine's (`Timer::instructions`).
+    pub fn new(timebase: Timebase, clock: u64) -> Self {
+        Self { stack: CallStack::new(), clock, timebase, events: Vec::new() }
+    }
+
+    /// Calls and returns seen.
+    pub fn events(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Writes the events as a JSON trace-event array. Frames still open are
+    /// closed at the last time stamp.
+    pub fn write_json(&self, out: &mut dyn Write, symbols: &Symbols) -> io::Result<()> {
+        let ts = |clock: u64| match self.timebase {
+            Timebase::Instructions => clock.to_string(),
+            Timebase::Virtual { instructions_per_ms } => {
+                format!("{:.3}", clock as f64 * 1000.0 / instructions_per_ms as f64)
+            }
+        };
+        let mut open: Vec<String> = Vec::new();
+        let mut sep = "";
+        writeln!(out, "[")?;
+        for &(clock, ev) in &self.events {
+            let (ph, name) = match ev {
+                CallEvent::Call(entry) => ("B", json_string(&symbols.name(entry))),
+                CallEvent::Interrupt => ("B", json_string("interrupt")),
+                CallEvent::Return => match open.pop() {
+                    Some(name) => ("E", name),
+                    None => continue,
+                },
+            };
+            if ph == "B" {
+                open.push(name.clone());
+            }
+            write!(out, "{sep}{{\"name\":{name},\"ph\":\"{ph}\",\"ts\":{},\"pid\":1,\"tid\":1}}", ts(clock))?;
+            sep = ",\n";
+        }
+        while let Some(name) = open.pop() {
+            write!(out, "{sep}{{\"name\":{name},\"ph\":\"E\",\"ts\":{},\"pid\":1,\"tid\":1}}", ts(self.clock))?;
+            sep = ",\n";
+        }
+        writeln!(out, "\n]")
+    }
+}
+
+impl Tracer for CallTracer {
+    fn record(&mut self, rec: &TraceRecord) {
+        let Self { stack, clock, events, .. } = self;
+        stack.update(rec, &mut |ev| events.push((*clock, ev)));
+        self.clock += 1;
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,302 @@
+// src/debug/coverage.rs
+//
+// Code coverage of guest programs. `Coverage` is a `Tracer` with one bit per
+// RAM word for "executed" and, for conditional branches, one each for
+// "taken" and "not taken". Code outside RAM (the boot ROM) is not covered.
+//
+// Before saving, `scan` marks the conditional branches in memory, so branches
+// that never ran still count, and keeps the Oberon module list. Reports and
+// lcov output are per unit: the ranges asked for, else the modules, else the
+// span of executed RAM.
+//
+// Coverage files merge by address, so they must come from runs that load the
+// same modules at the same places. File format, little-endian:
+//
+//   "RISCCOV1" mem_size: u32, executed, taken, not_taken, branches
+//   (mem_size / 32 bytes each), module count: u32,
+//   { name: u32 length + bytes, code: u32, end: u32 }
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::bus::{BusError, BusResult};
+use crate::decode::{Decoded, Op};
+use crate::savestate::{Reader, Writer};
+
+use super::calls::branch_taken;
+use super::symbols::{ModuleInfo, Symbols};
+use super::trace::{AddrRange, TraceRecord, Tracer};
+
+const MAGIC: &[u8; 8] = b"RISCCOV1";
+
+fn err(msg: impl Into<String>) -> BusError {
+    BusError::Device(format!("coverage: {}", msg.into()))
+}
+
+/// One bit per RAM word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn new(words: usize) -> Self {
+        Bits(vec![0; words.div_ceil(64)])
+    }
+
+    fn get(&self, w: usize) -> bool {
+        self.0.get(w / 64).is_some_and(|b| b & (1 << (w % 64)) != 0)
+    }
+
+    fn set(&mut self, w: usize) {
+        if let Some(b) = self.0.get_mut(w / 64) {
+            *b |= 1 << (w % 64);
+        }
+    }
+
+    fn or(&mut self, other: &Bits) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a |= b;
+        }
+    }
+}
+
+/// Coverage of one unit (module or address range).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitReport {
+    pub name: String,
+    pub range: AddrRange,
+    /// Words in the range.
+    pub instructions: u32,
+    pub executed: u32,
+    /// Conditional branches in the range.
+    pub branches: u32,
+    /// Branch directions (taken, not taken) seen, out of `2 * branches`.
+    pub directions: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    mem_size: u32,
+    executed: Bits,
+    taken: Bits,
+    not_taken: Bits,
+    branches: Bits,
+    modules: Vec<ModuleInfo>,
+}
+
+/// A branch that can go both ways.
+fn is_conditional(d: &Decoded) -> bool {
+    matches!(d.op, Op::Br | Op::BrLink) && d.a & 7 != 7
+}
+
+impl Coverage {
+    pub fn new(mem_size: u32) -> Self {
+        let words = mem_size as usize / 4;
+        Self {
+            mem_size,
+            executed: Bits::new(words),
+            taken: Bits::new(words),
+            not_taken: Bits::new(words),
+            branches: Bits::new(words),
+            modules: Vec::new(),
+        }
+    }
+
+    pub fn mem_size(&self) -> u32 {
+        self.mem_size
+    }
+
+    pub fn modules(&self) -> &[ModuleInfo] {
+        &self.modules
+    }
+
+    fn word(&self, addr: u32) -> Option<usize> {
+        (addr < self.mem_size).then_some(addr as usize / 4)
+    }
+
+    pub fn executed(&self, addr: u32) -> bool {
+        self.word(addr).is_some_and(|w| self.executed.get(w))
+    }
+
+    /// Whether the branch at `addr` was seen taken and not taken.
+    pub fn directions(&self, addr: u32) -> (bool, bool) {
+        self.word(addr).map_or((false, false), |w| (self.taken.get(w), self.not_taken.get(w)))
+    }
+
+    /// Marks the conditional branches in RAM and takes the module list of
+    /// `symbols`.
+    pub fn scan(&mut self, peek: &mut dyn FnMut(u32) -> Option<u32>, symbols: &Symbols) {
+        for w in 0..self.mem_size as usize / 4 {
+            if peek(w as u32 * 4).is_some_and(|ir| is_conditional(&Decoded::new(ir))) {
+                self.branches.set(w);
+            }
+        }
+        self.modules = symbols.modules().iter().filter(|m| m.code < self.mem_size).cloned().collect();
+    }
+
+    /// Adds the coverage of another run of the same memory layout.
+    pub fn merge(&mut self, other: &Coverage) -> BusResult<()> {
+        if other.mem_size != self.mem_size {
+            return Err(err(format!("memory sizes differ ({:#X} and {:#X})", self.mem_size, other.mem_size)));
+        }
+        for m in &other.modules {
+            match self.modules.iter().find(|o| o.name == m.name) {
+                Some(o) if o != m => {
+                    return Err(err(format!("module {} is at {:08X} in one run and {:08X} in the other", m.name, o.code, m.code)))
+                }
+                Some(_) => {}
+                None => self.modules.push(m.clone()),
+            }
+        }
+        self.executed.or(&other.executed);
+        self.taken.or(&other.taken);
+        self.not_taken.or(&other.not_taken);
+        self.branches.or(&other.branches);
+        Ok(())
+    }
+
+    /// `ranges`, or else the modules, or else the span of executed RAM.
+    pub fn units(&self, ranges: &[(String, AddrRange)]) -> Vec<(String, AddrRange)> {
+        if !ranges.is_empty() {
+            return ranges.to_vec();
+        }
+        if !self.modules.is_empty() {
+            return self
+                .modules
+                .iter()
+                .filter(|m| m.end > m.code)
+                .map(|m| (m.name.clone(), AddrRange { first: m.code, last: m.end - 1 }))
+                .collect();
+        }
+        let words = self.mem_size as usize / 4;
+        let first = (0..words).find(|&w| self.executed.get(w));
+        let last = (0..words).rev().find(|&w| self.executed.get(w));
+        match (first, last) {
+            (Some(f), Some(l)) => vec![("ram".into(), AddrRange { first: f as u32 * 4, last: l as u32 * 4 + 3 })],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The RAM words inside `range`: address and word number.
+    fn words(&self, range: AddrRange) -> impl Iterator<Item = (u32, usize)> {
+        let first = range.first.div_ceil(4) as usize;
+        let end = ((range.last as u64 + 1).min(self.mem_size as u64) / 4) as usize;
+        (first..end.max(first)).map(|w| (w as u32 * 4, w))
+    }
+
+    pub fn report(&self, ranges: &[(String, AddrRange)]) -> Vec<UnitReport> {
+        self.units(ranges)
+            .into_iter()
+            .map(|(name, range)| {
+                let mut r = UnitReport { name, range, instructions: 0, executed: 0, branches: 0, directions: 0 };
+                for (_, w) in self.words(range) {
+                    r.instructions += 1;
+                    r.executed += self.executed.get(w) as u32;
+                    if self.branches.get(w) {
+                        r.branches += 1;
+                        r.directions += self.taken.get(w) as u32 + self.not_taken.get(w) as u32;
+                    }
+                }
+                r
+            })
+            .collect()
+    }
+
+    /// Writes lcov tracefile records, one per unit. Lines are word numbers
+    /// in the unit, from 1; counts are 0 or 1.
+    pub fn write_lcov(&self, out: &mut dyn Write, ranges: &[(String, AddrRange)]) -> io::Result<()> {
+        writeln!(out, "TN:")?;
+        for (name, range) in self.units(ranges) {
+            writeln!(out, "SF:{name}")?;
+            let (mut lines, mut hit, mut brf, mut brh) = (0, 0, 0, 0);
+            for (addr, w) in self.words(range) {
+                let line = (addr - range.first) / 4 + 1;
+                let ran = self.executed.get(w);
+                writeln!(out, "DA:{line},{}", ran as u32)?;
+                lines += 1;
+                hit += ran as u32;
+                if self.branches.get(w) {
+                    for (i, seen) in [self.taken.get(w), self.not_taken.get(w)].into_iter().enumerate() {
+                        let count = if ran { (seen as u32).to_string() } else { "-".into() };
+                        writeln!(out, "BRDA:{line},0,{i},{count}")?;
+                        brh += seen as u32;
+                    }
+                    brf += 2;
+                }
+            }
+            writeln!(out, "BRF:{brf}\nBRH:{brh}\nLF:{lines}\nLH:{hit}\nend_of_record")?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> BusResult<()> {
+        std::fs::write(path, self.to_bytes())
+            .map_err(|e| err(format!("can't write \"{}\": {e}", path.display())))
+    }
+
+    pub fn load(path: &Path) -> BusResult<Self> {
+        let data = std::fs::read(path).map_err(|e| err(format!("can't read \"{}\": {e}", path.display())))?;
+        Self::from_bytes(&data).map_err(|e| err(format!("{}: {e}", path.display())))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(MAGIC);
+        w.u32(self.mem_size);
+        for bits in [&self.executed, &self.taken, &self.not_taken, &self.branches] {
+            for b in &bits.0 {
+                w.u64(*b);
+            }
+        }
+        w.u32(self.modules.len() as u32);
+        for m in &self.modules {
+            w.blob(m.name.as_bytes());
+            w.u32(m.code);
+            w.u32(m.end);
+        }
+        w.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let cut = |_| "file is cut short".to_string();
+        let mut r = Reader::new(data, "coverage");
+        if r.array::<8>().map_err(cut)? != *MAGIC {
+            return Err("not a coverage file".into());
+        }
+        let mem_size = r.u32().map_err(cut)?;
+        if mem_size % 4 != 0 || (mem_size as usize / 4).div_ceil(64) * 8 * 4 > data.len() {
+            return Err(format!("bad memory size {mem_size:#X}"));
+        }
+        let mut cov = Coverage::new(mem_size);
+        for bits in [&mut cov.executed, &mut cov.taken, &mut cov.not_taken, &mut cov.branches] {
+            for b in &mut bits.0 {
+                *b = r.u64().map_err(cut)?;
+            }
+        }
+        for _ in 0..r.u32().map_err(cut)? {
+            let name = String::from_utf8(r.blob().map_err(cut)?.to_vec()).map_err(|_| "bad module name")?;
+            let code = r.u32().map_err(cut)?;
+            let end = r.u32().map_err(cut)?;
+            cov.modules.push(ModuleInfo { name, code, end });
+        }
+        if !r.is_empty() {
+            return Err("unexpected data at the end".into());
+        }
+        Ok(cov)
+    }
+}
+
+impl Tracer for Coverage {
+    fn record(&mut self, rec: &TraceRecord) {
+        let Some(w) = self.word(rec.pc) else { return };
+        self.executed.set(w);
+        let d = Decoded::new(rec.ir);
+        if is_conditional(&d) {
+            self.branches.set(w);
+            if branch_taken(&d, rec) {
+                self.taken.set(w);
+            } else {
+                self.not_taken.set(w);
+            }
+        }
+    }
+}
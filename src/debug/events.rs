@@ -0,0 +1,215 @@
+// src/debug/events.rs
+//
+// Breakpoints on device activity rather than addresses: IO register
+// accesses, SD card commands, keyboard bytes taken by the guest and
+// framebuffer stores. `IoBus` reports SD commands and key bytes as events;
+// `SystemBus` adds IO and framebuffer accesses and checks them all against
+// `DeviceBreaks`, the same way as watchpoints.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::watchpoints::{Access, WatchKind};
+
+/// Something a device saw, caused by the current instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// Load or store at IO offset `offset` (from `IO_START`).
+    Io { offset: u32, access: Access, value: u32 },
+    /// A complete command frame reached the SD card on SPI `spi`.
+    /// `sector` is the image sector for block reads and writes.
+    SdCommand { spi: u8, cmd: u8, app: bool, arg: u32, sector: Option<u32> },
+    /// The guest read a byte from the keyboard buffer.
+    Key(u8),
+    /// Store to the framebuffer word covering pixels `x..x + 32` of line `y`.
+    Framebuffer { x: u32, y: u32 },
+}
+
+impl fmt::Display for DeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DeviceEvent::Io { offset, access: Access::Read, value } => write!(f, "IO {offset} read {value:08X}"),
+            DeviceEvent::Io { offset, access: Access::Write, value } => write!(f, "IO {offset} write {value:08X}"),
+            DeviceEvent::SdCommand { spi, cmd, app, arg, sector } => {
+                write!(f, "SPI{spi} {}CMD{cmd} arg {arg:08X}", if app { "A" } else { "" })?;
+                match sector {
+                    Some(s) => write!(f, " (sector {s})"),
+                    None => Ok(()),
+                }
+            }
+            DeviceEvent::Key(b) => write!(f, "key byte {b:02X}"),
+            DeviceEvent::Framebuffer { x, y } => write!(f, "framebuffer store at ({x}, {y})"),
+        }
+    }
+}
+
+/// A device event that matched a `DeviceBreak`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceHit {
+    /// The instruction that caused it.
+    pub pc: u32,
+    pub event: DeviceEvent,
+}
+
+impl fmt::Display for DeviceHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PC {:08X} {}", self.pc, self.event)
+    }
+}
+
+/// Which events stop the CPU.
+///
+/// Written as `io:OFFSET[:KIND]`, `sd[:CMD|*[:SECTOR]]`, `key[:BYTE]` or
+/// `fb:X,Y,W,H` (pixels); CMD, OFFSET and SECTOR are decimal, BYTE is hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceBreak {
+    Io { offset: u32, kind: WatchKind },
+    Sd { cmd: Option<u8>, sector: Option<u32> },
+    Key(Option<u8>),
+    Framebuffer { x: u32, y: u32, w: u32, h: u32 },
+}
+
+impl DeviceBreak {
+    pub fn matches(&self, event: &DeviceEvent) -> bool {
+        match (*self, *event) {
+            (DeviceBreak::Io { offset, kind }, DeviceEvent::Io { offset: o, access, .. }) => {
+                offset == o
+                    && matches!(
+                        (kind, access),
+                        (WatchKind::Access, _) | (WatchKind::Read, Access::Read) | (WatchKind::Write, Access::Write)
+                    )
+            }
+            (DeviceBreak::Sd { cmd, sector }, DeviceEvent::SdCommand { cmd: c, sector: s, .. }) => {
+                cmd.is_none_or(|cmd| cmd == c) && sector.is_none_or(|sector| s == Some(sector))
+            }
+            (DeviceBreak::Key(want), DeviceEvent::Key(b)) => want.is_none_or(|want| want == b),
+            (DeviceBreak::Framebuffer { x, y, w, h }, DeviceEvent::Framebuffer { x: px, y: py }) => {
+                px < x.saturating_add(w) && x < px + 32 && (y..y.saturating_add(h)).contains(&py)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for DeviceBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let num = |v: &str| v.parse::<u32>().map_err(|e| format!("invalid number \"{v}\" in \"{s}\": {e}"));
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let args: Vec<&str> = parts.collect();
+        match (kind, args.as_slice()) {
+            ("io", [offset]) => Ok(DeviceBreak::Io { offset: num(offset)?, kind: WatchKind::Access }),
+            ("io", [offset, kind]) => match kind.parse()? {
+                WatchKind::Change => Err("IO breakpoints take read, write or access".into()),
+                kind => Ok(DeviceBreak::Io { offset: num(offset)?, kind }),
+            },
+            ("sd", rest) if rest.len() <= 2 => {
+                let cmd = match rest.first() {
+                    None | Some(&"*") => None,
+                    Some(c) => Some(u8::try_from(num(c)?).ok().filter(|c| *c < 64).ok_or(format!("no SD command {c}"))?),
+                };
+                let sector = rest.get(1).map(|v| num(v)).transpose()?;
+                Ok(DeviceBreak::Sd { cmd, sector })
+            }
+            ("key", []) => Ok(DeviceBreak::Key(None)),
+            ("key", [b]) => {
+                let b = u8::from_str_radix(b.trim_start_matches("0x"), 16).map_err(|e| format!("invalid key byte \"{b}\": {e}"))?;
+                Ok(DeviceBreak::Key(Some(b)))
+            }
+            ("fb", [rect]) => match rect.split(',').map(num).collect::<Result<Vec<_>, _>>()?.as_slice() {
+                &[x, y, w, h] => Ok(DeviceBreak::Framebuffer { x, y, w, h }),
+                _ => Err(format!("expected fb:X,Y,W,H, got \"{s}\"")),
+            },
+            _ => Err(format!("unknown device event \"{s}\" (io:OFFSET[:KIND], sd[:CMD[:SECTOR]], key[:BYTE] or fb:X,Y,W,H)")),
+        }
+    }
+}
+
+impl fmt::Display for DeviceBreak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DeviceBreak::Io { offset, kind } => write!(f, "io:{offset}:{kind}"),
+            DeviceBreak::Sd { cmd, sector } => {
+                f.write_str("sd")?;
+                match (cmd, sector) {
+                    (None, None) => Ok(()),
+                    (Some(c), None) => write!(f, ":{c}"),
+                    (c, Some(s)) => write!(f, ":{}:{s}", c.map_or("*".to_string(), |c| c.to_string())),
+                }
+            }
+            DeviceBreak::Key(None) => f.write_str("key"),
+            DeviceBreak::Key(Some(b)) => write!(f, "key:{b:02X}"),
+            DeviceBreak::Framebuffer { x, y, w, h } => write!(f, "fb:{x},{y},{w},{h}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceBreakpoint {
+    pub id: u32,
+    pub cond: DeviceBreak,
+    pub enabled: bool,
+    pub hits: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DeviceBreaks {
+    list: Vec<DeviceBreakpoint>,
+    next_id: u32,
+}
+
+impl DeviceBreaks {
+    /// Returns the new breakpoint's id.
+    pub fn add(&mut self, cond: DeviceBreak) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.list.push(DeviceBreakpoint { id, cond, enabled: true, hits: 0 });
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<DeviceBreakpoint> {
+        let i = self.list.iter().position(|b| b.id == id)?;
+        Some(self.list.remove(i))
+    }
+
+    pub fn set_enabled(&mut self, id: u32, enabled: bool) -> bool {
+        self.list.iter_mut().find(|b| b.id == id).map(|b| b.enabled = enabled).is_some()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&DeviceBreakpoint> {
+        self.list.iter().find(|b| b.id == id)
+    }
+
+    /// In the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &DeviceBreakpoint> {
+        self.list.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// Counts `event` against every enabled breakpoint it matches; true if
+    /// there was one.
+    pub fn check(&mut self, event: &DeviceEvent) -> bool {
+        let mut hit = false;
+        for b in &mut self.list {
+            if b.enabled && b.cond.matches(event) {
+                b.hits += 1;
+                hit = true;
+            }
+        }
+        hit
+    }
+}
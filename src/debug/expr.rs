@@ -0,0 +1,406 @@
+// src/debug/expr.rs
+//
+// Breakpoint conditions and logpoint messages. An expression is parsed once
+// into a small tree and evaluated against a `CpuView` whenever execution
+// reaches the breakpoint.
+//
+//   R0..R15, SP (R14), LNK (R15), PC, H   registers
+//   N, Z, C, V                            flags (0 or 1)
+//   hits                                  times the breakpoint was reached
+//   [e], b[e]                             word / byte in memory
+//   12, 0x1C, 1CH                         numbers (decimal, hex)
+//
+// Operators, loosest first: `||`, `&&`, `|`, `^`, `&`, `==` `!=`,
+// `<` `<=` `>` `>=`, `<<` `>>`, `+` `-`, `*` `/` `%`, unary `-` `!` `~`.
+// Values are 32-bit; comparisons, division and `>>` are signed, as in
+// Oberon's INTEGER.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cpu::CpuView;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    Reg(u8),
+    Pc,
+    H,
+    N,
+    Z,
+    C,
+    V,
+    Hits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Num(u32),
+    Var(Var),
+    Word(Box<Node>),
+    Byte(Box<Node>),
+    Un(UnOp, Box<Node>),
+    Bin(BinOp, Box<Node>, Box<Node>),
+}
+
+/// What an expression can see.
+pub struct Env<'a> {
+    pub cpu: &'a CpuView,
+    pub hits: u64,
+    /// Word at an aligned address, without side effects.
+    pub peek: &'a mut dyn FnMut(u32) -> Option<u32>,
+}
+
+/// A parsed expression; `Display` gives back the source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    src: String,
+    root: Node,
+}
+
+impl Expr {
+    /// The value, or the address of a memory operand that could not be
+    /// read.
+    pub fn eval(&self, env: &mut Env) -> Result<u32, u32> {
+        eval(&self.root, env)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut p = Parser { toks: lex(s)?, pos: 0 };
+        let root = p.expr(0)?;
+        if let Some(t) = p.toks.get(p.pos) {
+            return Err(format!("unexpected {t} in \"{s}\""));
+        }
+        Ok(Self { src: s.trim().to_string(), root })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.src)
+    }
+}
+
+fn eval(n: &Node, env: &mut Env) -> Result<u32, u32> {
+    Ok(match n {
+        Node::Num(v) => *v,
+        Node::Var(v) => match v {
+            Var::Reg(i) => env.cpu.r[*i as usize],
+            Var::Pc => env.cpu.pc,
+            Var::H => env.cpu.h,
+            Var::N => env.cpu.n as u32,
+            Var::Z => env.cpu.z as u32,
+            Var::C => env.cpu.c as u32,
+            Var::V => env.cpu.v as u32,
+            Var::Hits => env.hits.min(u32::MAX as u64) as u32,
+        },
+        Node::Word(a) => {
+            let a = eval(a, env)? & !3;
+            (env.peek)(a).ok_or(a)?
+        }
+        Node::Byte(a) => {
+            let a = eval(a, env)?;
+            let w = (env.peek)(a & !3).ok_or(a)?;
+            (w >> ((a & 3) * 8)) & 0xFF
+        }
+        Node::Un(op, x) => {
+            let x = eval(x, env)?;
+            match op {
+                UnOp::Neg => x.wrapping_neg(),
+                UnOp::Not => (x == 0) as u32,
+                UnOp::BitNot => !x,
+            }
+        }
+        // short-circuit, so `R1 != 0 && [R1] == 5` doesn't read address 0
+        Node::Bin(BinOp::And, a, b) => (eval(a, env)? != 0 && eval(b, env)? != 0) as u32,
+        Node::Bin(BinOp::Or, a, b) => (eval(a, env)? != 0 || eval(b, env)? != 0) as u32,
+        Node::Bin(op, a, b) => {
+            let (x, y) = (eval(a, env)?, eval(b, env)?);
+            let (sx, sy) = (x as i32, y as i32);
+            match op {
+                BinOp::BitOr => x | y,
+                BinOp::BitXor => x ^ y,
+                BinOp::BitAnd => x & y,
+                BinOp::Eq => (x == y) as u32,
+                BinOp::Ne => (x != y) as u32,
+                BinOp::Lt => (sx < sy) as u32,
+                BinOp::Le => (sx <= sy) as u32,
+                BinOp::Gt => (sx > sy) as u32,
+                BinOp::Ge => (sx >= sy) as u32,
+                BinOp::Shl => x.wrapping_shl(y),
+                BinOp::Shr => sx.wrapping_shr(y) as u32,
+                BinOp::Add => x.wrapping_add(y),
+                BinOp::Sub => x.wrapping_sub(y),
+                BinOp::Mul => x.wrapping_mul(y),
+                BinOp::Div => sx.checked_div(sy).unwrap_or(0) as u32,
+                BinOp::Mod => sx.checked_rem(sy).unwrap_or(0) as u32,
+                BinOp::And | BinOp::Or => unreachable!(),
+            }
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Num(u32),
+    Ident(String),
+    Sym(&'static str),
+}
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tok::Num(v) => write!(f, "{v}"),
+            Tok::Ident(s) => write!(f, "\"{s}\""),
+            Tok::Sym(s) => write!(f, "\"{s}\""),
+        }
+    }
+}
+
+const SYMS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(",
+    ")", "[", "]",
+];
+
+fn lex(s: &str) -> Result<Vec<Tok>, String> {
+    let mut toks = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let word = &rest[..end];
+            toks.push(if c.is_ascii_digit() { Tok::Num(number(word)?) } else { Tok::Ident(word.to_string()) });
+            rest = &rest[end..];
+        } else if let Some(sym) = SYMS.iter().find(|sym| rest.starts_with(**sym)) {
+            toks.push(Tok::Sym(sym));
+            rest = &rest[sym.len()..];
+        } else {
+            return Err(format!("unexpected '{c}' in \"{s}\""));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(toks)
+}
+
+fn number(w: &str) -> Result<u32, String> {
+    let parsed = if let Some(hex) = w.strip_prefix("0x").or_else(|| w.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(hex) = w.strip_suffix('H') {
+        u32::from_str_radix(hex, 16)
+    } else {
+        w.parse()
+    };
+    parsed.map_err(|e| format!("invalid number \"{w}\": {e}"))
+}
+
+/// Binding power of the binary operators, loosest first.
+fn binop(sym: &str) -> Option<(u8, BinOp)> {
+    Some(match sym {
+        "||" => (1, BinOp::Or),
+        "&&" => (2, BinOp::And),
+        "|" => (3, BinOp::BitOr),
+        "^" => (4, BinOp::BitXor),
+        "&" => (5, BinOp::BitAnd),
+        "==" => (6, BinOp::Eq),
+        "!=" => (6, BinOp::Ne),
+        "<" => (7, BinOp::Lt),
+        "<=" => (7, BinOp::Le),
+        ">" => (7, BinOp::Gt),
+        ">=" => (7, BinOp::Ge),
+        "<<" => (8, BinOp::Shl),
+        ">>" => (8, BinOp::Shr),
+        "+" => (9, BinOp::Add),
+        "-" => (9, BinOp::Sub),
+        "*" => (10, BinOp::Mul),
+        "/" => (10, BinOp::Div),
+        "%" => (10, BinOp::Mod),
+        _ => return None,
+    })
+}
+
+struct Parser {
+    toks: Vec<Tok>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Tok> {
+        let t = self.toks.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn expect(&mut self, sym: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Tok::Sym(s)) if s == sym => Ok(()),
+            Some(t) => Err(format!("expected \"{sym}\", found {t}")),
+            None => Err(format!("expected \"{sym}\" at the end")),
+        }
+    }
+
+    // precedence climbing: operators binding tighter than `min`
+    fn expr(&mut self, min: u8) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+        while let Some(Tok::Sym(sym)) = self.toks.get(self.pos) {
+            let Some((prec, op)) = binop(sym) else { break };
+            if prec <= min {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(prec)?;
+            lhs = Node::Bin(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let op = match self.toks.get(self.pos) {
+            Some(Tok::Sym("-")) => UnOp::Neg,
+            Some(Tok::Sym("!")) => UnOp::Not,
+            Some(Tok::Sym("~")) => UnOp::BitNot,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Node::Un(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Tok::Num(v)) => Ok(Node::Num(v)),
+            Some(Tok::Sym("(")) => {
+                let e = self.expr(0)?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some(Tok::Sym("[")) => {
+                let e = self.expr(0)?;
+                self.expect("]")?;
+                Ok(Node::Word(Box::new(e)))
+            }
+            Some(Tok::Ident(id)) if id == "b" => {
+                self.expect("[")?;
+                let e = self.expr(0)?;
+                self.expect("]")?;
+                Ok(Node::Byte(Box::new(e)))
+            }
+            Some(Tok::Ident(id)) => variable(&id).map(Node::Var).ok_or(format!("unknown name \"{id}\"")),
+            Some(t) => Err(format!("unexpected {t}")),
+            None => Err("expression ends too early".into()),
+        }
+    }
+}
+
+fn variable(id: &str) -> Option<Var> {
+    let upper = id.to_ascii_uppercase();
+    Some(match upper.as_str() {
+        "PC" => Var::Pc,
+        "H" => Var::H,
+        "N" => Var::N,
+        "Z" => Var::Z,
+        "C" => Var::C,
+        "V" => Var::V,
+        "SP" => Var::Reg(14),
+        "LNK" => Var::Reg(15),
+        "HITS" => Var::Hits,
+        _ => {
+            let n: u8 = upper.strip_prefix('R')?.parse().ok()?;
+            (n < 16).then_some(Var::Reg(n))?
+        }
+    })
+}
+
+/// Logpoint text with `{expr}` (decimal) and `{expr:x}` (hex) fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogMessage {
+    src: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Value(Expr, bool),
+}
+
+impl LogMessage {
+    /// The message with its fields filled in; fields that read unmapped
+    /// memory show as `<addr?>`.
+    pub fn format(&self, env: &mut Env) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(t) => out.push_str(t),
+                Part::Value(e, hex) => match e.eval(env) {
+                    Ok(v) if *hex => out.push_str(&format!("{v:X}")),
+                    Ok(v) => out.push_str(&(v as i32).to_string()),
+                    Err(a) => out.push_str(&format!("<{a:08X}?>")),
+                },
+            }
+        }
+        out
+    }
+}
+
+impl FromStr for LogMessage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+            let close = rest[open..].find('}').ok_or(format!("unclosed '{{' in \"{s}\""))? + open;
+            let field = &rest[open + 1..close];
+            let (expr, hex) = match field.strip_suffix(":x") {
+                Some(e) => (e, true),
+                None => (field, false),
+            };
+            parts.push(Part::Value(expr.parse()?, hex));
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Self { src: s.to_string(), parts })
+    }
+}
+
+impl fmt::Display for LogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.src)
+    }
+}
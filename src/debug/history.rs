@@ -0,0 +1,155 @@
+// src/debug/history.rs
+//
+// Undo journal for reverse execution. While `Cpu::history` is set, every
+// instruction leaves an entry with what it is about to change: PC, H,
+// flags and the saved interrupt state, the old value of each register it
+// wrote and the old contents of the RAM word it stored to. `Cpu::step_back`
+// plays the newest entry backwards; only the last `limit` instructions are
+// kept.
+//
+// Devices are not journaled. Sectors written to disk, bytes sent over the
+// serial line and key bytes the guest consumed stay as they are, and stores
+// to IO are not undone. The virtual timer is wound back with the
+// instruction count.
+
+use std::collections::VecDeque;
+
+/// Instructions kept when no limit is given.
+pub const DEFAULT_HISTORY: usize = 1_000_000;
+
+/// CPU state an instruction may change besides R0–R15 and RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    pub pc: u32,
+    pub h: u32,
+    pub spc: u32,
+    pub sflags: u32,
+    /// N Z C V, interrupts enabled, interrupt mode (bit 0 up).
+    pub flags: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    state: Snapshot,
+    // entries of `regs` and `stores` that belong to this instruction
+    regs: u8,
+    stores: u8,
+    // false when the instruction could not be fetched, so the timer did not
+    // advance
+    clock: bool,
+}
+
+/// One instruction taken off the journal.
+pub(crate) struct Entry {
+    /// The state to go back to.
+    pub state: Snapshot,
+    /// Registers to restore.
+    pub regs: Vec<(u8, u32)>,
+    /// Stores to undo, newest first: address, size, old word.
+    pub stores: Vec<(u32, u8, u32)>,
+    pub clock: bool,
+}
+
+/// A recorded store, as seen by the instruction that did it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Store {
+    pub addr: u32,
+    /// 1 or 4 bytes.
+    pub size: u8,
+    /// The stored bytes before and after the instruction.
+    pub old: u32,
+    pub new: u32,
+}
+
+/// An instruction taken back by `Cpu::step_back`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undone {
+    /// Address of the instruction, where PC is now.
+    pub pc: u32,
+    pub stores: Vec<Store>,
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    limit: usize,
+    frames: VecDeque<Frame>,
+    regs: VecDeque<(u8, u32)>,
+    // address (byte address for byte stores), size, old word
+    stores: VecDeque<(u32, u8, u32)>,
+    pending_stores: u8,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY)
+    }
+}
+
+impl History {
+    /// Keeps the last `limit` instructions.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            frames: VecDeque::new(),
+            regs: VecDeque::new(),
+            stores: VecDeque::new(),
+            pending_stores: 0,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Recorded instructions.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.regs.clear();
+        self.stores.clear();
+        self.pending_stores = 0;
+    }
+
+    /// The current instruction is about to overwrite `old`, the word
+    /// holding `addr`.
+    pub(crate) fn store(&mut self, addr: u32, size: u8, old: u32) {
+        self.stores.push_back((addr, size, old));
+        self.pending_stores += 1;
+    }
+
+    /// Closes the entry of an instruction that started in `state` with
+    /// registers `before`.
+    pub(crate) fn push(&mut self, state: Snapshot, before: &[u32; 16], after: &[u32; 16], clock: bool) {
+        let mut regs = 0;
+        for (i, (&b, &a)) in before.iter().zip(after).enumerate() {
+            if b != a {
+                self.regs.push_back((i as u8, b));
+                regs += 1;
+            }
+        }
+        let stores = std::mem::take(&mut self.pending_stores);
+        self.frames.push_back(Frame { state, regs, stores, clock });
+
+        if self.frames.len() > self.limit {
+            let old = self.frames.pop_front().expect("frames over limit");
+            self.regs.drain(..old.regs as usize);
+            self.stores.drain(..old.stores as usize);
+        }
+    }
+
+    /// Takes the newest entry.
+    pub(crate) fn pop(&mut self) -> Option<Entry> {
+        let f = self.frames.pop_back()?;
+        let regs = self.regs.split_off(self.regs.len() - f.regs as usize).into();
+        let mut stores: Vec<_> = self.stores.split_off(self.stores.len() - f.stores as usize).into();
+        stores.reverse();
+        Some(Entry { state: f.state, regs, stores, clock: f.clock })
+    }
+}
@@ -0,0 +1,31 @@
+//! Debugger support shared by `Cpu::run`, the headless runner and the GUI.
+
+pub mod breakpoints;
+pub mod calls;
+pub mod coverage;
+pub mod events;
+pub mod expr;
+pub mod history;
+pub mod profile;
+pub mod symbols;
+pub mod trace;
+pub mod watchpoints;
+
+pub use breakpoints::{BreakSpec, Breakpoint, Breakpoints};
+pub use calls::{CallEvent, CallStack, CallTracer, Timebase};
+pub use coverage::{Coverage, UnitReport};
+pub use expr::{Expr, LogMessage};
+pub use history::{History, Store, Undone};
+pub use profile::{OpClass, PcStats, ProcStats, Profiler};
+pub use symbols::{ModuleInfo, Symbols};
+pub use trace::{AddrRange, MemAccess, TraceReader, TraceRecord, TraceWriter, Tracer};
+pub use events::{DeviceBreak, DeviceBreakpoint, DeviceBreaks, DeviceEvent, DeviceHit};
+pub use watchpoints::{Access, WatchHit, WatchKind, WatchSpec, Watchpoint, Watchpoints};
+
+/// A watchpoint or device breakpoint hit by the current instruction, kept
+/// by the bus until the CPU has finished it (`CpuBus::take_debug_hit`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugHit {
+    Watch(WatchHit),
+    Device(DeviceEvent),
+}
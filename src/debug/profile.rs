@@ -0,0 +1,248 @@
+// src/debug/profile.rs
+//
+// Execution profile. `Profiler` is a `Tracer`: put it in `Cpu::tracers` and
+// it counts executions per PC, taken and not-taken branches and the
+// instruction mix.
+//
+// It also follows the calls with a `CallStack` and counts each instruction in
+// the call tree node of the stack it ran in; `write_folded` prints the tree
+// in the folded format of flamegraph.pl and inferno.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::decode::{Decoded, Op};
+
+use super::calls::{branch_taken, CallEvent, CallStack, INTERRUPT_VECTOR};
+use super::trace::{AddrRange, TraceRecord, Tracer};
+
+/// Instruction classes of `Profiler::mix`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpClass {
+    Alu,
+    Mul,
+    Div,
+    Float,
+    Load,
+    Store,
+    Branch,
+    Interrupt,
+}
+
+impl OpClass {
+    pub const ALL: [OpClass; 8] = [
+        OpClass::Alu,
+        OpClass::Mul,
+        OpClass::Div,
+        OpClass::Float,
+        OpClass::Load,
+        OpClass::Store,
+        OpClass::Branch,
+        OpClass::Interrupt,
+    ];
+
+    pub fn of(op: Op) -> Self {
+        match op {
+            Op::Mul | Op::Mulu => OpClass::Mul,
+            Op::Div | Op::Divu => OpClass::Div,
+            Op::Fad | Op::Fsb | Op::Fml | Op::Fdv => OpClass::Float,
+            Op::Ldw | Op::Ldb => OpClass::Load,
+            Op::Stw | Op::Stb => OpClass::Store,
+            Op::Br | Op::BrLink => OpClass::Branch,
+            Op::IntCtl => OpClass::Interrupt,
+            _ => OpClass::Alu,
+        }
+    }
+}
+
+impl fmt::Display for OpClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OpClass::Alu => "alu",
+            OpClass::Mul => "mul",
+            OpClass::Div => "div",
+            OpClass::Float => "float",
+            OpClass::Load => "load",
+            OpClass::Store => "store",
+            OpClass::Branch => "branch",
+            OpClass::Interrupt => "sti/cli/rti",
+        })
+    }
+}
+
+/// Counts for one instruction address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PcStats {
+    pub count: u64,
+    /// Branches only.
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Instructions run in one procedure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcStats {
+    /// Entry address, as reached by `BL` (or the interrupt vector).
+    pub entry: u32,
+    /// Instructions run in the procedure itself.
+    pub own: u64,
+    /// Including the procedures it called.
+    pub total: u64,
+}
+
+/// Call tree node; node 0 is the stack the profile started in.
+#[derive(Debug, Clone)]
+struct Node {
+    parent: usize,
+    entry: u32,
+    count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    pcs: HashMap<u32, PcStats>,
+    mix: [u64; OpClass::ALL.len()],
+    total: u64,
+    nodes: Vec<Node>,
+    children: HashMap<(usize, u32), usize>,
+    calls: CallStack,
+    /// Call tree nodes of the frames of `calls`.
+    path: Vec<usize>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            pcs: HashMap::new(),
+            mix: [0; OpClass::ALL.len()],
+            total: 0,
+            nodes: vec![Node { parent: 0, entry: 0, count: 0 }],
+            children: HashMap::new(),
+            calls: CallStack::new(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Instructions counted.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn pc(&self, pc: u32) -> PcStats {
+        self.pcs.get(&pc).copied().unwrap_or_default()
+    }
+
+    /// Counts per address, most executed first.
+    pub fn hot_spots(&self) -> Vec<(u32, PcStats)> {
+        let mut v: Vec<_> = self.pcs.iter().map(|(&pc, &s)| (pc, s)).collect();
+        v.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(&b.0)));
+        v
+    }
+
+    pub fn mix(&self, class: OpClass) -> u64 {
+        self.mix[class as usize]
+    }
+
+    /// Sums the counts of the addresses in each range.
+    pub fn by_range<'a>(&self, ranges: &'a [(String, AddrRange)]) -> Vec<(&'a str, u64)> {
+        ranges
+            .iter()
+            .map(|(name, r)| {
+                let n = self.pcs.iter().filter(|(pc, _)| r.contains(**pc)).map(|(_, s)| s.count).sum();
+                (name.as_str(), n)
+            })
+            .collect()
+    }
+
+    /// Counts per procedure entry, most instructions (with callees) first.
+    /// Code that ran before the first call seen is not in any procedure.
+    pub fn procedures(&self) -> Vec<ProcStats> {
+        let mut procs: HashMap<u32, ProcStats> = HashMap::new();
+        let mut seen = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            procs.entry(node.entry).or_insert(ProcStats { entry: node.entry, own: 0, total: 0 }).own += node.count;
+            // a recursive procedure counts once per stack
+            seen.clear();
+            let mut n = i;
+            while n != 0 {
+                let entry = self.nodes[n].entry;
+                if !seen.contains(&entry) {
+                    seen.push(entry);
+                    procs.entry(entry).or_insert(ProcStats { entry, own: 0, total: 0 }).total += node.count;
+                }
+                n = self.nodes[n].parent;
+            }
+        }
+        let mut v: Vec<_> = procs.into_values().collect();
+        v.sort_by(|a, b| b.total.cmp(&a.total).then(a.entry.cmp(&b.entry)));
+        v
+    }
+
+    /// Writes one `frame;frame;frame count` line per call stack, outermost
+    /// frame first. `name` turns entry addresses into frame names.
+    pub fn write_folded(&self, out: &mut dyn Write, name: &dyn Fn(u32) -> String) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.count == 0 {
+                continue;
+            }
+            let mut frames = Vec::new();
+            let mut n = i;
+            while n != 0 {
+                frames.push(name(self.nodes[n].entry));
+                n = self.nodes[n].parent;
+            }
+            frames.push("(root)".to_string());
+            frames.reverse();
+            lines.push((frames.join(";"), node.count));
+        }
+        lines.sort();
+        for (stack, count) in lines {
+            writeln!(out, "{stack} {count}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Tracer for Profiler {
+    fn record(&mut self, rec: &TraceRecord) {
+        let Self { calls, path, nodes, children, .. } = self;
+        calls.update(rec, &mut |ev| match ev {
+            CallEvent::Call(_) | CallEvent::Interrupt => {
+                let entry = if let CallEvent::Call(e) = ev { e } else { INTERRUPT_VECTOR };
+                let parent = path.last().copied().unwrap_or(0);
+                let next = nodes.len();
+                let node = *children.entry((parent, entry)).or_insert(next);
+                if node == next {
+                    nodes.push(Node { parent, entry, count: 0 });
+                }
+                path.push(node);
+            }
+            CallEvent::Return => {
+                path.pop();
+            }
+        });
+
+        let d = Decoded::new(rec.ir);
+        let node = self.path.last().copied().unwrap_or(0);
+        self.nodes[node].count += 1;
+        self.mix[OpClass::of(d.op) as usize] += 1;
+        self.total += 1;
+        let stats = self.pcs.entry(rec.pc).or_default();
+        stats.count += 1;
+        if matches!(d.op, Op::Br | Op::BrLink) {
+            if branch_taken(&d, rec) {
+                stats.taken += 1;
+            } else {
+                stats.not_taken += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// src/debug/symbols.rs
+//
+// Names for code addresses, used by the profiler and the call tracer.
+// They come from two places:
+//
+// - map files written on the host, one `ADDR NAME` per line (hex address,
+//   `#` starts a comment);
+// - the module list of a running Oberon. Each descriptor starts with the
+//   module name (32 bytes), then `next` at 32, `data` at 52, `code` at 56
+//   and `imp` at 60, which is where the code ends, and `cmd` at 64: the
+//   command table, NUL-terminated names padded to a word, each followed by
+//   its byte offset in the code, ended by an empty name.
+//
+//   The boot loader leaves the list of the inner core at address 20, where
+//   `Modules.Init` picks it up. Later modules go in front of the variable
+//   `Modules.root`, which lives in the data area of module Modules after its
+//   type descriptors. We take the word there that heads the longest list
+//   running through Modules itself; other pointers to descriptors (such as
+//   `Modules.M`) head a part of it at most.
+//
+// Modules give code ranges and command names only; other procedures show as
+// `Module+OFFSET`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::bus::{BusError, BusResult};
+
+/// Where the boot loader leaves the module list of the inner core.
+pub const BOOT_MODULES: u32 = 20;
+
+/// Data areas larger than this are not the one of module Modules.
+const MAX_DATA: u32 = 0x1_0000;
+
+/// More modules than this means the list is not what we think it is.
+const MAX_MODULES: usize = 512;
+const MAX_COMMANDS: usize = 1024;
+
+/// Code range of an Oberon module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub code: u32,
+    /// First address past the code.
+    pub end: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    names: BTreeMap<u32, String>,
+    modules: Vec<ModuleInfo>,
+    /// Command entries of `modules`.
+    commands: BTreeMap<u32, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.modules.is_empty()
+    }
+
+    pub fn insert(&mut self, addr: u32, name: impl Into<String>) {
+        self.names.insert(addr, name.into());
+    }
+
+    pub fn modules(&self) -> &[ModuleInfo] {
+        &self.modules
+    }
+
+    /// Adds the symbols of a map file.
+    pub fn load_map(&mut self, path: &Path) -> BusResult<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| BusError::Device(format!("can't read \"{}\": {e}", path.display())))?;
+        self.parse_map(&text).map_err(|e| BusError::Device(format!("{}: {e}", path.display())))
+    }
+
+    pub fn parse_map(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (addr, name) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| format!("line {}: expected ADDR NAME", i + 1))?;
+            let addr = u32::from_str_radix(addr.trim_start_matches("0x"), 16)
+                .map_err(|e| format!("line {}: invalid address \"{addr}\": {e}", i + 1))?;
+            self.insert(addr, name.trim());
+        }
+        Ok(())
+    }
+
+    /// Replaces the module symbols with those of the module list in guest
+    /// memory. Returns the number of modules found; a list that can't be
+    /// read (no Oberon loaded yet) gives none.
+    pub fn read_modules(&mut self, peek: &mut dyn FnMut(u32) -> Option<u32>) -> usize {
+        self.modules.clear();
+        self.commands.clear();
+        let boot = peek(BOOT_MODULES).unwrap_or(0);
+        let mut m = find_root(boot, peek).unwrap_or(boot);
+        while m != 0 && self.modules.len() < MAX_MODULES {
+            let Some(module) = read_module(m, peek) else { break };
+            for (name, addr) in module.commands {
+                self.commands.insert(addr, format!("{}.{name}", module.info.name));
+            }
+            self.modules.push(module.info);
+            m = module.next;
+        }
+        self.modules.len()
+    }
+
+    /// The symbol at `addr`, `Module+OFFSET` inside a module, `name+OFFSET`
+    /// after a map symbol, or the address in hex.
+    pub fn name(&self, addr: u32) -> String {
+        if let Some(name) = self.names.get(&addr).or_else(|| self.commands.get(&addr)) {
+            return name.clone();
+        }
+        if let Some(m) = self.modules.iter().find(|m| (m.code..m.end).contains(&addr)) {
+            return format!("{}+{:X}", m.name, addr - m.code);
+        }
+        match self.names.range(..addr).next_back() {
+            Some((base, name)) => format!("{name}+{:X}", addr - base),
+            None => format!("{addr:08X}"),
+        }
+    }
+}
+
+struct Module {
+    info: ModuleInfo,
+    next: u32,
+    commands: Vec<(String, u32)>,
+}
+
+/// Descriptors of the module list starting at `m`, as far as they have names.
+fn chain(mut m: u32, peek: &mut dyn FnMut(u32) -> Option<u32>) -> Vec<u32> {
+    let mut list = Vec::new();
+    while m != 0 && m & 3 == 0 && list.len() < MAX_MODULES && !list.contains(&m) {
+        if read_name(m, 32, peek).is_none_or(|(name, _)| name.is_empty()) {
+            break;
+        }
+        list.push(m);
+        let Some(next) = peek(m.wrapping_add(32)) else { break };
+        m = next;
+    }
+    list
+}
+
+/// The value of `Modules.root`, found through the boot list.
+fn find_root(boot: u32, peek: &mut dyn FnMut(u32) -> Option<u32>) -> Option<u32> {
+    let modules = chain(boot, peek)
+        .into_iter()
+        .find(|&m| read_name(m, 32, peek).is_some_and(|(name, _)| name == "Modules"))?;
+    let data = peek(modules.wrapping_add(52))?;
+    let code = peek(modules.wrapping_add(56))?;
+    if code <= data || code - data > MAX_DATA {
+        return None;
+    }
+    let mut best: Option<(u32, usize)> = None;
+    for addr in (data & !3..code).step_by(4) {
+        let Some(p) = peek(addr) else { continue };
+        let list = chain(p, peek);
+        if list.contains(&modules) && best.is_none_or(|(_, n)| list.len() > n) {
+            best = Some((p, list.len()));
+        }
+    }
+    best.map(|(p, _)| p)
+}
+
+fn read_module(m: u32, peek: &mut dyn FnMut(u32) -> Option<u32>) -> Option<Module> {
+    let name = read_name(m, 32, peek)?.0;
+    let next = peek(m.wrapping_add(32))?;
+    let code = peek(m.wrapping_add(56))?;
+    let end = peek(m.wrapping_add(60))?;
+    let mut cmd = peek(m.wrapping_add(64))?;
+    if name.is_empty() || end < code {
+        return None;
+    }
+    let mut commands = Vec::new();
+    while commands.len() < MAX_COMMANDS {
+        let (cname, len) = read_name(cmd, 32, peek)?;
+        if cname.is_empty() {
+            break;
+        }
+        let at = cmd.wrapping_add((len as u32 + 1).next_multiple_of(4));
+        commands.push((cname, code.wrapping_add(peek(at)?)));
+        cmd = at.wrapping_add(4);
+    }
+    Some(Module { info: ModuleInfo { name, code, end }, next, commands })
+}
+
+/// NUL-terminated ASCII name of at most `max` bytes, and its length.
+fn read_name(addr: u32, max: usize, peek: &mut dyn FnMut(u32) -> Option<u32>) -> Option<(String, usize)> {
+    let mut name = String::new();
+    for i in 0..max as u32 {
+        let a = addr.wrapping_add(i);
+        let b = (peek(a & !3)? >> ((a & 3) * 8)) as u8;
+        if b == 0 {
+            return Some((name, i as usize));
+        }
+        if !b.is_ascii_graphic() {
+            return None;
+        }
+        name.push(b as char);
+    }
+    None
+}
@@ -0,0 +1,267 @@
+// src/debug/trace.rs
+//
+// Instruction traces. `Cpu::try_step` hands a `TraceRecord` for every
+// instruction that completes to each of `Cpu::tracers`; `TraceWriter` is
+// the tracer that stores them in a file, `TraceReader` reads them back.
+//
+// File format, little-endian:
+//
+//   "RISCTRC1" { head: u16, [pc: u32], ir: u32, [value: u32],
+//                [addr: u32, data: u32] }
+//
+//   head bit 0     PC follows; otherwise PC is the previous PC + 4
+//   head bit 1     a register was written: number in bits 8..11, value follows
+//   head bit 2     load or store: address and data follow
+//   head bit 3     byte access
+//   head bit 12    store
+//   head bits 4..7 N Z C V after the instruction (N in bit 7)
+//
+// A straight-line ALU instruction takes 10 bytes.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::bus::{BusError, BusResult};
+
+const MAGIC: &[u8; 8] = b"RISCTRC1";
+
+const HAS_PC: u16 = 1 << 0;
+const HAS_DEST: u16 = 1 << 1;
+const HAS_MEM: u16 = 1 << 2;
+const BYTE: u16 = 1 << 3;
+const STORE: u16 = 1 << 12;
+
+fn err(msg: impl Into<String>) -> BusError {
+    BusError::Device(format!("trace: {}", msg.into()))
+}
+
+/// Data access of a traced instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: u32,
+    /// Loaded or stored value (one byte for byte accesses).
+    pub value: u32,
+    pub store: bool,
+    pub byte: bool,
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u32,
+    pub ir: u32,
+    /// Register written and its new value.
+    pub dest: Option<(u8, u32)>,
+    /// N Z C V after the instruction, N in bit 3.
+    pub flags: u8,
+    pub mem: Option<MemAccess>,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<48}", crate::disasm::format_line(self.pc, self.ir))?;
+        if let Some((r, v)) = self.dest {
+            write!(f, " R{r}={v:08X}")?;
+        }
+        let flag = |bit: u8, c: char| if self.flags & (1 << bit) != 0 { c } else { '-' };
+        write!(f, " {}{}{}{}", flag(3, 'N'), flag(2, 'Z'), flag(1, 'C'), flag(0, 'V'))?;
+        if let Some(m) = self.mem {
+            let w = if m.byte { 2 } else { 8 };
+            let dir = if m.store { "<-" } else { "->" };
+            write!(f, " [{:08X}] {dir} {:0w$X}", m.addr, m.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sees every instruction the CPU completes.
+pub trait Tracer: fmt::Debug + Send {
+    fn record(&mut self, rec: &TraceRecord);
+}
+
+/// Writes records to a trace file. Write errors are kept and returned by
+/// `finish`; records after one are dropped.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    next_pc: Option<u32>,
+    records: u64,
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write> fmt::Debug for TraceWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceWriter").field("records", &self.records).finish()
+    }
+}
+
+impl TraceWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> BusResult<Self> {
+        let f = File::create(path).map_err(|e| err(format!("can't create \"{}\": {e}", path.display())))?;
+        Self::new(BufWriter::new(f))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W) -> BusResult<Self> {
+        out.write_all(MAGIC).map_err(|e| err(e.to_string()))?;
+        Ok(Self { out, next_pc: None, records: 0, error: None })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    /// Records written so far.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Flushes the output; reports the first write error, if any.
+    pub fn finish(&mut self) -> BusResult<()> {
+        if let Some(e) = self.error.take() {
+            return Err(err(e.to_string()));
+        }
+        self.out.flush().map_err(|e| err(e.to_string()))
+    }
+
+    fn write(&mut self, rec: &TraceRecord) -> std::io::Result<()> {
+        let mut head = (rec.flags as u16 & 0xF) << 4;
+        let mut buf = [0u8; 22];
+        let mut n = 2;
+        let mut put = |v: u32| {
+            buf[n..n + 4].copy_from_slice(&v.to_le_bytes());
+            n += 4;
+        };
+        if self.next_pc != Some(rec.pc) {
+            head |= HAS_PC;
+            put(rec.pc);
+        }
+        put(rec.ir);
+        if let Some((r, v)) = rec.dest {
+            head |= HAS_DEST | (r as u16 & 0xF) << 8;
+            put(v);
+        }
+        if let Some(m) = rec.mem {
+            head |= HAS_MEM;
+            if m.byte {
+                head |= BYTE;
+            }
+            if m.store {
+                head |= STORE;
+            }
+            put(m.addr);
+            put(m.value);
+        }
+        buf[..2].copy_from_slice(&head.to_le_bytes());
+        self.next_pc = Some(rec.pc.wrapping_add(4));
+        self.out.write_all(&buf[..n])
+    }
+}
+
+impl<W: Write + Send> Tracer for TraceWriter<W> {
+    fn record(&mut self, rec: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.write(rec) {
+            Ok(()) => self.records += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+/// Reads a trace file record by record.
+pub struct TraceReader<R: Read> {
+    input: R,
+    next_pc: u32,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open(path: &Path) -> BusResult<Self> {
+        let f = File::open(path).map_err(|e| err(format!("can't open \"{}\": {e}", path.display())))?;
+        Self::new(BufReader::new(f))
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> BusResult<Self> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic).map_err(|e| err(e.to_string()))?;
+        if &magic != MAGIC {
+            return Err(err("not a trace file"));
+        }
+        Ok(Self { input, next_pc: 0 })
+    }
+
+    fn u32(&mut self) -> BusResult<u32> {
+        let mut b = [0u8; 4];
+        self.input.read_exact(&mut b).map_err(|_| err("last record is cut short"))?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn next_record(&mut self) -> BusResult<Option<TraceRecord>> {
+        let mut b = [0u8; 2];
+        match self.input.read_exact(&mut b[..1]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(err(e.to_string())),
+        }
+        self.input.read_exact(&mut b[1..]).map_err(|_| err("last record is cut short"))?;
+        let head = u16::from_le_bytes(b);
+        let pc = if head & HAS_PC != 0 { self.u32()? } else { self.next_pc };
+        let ir = self.u32()?;
+        let dest = if head & HAS_DEST != 0 { Some(((head >> 8) as u8 & 0xF, self.u32()?)) } else { None };
+        let mem = if head & HAS_MEM != 0 {
+            let addr = self.u32()?;
+            let value = self.u32()?;
+            Some(MemAccess { addr, value, store: head & STORE != 0, byte: head & BYTE != 0 })
+        } else {
+            None
+        };
+        self.next_pc = pc.wrapping_add(4);
+        Ok(Some(TraceRecord { pc, ir, dest, flags: (head >> 4) as u8 & 0xF, mem }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = BusResult<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Inclusive address range, written `ADDR` or `FIRST-LAST` (hex).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddrRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl AddrRange {
+    pub fn contains(&self, addr: u32) -> bool {
+        (self.first..=self.last).contains(&addr)
+    }
+}
+
+impl FromStr for AddrRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = |v: &str| {
+            let digits = v.strip_prefix("0x").or_else(|| v.strip_prefix("0X")).unwrap_or(v);
+            u32::from_str_radix(digits, 16).map_err(|e| format!("invalid address \"{v}\": {e}"))
+        };
+        let (first, last) = match s.split_once('-') {
+            Some((a, b)) => (hex(a)?, hex(b)?),
+            None => (hex(s)?, hex(s)?),
+        };
+        if first > last {
+            return Err(format!("empty range \"{s}\""));
+        }
+        Ok(AddrRange { first, last })
+    }
+}
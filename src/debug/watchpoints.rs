@@ -0,0 +1,214 @@
+// src/debug/watchpoints.rs
+//
+// Data watchpoints on RAM address ranges. `SystemBus` checks every CPU load
+// and store against the list and keeps the first hit until the CPU picks
+// it up after the instruction (`CpuBus::take_debug_hit`), so a hit stops
+// the run with the accessing instruction completed. Byte loads and stores
+// are reported as one byte, even though the bus moves whole words.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// What a watchpoint reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Reads and writes.
+    Access,
+    /// Writes that change the stored value.
+    Change,
+}
+
+impl FromStr for WatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "r" | "read" => Ok(WatchKind::Read),
+            "w" | "write" => Ok(WatchKind::Write),
+            "rw" | "access" => Ok(WatchKind::Access),
+            "change" => Ok(WatchKind::Change),
+            _ => Err(format!("unknown watch kind \"{s}\" (read, write, access or change)")),
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+            WatchKind::Change => "change",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Watches `[start, start + len)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: u32,
+    pub start: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+    pub enabled: bool,
+    /// Accesses that matched while enabled.
+    pub hits: u64,
+}
+
+impl Watchpoint {
+    fn overlaps(&self, addr: u32, size: u32) -> bool {
+        let end = self.start as u64 + self.len as u64;
+        (addr as u64) < end && (self.start as u64) < addr as u64 + size as u64
+    }
+
+    /// `old` and `new` hold the `size` bytes at `addr`, the first in the low
+    /// byte; a change counts only in the bytes watched.
+    fn matches(&self, access: Access, addr: u32, size: u32, old: u32, new: u32) -> bool {
+        match (self.kind, access) {
+            (WatchKind::Access, _) => true,
+            (WatchKind::Read, Access::Read) | (WatchKind::Write, Access::Write) => true,
+            (WatchKind::Change, Access::Write) => {
+                let mask = (0..size.min(4))
+                    .filter(|&i| self.overlaps(addr.wrapping_add(i), 1))
+                    .fold(0u32, |m, i| m | 0xFF << (i * 8));
+                (old ^ new) & mask != 0
+            }
+            _ => false,
+        }
+    }
+}
+
+/// One access that matched a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// The accessing instruction (set by the CPU).
+    pub pc: u32,
+    pub addr: u32,
+    /// 1 or 4 bytes.
+    pub size: u8,
+    pub access: Access,
+    /// Value before the access; the same as `new` for reads.
+    pub old: u32,
+    pub new: u32,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let w = self.size as usize * 2;
+        match self.access {
+            Access::Read => write!(f, "PC {:08X} read {:0w$X} from {:08X} ({} bytes)", self.pc, self.new, self.addr, self.size),
+            Access::Write => write!(
+                f,
+                "PC {:08X} wrote {:08X}: {:0w$X} -> {:0w$X} ({} bytes)",
+                self.pc, self.addr, self.old, self.new, self.size
+            ),
+        }
+    }
+}
+
+/// Command line form of a watchpoint: `ADDR[+LEN][:KIND]`, address in hex,
+/// length in bytes (default 4), kind defaults to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchSpec {
+    pub start: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+impl FromStr for WatchSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, kind) = match s.split_once(':') {
+            Some((r, k)) => (r, k.parse()?),
+            None => (s, WatchKind::Write),
+        };
+        let (addr, len) = match range.split_once('+') {
+            Some((a, n)) => (a, n.parse().map_err(|e| format!("invalid length \"{n}\": {e}"))?),
+            None => (range, 4),
+        };
+        let hex = addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")).unwrap_or(addr);
+        let start = u32::from_str_radix(hex, 16).map_err(|e| format!("invalid address \"{addr}\": {e}"))?;
+        if len == 0 {
+            return Err("watch length must be at least 1".into());
+        }
+        Ok(Self { start, len, kind })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    next_id: u32,
+}
+
+impl Watchpoints {
+    /// Returns the new watchpoint's id.
+    pub fn add(&mut self, start: u32, len: u32, kind: WatchKind) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.list.push(Watchpoint { id, start, len: len.max(1), kind, enabled: true, hits: 0 });
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Watchpoint> {
+        let i = self.list.iter().position(|w| w.id == id)?;
+        Some(self.list.remove(i))
+    }
+
+    pub fn set_enabled(&mut self, id: u32, enabled: bool) -> bool {
+        self.get_mut(id).map(|w| w.enabled = enabled).is_some()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Watchpoint> {
+        self.list.iter().find(|w| w.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Watchpoint> {
+        self.list.iter_mut().find(|w| w.id == id)
+    }
+
+    /// In the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.list.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// Counts a `size`-byte access at `addr` against every enabled
+    /// watchpoint it matches; true if there was one.
+    pub fn check(&mut self, access: Access, addr: u32, size: u32, old: u32, new: u32) -> bool {
+        let mut hit = false;
+        for w in &mut self.list {
+            if w.enabled && w.overlaps(addr, size) && w.matches(access, addr, size, old, new) {
+                w.hits += 1;
+                hit = true;
+            }
+        }
+        hit
+    }
+
+    /// Like `check`, without counting.
+    pub fn matches(&self, access: Access, addr: u32, size: u32, old: u32, new: u32) -> bool {
+        self.list.iter().any(|w| w.enabled && w.overlaps(addr, size) && w.matches(access, addr, size, old, new))
+    }
+}
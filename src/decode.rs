@@ -0,0 +1,195 @@
+// src/decode.rs
+//
+// Predecoded RISC5 instructions. `Decoded::new` pulls the fields out of an
+// instruction word once; `Op` picks the handler in `Cpu::step`. The
+// `DecodeCache` keeps decoded words per address so loops skip both the bus
+// read and the decoding. Whoever owns the memory must call `invalidate` on
+// writes.
+
+use crate::bus::BusResult;
+
+/// Operand `c` of a `Decoded` that holds `imm` instead of a register.
+pub const IMM: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Cache slot that has not been decoded yet.
+    Empty,
+
+    Mov,
+    /// `MOV a, H`
+    MovH,
+    /// `MOV a, flags`
+    MovFlags,
+    Lsl,
+    Asr,
+    Ror,
+    And,
+    Ann,
+    Ior,
+    Xor,
+    Add,
+    /// `ADD'` (with carry in)
+    Addc,
+    Sub,
+    /// `SUB'` (with borrow in)
+    Subb,
+    Mul,
+    Mulu,
+    Div,
+    Divu,
+    Fad,
+    Fsb,
+    Fml,
+    Fdv,
+
+    Ldw,
+    Ldb,
+    Stw,
+    Stb,
+
+    /// Conditional branch; `a` is the condition nibble (IR[27:24]), the
+    /// target is register `c` or `pc + imm`.
+    Br,
+    /// Like `Br`, but saves the return address in R15.
+    BrLink,
+    /// STI/CLI/RTI.
+    IntCtl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+    pub op: Op,
+    pub a: u8,
+    pub b: u8,
+    /// Register number, or `IMM`.
+    pub c: u8,
+    /// Extended immediate, memory offset or branch offset in bytes.
+    pub imm: u32,
+    /// The raw instruction word.
+    pub ir: u32,
+}
+
+const PBIT: u32 = 0x8000_0000;
+const QBIT: u32 = 0x4000_0000;
+const UBIT: u32 = 0x2000_0000;
+const VBIT: u32 = 0x1000_0000;
+
+impl Decoded {
+    pub const EMPTY: Self = Self { op: Op::Empty, a: 0, b: 0, c: 0, imm: 0, ir: 0 };
+
+    pub fn new(ir: u32) -> Self {
+        let a = ((ir >> 24) & 0xF) as u8;
+        let b = ((ir >> 20) & 0xF) as u8;
+        let u = ir & UBIT != 0;
+        let v = ir & VBIT != 0;
+
+        if ir & PBIT == 0 {
+            // Register instructions
+            let q = ir & QBIT != 0;
+            let (c, mut imm) = match (q, v) {
+                (false, _) => ((ir & 0xF) as u8, 0),
+                (true, false) => (IMM, ir & 0xFFFF),
+                (true, true) => (IMM, 0xFFFF_0000 | (ir & 0xFFFF)),
+            };
+            let op = match (ir >> 16) & 0xF {
+                0 if !u => Op::Mov,
+                0 if q => {
+                    imm = (ir & 0xFFFF) << 16;
+                    Op::Mov
+                }
+                0 if v => Op::MovFlags,
+                0 => Op::MovH,
+                1 => Op::Lsl,
+                2 => Op::Asr,
+                3 => Op::Ror,
+                4 => Op::And,
+                5 => Op::Ann,
+                6 => Op::Ior,
+                7 => Op::Xor,
+                8 if u => Op::Addc,
+                8 => Op::Add,
+                9 if u => Op::Subb,
+                9 => Op::Sub,
+                10 if u => Op::Mulu,
+                10 => Op::Mul,
+                11 if u => Op::Divu,
+                11 => Op::Div,
+                12 => Op::Fad,
+                13 => Op::Fsb,
+                14 => Op::Fml,
+                _ => Op::Fdv,
+            };
+            Self { op, a, b, c, imm, ir }
+        } else if ir & QBIT == 0 {
+            // Memory instructions
+            let off = (((ir & 0x000F_FFFF) ^ 0x0008_0000) as i32 - 0x0008_0000) as u32;
+            let op = match (u, v) {
+                (false, false) => Op::Ldw,
+                (false, true) => Op::Ldb,
+                (true, false) => Op::Stw,
+                (true, true) => Op::Stb,
+            };
+            Self { op, a, b, c: 0, imm: off, ir }
+        } else {
+            // Branch instructions
+            if !u && !v && ir & 0x30 != 0 {
+                return Self { op: Op::IntCtl, a, b, c: 0, imm: 0, ir };
+            }
+            let (c, imm) = if u {
+                let off = ((ir & 0x00FF_FFFF) ^ 0x0080_0000) as i32 - 0x0080_0000;
+                (IMM, off.wrapping_mul(4) as u32)
+            } else {
+                ((ir & 0xF) as u8, 0)
+            };
+            let op = if v { Op::BrLink } else { Op::Br };
+            Self { op, a, b, c, imm, ir }
+        }
+    }
+}
+
+/// Decoded instructions for `[base, base + size)`, filled on first fetch.
+#[derive(Debug)]
+pub struct DecodeCache {
+    base: u32,
+    slots: Vec<Decoded>,
+}
+
+impl DecodeCache {
+    pub fn new(base: u32, size_bytes: u32) -> Self {
+        Self { base, slots: vec![Decoded::EMPTY; size_bytes.div_ceil(4) as usize] }
+    }
+
+    #[inline]
+    fn slot(&self, addr: u32) -> Option<usize> {
+        let i = (addr.wrapping_sub(self.base) >> 2) as usize;
+        (i < self.slots.len()).then_some(i)
+    }
+
+    /// Decoded word at `addr`; `read` supplies the word on a miss.
+    #[inline]
+    pub fn fetch(&mut self, addr: u32, read: impl FnOnce() -> BusResult<u32>) -> BusResult<Decoded> {
+        let Some(i) = self.slot(addr) else {
+            return read().map(Decoded::new);
+        };
+        let d = self.slots[i];
+        if d.op != Op::Empty {
+            return Ok(d);
+        }
+        let d = Decoded::new(read()?);
+        self.slots[i] = d;
+        Ok(d)
+    }
+
+    /// Forgets the word at `addr` after it was written.
+    #[inline]
+    pub fn invalidate(&mut self, addr: u32) {
+        if let Some(i) = self.slot(addr) {
+            self.slots[i] = Decoded::EMPTY;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.fill(Decoded::EMPTY);
+    }
+}
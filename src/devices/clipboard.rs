@@ -1,14 +1,79 @@
 use std::fmt;
-use arboard::Clipboard;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    bus::{BusError, BusResult},
+    devices::IoDevice,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State { Idle, Get, Put }
 
+/// Host side of the clipboard.
+pub trait ClipboardBackend {
+    fn get_text(&mut self) -> Option<String>;
+    fn set_text(&mut self, text: String);
+}
+
+/// The desktop clipboard (needs a display server on Linux).
+pub struct SystemClipboard {
+    cb: arboard::Clipboard,
+}
+
+impl SystemClipboard {
+    pub fn new() -> BusResult<Self> {
+        arboard::Clipboard::new()
+            .map(|cb| Self { cb })
+            .map_err(|e| BusError::Device(format!("system clipboard unavailable: {e}")))
+    }
+}
+
+impl ClipboardBackend for SystemClipboard {
+    fn get_text(&mut self) -> Option<String> {
+        self.cb.get_text().ok()
+    }
+
+    fn set_text(&mut self, text: String) {
+        let _ = self.cb.set_text(text);
+    }
+}
+
+/// Process-local clipboard for headless runs and tests; clones share the text.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryClipboard {
+    text: Arc<Mutex<String>>,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> String {
+        self.text.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, text: &str) {
+        *self.text.lock().unwrap() = text.to_string();
+    }
+}
+
+impl ClipboardBackend for MemoryClipboard {
+    fn get_text(&mut self) -> Option<String> {
+        Some(self.text())
+    }
+
+    fn set_text(&mut self, text: String) {
+        *self.text.lock().unwrap() = text;
+    }
+}
+
+/// Clipboard at IO offsets 40 (control: length) and 44 (data bytes).
 pub struct ClipboardDevice {
     state: State,
     data: Vec<u8>,
     ptr: usize,
-    cb: Clipboard,
+    backend: Box<dyn ClipboardBackend>,
 }
 impl fmt::Debug for ClipboardDevice {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -21,12 +86,21 @@ impl fmt::Debug for ClipboardDevice {
 }
 
 impl ClipboardDevice {
-    pub fn new() -> Self {
+    pub fn new(backend: Box<dyn ClipboardBackend>) -> Self {
         Self {
             state: State::Idle,
             data: Vec::new(),
             ptr: 0,
-            cb: Clipboard::new().expect("clipboard"),
+            backend,
+        }
+    }
+
+    /// System clipboard, or an in-memory one if there is no display server.
+    /// The error says why the fallback was taken.
+    pub fn system_or_memory() -> (Self, Option<BusError>) {
+        match SystemClipboard::new() {
+            Ok(cb) => (Self::new(Box::new(cb)), None),
+            Err(e) => (Self::new(Box::new(MemoryClipboard::new())), Some(e)),
         }
     }
 
@@ -39,17 +113,12 @@ impl ClipboardDevice {
     // IOStart+40 read: return length, and prepare GET
     pub fn read_control(&mut self) -> u32 {
         self.reset();
-        let Ok(txt) = self.cb.get_text() else { return 0; };
+        let Some(txt) = self.backend.get_text() else { return 0; };
         if txt.is_empty() { return 0; }
 
-        // Oberon forventer CR, og C-koden normaliserer CRLF
-        let bytes = txt.into_bytes();
-
-        // Hvis du vil lave samme CRLF-justering som C:
-        // C: mindsker reported length hvis CRLF
-        // Her: vi kan bare gøre det simpelt: send bytes som de er,
-        // og i read_data normaliserer vi '\n' -> '\r'.
-        self.data = bytes;
+        // Oberon forventer CR; CRLF tælles som ét tegn (som C-koden),
+        // read_data laver '\n' -> '\r'
+        self.data = txt.replace("\r\n", "\n").into_bytes();
         self.state = State::Get;
         self.data.len().min(u32::MAX as usize) as u32
     }
@@ -87,10 +156,28 @@ impl ClipboardDevice {
         self.ptr += 1;
 
         if self.ptr >= self.data.len() {
-            if let Ok(s) = String::from_utf8(self.data.clone()) {
-                let _ = self.cb.set_text(s);
-            }
+            let s = String::from_utf8_lossy(&self.data).into_owned();
+            self.backend.set_text(s);
             self.reset();
         }
     }
-}
\ No newline at end of file
+}
+
+impl IoDevice for ClipboardDevice {
+    fn read(&mut self, offset: u32) -> BusResult<u32> {
+        match offset {
+            40 => Ok(self.read_control()),
+            44 => Ok(self.read_data()),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, offset: u32, value: u32) -> BusResult<()> {
+        match offset {
+            40 => self.write_control(value),
+            44 => self.write_data(value),
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,101 @@
+// src/devices/disk.rs
+//
+// SDHC card in SPI mode, as seen by BootLoad.Mod and Kernel.Mod.
+//
+// Command frames are six bytes (0x40|cmd, arg[31:24] .. arg[7:0], crc7<<1|1);
+// the RISC5 sends them in 8-bit mode, so only the low byte of each transfer
+// counts. Data blocks move in 32-bit (fast) mode, 128 words per 512-byte
+// block, stored little-endian in the image like the C emulator does.
+//
+// A card powers up idle and must be initialised (CMD0, CMD8, ACMD41 with
+// HCS) before it accepts data commands. CRC is only checked on CMD0/CMD8
+// unless CMD59 turns it on.
+//
+// A read-only card sets TMP_WRITE_PROTECT in its CSD, answers data blocks
+// with "write error" and reports WP violation in CMD13 status.
+
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use crate::bus::{BusError, BusResult};
-use crate::devices::spi::SpiDevice;
+use crate::devices::overlay::Overlay;
+use crate::devices::spi::{SdCommand, SpiDevice};
+use crate::fs::image::FS_ONLY_OFFSET;
+use crate::fs::layout::DIR_MARK;
+use crate::savestate::{Reader, Writer};
+
+// R1 bits
+pub const R1_IDLE: u32 = 0x01;
+pub const R1_ILLEGAL_COMMAND: u32 = 0x04;
+pub const R1_CRC_ERROR: u32 = 0x08;
+pub const R1_PARAMETER_ERROR: u32 = 0x40;
+
+// data tokens and data response
+pub const TOKEN_START_BLOCK: u32 = 0xFE;
+pub const TOKEN_START_MULTI_WRITE: u32 = 0xFC;
+pub const TOKEN_STOP_TRAN: u32 = 0xFD;
+pub const DATA_ACCEPTED: u32 = 0x05;
+pub const DATA_CRC_ERROR: u32 = 0x0B;
+pub const DATA_WRITE_ERROR: u32 = 0x0D;
+
+// second byte of R2
+pub const R2_WP_VIOLATION: u32 = 0x20;
+
+/// Power up done, card capacity status (SDHC), 2.7-3.6 V.
+pub const OCR_READY: u32 = 0xC0FF_8000;
+const OCR_VOLTAGE: u32 = 0x00FF_8000;
+const HCS: u32 = 1 << 30;
+
+// ACMD41 answers "still idle" this many times before the card is ready
+const INIT_POLLS: u32 = 1;
+
+/// How writes from the guest reach the image file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DiskMode {
+    #[default]
+    ReadWrite,
+    /// The card is write-protected; the image is opened read-only.
+    ReadOnly,
+    /// Writes are kept in memory until committed or discarded.
+    Overlay,
+    /// Writes go to a delta file, which survives restarts.
+    Sidecar(PathBuf),
+}
+
+impl DiskMode {
+    /// Default delta file for `image`: `<image>.delta`.
+    pub fn sidecar_for(image: &Path) -> PathBuf {
+        let mut p = image.as_os_str().to_owned();
+        p.push(".delta");
+        PathBuf::from(p)
+    }
+}
+
+impl FromStr for DiskMode {
+    type Err = String;
 
-#[derive(Debug, Clone, Copy)]
+    /// `rw`, `ro`, `overlay` or `sidecar[:PATH]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rw" => Ok(DiskMode::ReadWrite),
+            "ro" => Ok(DiskMode::ReadOnly),
+            "overlay" => Ok(DiskMode::Overlay),
+            "sidecar" => Ok(DiskMode::Sidecar(PathBuf::new())),
+            _ => match s.strip_prefix("sidecar:") {
+                Some(p) if !p.is_empty() => Ok(DiskMode::Sidecar(PathBuf::from(p))),
+                _ => Err(format!("unknown disk mode \"{s}\" (rw, ro, overlay, sidecar[:PATH])")),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum DiskState {
     DiskCommand,
-    DiskRead,
-    DiskWrite,
-    DiskWriting,
+    DiskRead { multi: bool },
+    DiskWrite { multi: bool },
+    DiskWriting { multi: bool },
 }
 
 #[derive(Debug)]
@@ -17,88 +103,404 @@ pub struct Disk {
     state: DiskState,
     file: Option<File>,
     offset: u32,
+    overlay: Option<Overlay>,
+    write_protect: bool,
+
+    // card state
+    idle: bool,
+    if_cond: bool,
+    app_cmd: bool,
+    init_polls: u32,
+    crc_on: bool,
+    wp_violation: bool,
+    block: u32, // next block of a multi-block transfer
+
+    cmd_buf: [u8; 6],
+    cmd_idx: usize,
+    last_cmd: Option<SdCommand>,
 
-    rx_buf: [u32; 128],
+    rx_buf: [u32; 128 + 2],
     rx_idx: usize,
 
-    tx_buf: [u32; 128 + 2],
+    tx_buf: [u32; 2 + 128 + 2],
     tx_cnt: usize,
     tx_idx: i32, // C brugte -1 som “første read giver tx_buf[0]”
 }
 
 impl Disk {
     pub fn new(filename: Option<&Path>) -> BusResult<Self> {
-        let mut disk = Self {
+        match filename {
+            Some(path) => Self::with_mode(path, DiskMode::ReadWrite),
+            None => Ok(Self::empty()),
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
             state: DiskState::DiskCommand,
             file: None,
             offset: 0,
-            rx_buf: [0; 128],
+            overlay: None,
+            write_protect: false,
+            idle: true,
+            if_cond: false,
+            app_cmd: false,
+            init_polls: 0,
+            crc_on: false,
+            wp_violation: false,
+            block: 0,
+            cmd_buf: [0; 6],
+            cmd_idx: 0,
+            last_cmd: None,
+            rx_buf: [0; 130],
             rx_idx: 0,
-            tx_buf: [0; 130],
+            tx_buf: [0; 132],
             tx_cnt: 0,
             tx_idx: 0,
-        };
+        }
+    }
 
-        if let Some(path) = filename {
-            let mut f = File::options()
-                .read(true)
-                .write(true)
-                .open(path)
-                .map_err(|e| BusError::Device(format!(
-                    "Can't open file \"{}\": {}",
-                    path.display(),
-                    e
-                )))?;
+    /// Card backed by the image at `path`; `mode` decides where writes go.
+    /// A `Sidecar` with an empty path uses `DiskMode::sidecar_for(path)`.
+    pub fn with_mode(path: &Path, mode: DiskMode) -> BusResult<Self> {
+        let mut disk = Self::empty();
+        let mut f = File::options()
+            .read(true)
+            .write(mode == DiskMode::ReadWrite)
+            .open(path)
+            .map_err(|e| BusError::Device(format!(
+                "Can't open file \"{}\": {}",
+                path.display(),
+                e
+            )))?;
 
-            // Check for filesystem-only image, starting directly at sector 1 (DiskAdr 29)
-            let mut tmp = [0u32; 128];
-            read_sector(Some(&mut f), &mut tmp)?;
-            disk.offset = if tmp[0] == 0x9B1E_A38D { 0x80002 } else { 0 };
+        // Check for filesystem-only image, starting directly at sector 1 (DiskAdr 29)
+        let mut tmp = [0u32; 128];
+        read_sector(Some(&mut f), &mut tmp)?;
+        disk.offset = if tmp[0] == DIR_MARK { FS_ONLY_OFFSET } else { 0 };
 
-            // rewind ikke strengt nødvendigt; seek sker per command
-            disk.file = Some(f);
-        }
+        // rewind ikke strengt nødvendigt; seek sker per command
+        disk.file = Some(f);
 
+        match mode {
+            DiskMode::ReadWrite => {}
+            DiskMode::ReadOnly => disk.write_protect = true,
+            DiskMode::Overlay => disk.overlay = Some(Overlay::memory(path)),
+            DiskMode::Sidecar(delta) => {
+                let delta = if delta.as_os_str().is_empty() { DiskMode::sidecar_for(path) } else { delta };
+                disk.overlay = Some(Overlay::sidecar(path, &delta)?);
+            }
+        }
         Ok(disk)
     }
 
-    fn run_command(&mut self) -> BusResult<()> {
-        let cmd = self.rx_buf[0];
+    /// Pending writes, for `Overlay` and `Sidecar` disks.
+    pub fn overlay(&self) -> Option<Overlay> {
+        self.overlay.clone()
+    }
 
-        let arg = (self.rx_buf[1] << 24)
-            | (self.rx_buf[2] << 16)
-            | (self.rx_buf[3] << 8)
-            | (self.rx_buf[4] << 0);
+    pub fn is_write_protected(&self) -> bool {
+        self.write_protect
+    }
 
-        match cmd {
-            81 => {
-                // READ
-                self.state = DiskState::DiskRead;
-                self.tx_buf[0] = 0;
-                self.tx_buf[1] = 254;
+    /// SD block number of the first block in the image file.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
 
-                let sec = arg.wrapping_sub(self.offset);
-                seek_sector(self.file.as_mut(), sec)?;
-                read_sector(self.file.as_mut(), (&mut self.tx_buf[2..2 + 128]).try_into().unwrap())?;
+    /// True until ACMD41 has completed initialisation.
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
 
-                self.tx_cnt = 2 + 128;
+    /// Puts back the card state from a save state (`SpiDevice::save_state`):
+    /// transfer state: u8 (0 command, 1 read, 2 write, 3 writing), multi
+    /// block, idle, CMD8 seen, CMD55 seen: bool; ACMD41 polls: u32; CRC on,
+    /// WP violation: bool; next block: u32; command bytes: [u8; 6] and
+    /// count: u8; receive buffer: [u32; 130] and index: u32; transmit
+    /// buffer: [u32; 132], count and index: u32.
+    pub fn load_state(&mut self, state: &[u8]) -> BusResult<()> {
+        let mut r = Reader::new(state, "disk state");
+        let (tag, multi) = (r.u8()?, r.bool()?);
+        self.state = match tag {
+            0 => DiskState::DiskCommand,
+            1 => DiskState::DiskRead { multi },
+            2 => DiskState::DiskWrite { multi },
+            3 => DiskState::DiskWriting { multi },
+            _ => return Err(BusError::Device(format!("unknown disk transfer state {tag}"))),
+        };
+        self.idle = r.bool()?;
+        self.if_cond = r.bool()?;
+        self.app_cmd = r.bool()?;
+        self.init_polls = r.u32()?;
+        self.crc_on = r.bool()?;
+        self.wp_violation = r.bool()?;
+        self.block = r.u32()?;
+        self.cmd_buf = r.array()?;
+        self.cmd_idx = r.u8()? as usize;
+        for w in &mut self.rx_buf {
+            *w = r.u32()?;
+        }
+        self.rx_idx = r.u32()? as usize;
+        for w in &mut self.tx_buf {
+            *w = r.u32()?;
+        }
+        self.tx_cnt = r.u32()? as usize;
+        self.tx_idx = r.u32()? as i32;
+        if self.cmd_idx >= self.cmd_buf.len() || self.rx_idx >= self.rx_buf.len() || self.tx_cnt > self.tx_buf.len() {
+            return Err(BusError::Device("disk state out of range".into()));
+        }
+        Ok(())
+    }
+
+    fn r1(&self, flags: u32) -> u32 {
+        flags | self.idle as u32
+    }
+
+    fn respond(&mut self, bytes: &[u32]) {
+        self.tx_buf[..bytes.len()].copy_from_slice(bytes);
+        self.tx_cnt = bytes.len();
+        self.tx_idx = -1;
+    }
+
+    /// Image block for SD block `arg`, or None if it lies before the image
+    /// (reported as a parameter error).
+    fn sector(&self, arg: u32) -> Option<u32> {
+        arg.checked_sub(self.offset)
+    }
+
+    fn command_byte(&mut self, value: u32) -> BusResult<()> {
+        let b = value as u8;
+        // a frame starts with 01xxxxxx; anything else between frames is idle clocking
+        if self.cmd_idx == 0 && b & 0xC0 != 0x40 {
+            return Ok(());
+        }
+        self.cmd_buf[self.cmd_idx] = b;
+        self.cmd_idx += 1;
+        if self.cmd_idx == 6 {
+            self.cmd_idx = 0;
+            self.run_command()?;
+        }
+        Ok(())
+    }
+
+    fn run_command(&mut self) -> BusResult<()> {
+        let cmd = (self.cmd_buf[0] & 0x3F) as u32;
+        let arg = u32::from_be_bytes(self.cmd_buf[1..5].try_into().unwrap());
+        let app = std::mem::take(&mut self.app_cmd);
+        let sector = if matches!(cmd, 17 | 18 | 24 | 25) { self.sector(arg) } else { None };
+        self.last_cmd = Some(SdCommand { cmd: cmd as u8, app, arg, sector });
+
+        if (self.crc_on || cmd == 0 || cmd == 8) && self.cmd_buf[5] != crc7(&self.cmd_buf[..5]) << 1 | 1 {
+            self.respond(&[self.r1(R1_CRC_ERROR)]);
+            return Ok(());
+        }
+        // in idle state only the initialisation commands are accepted
+        let init_cmd = matches!((app, cmd), (_, 0 | 1 | 8 | 55 | 58 | 59) | (true, 41));
+        if self.idle && !init_cmd {
+            self.respond(&[self.r1(R1_ILLEGAL_COMMAND)]);
+            return Ok(());
+        }
+
+        match (app, cmd) {
+            (_, 0) => {
+                // GO_IDLE_STATE
+                self.idle = true;
+                self.if_cond = false;
+                self.init_polls = 0;
+                self.crc_on = false;
+                self.state = DiskState::DiskCommand;
+                self.respond(&[R1_IDLE]);
             }
-            88 => {
-                // WRITE
-                self.state = DiskState::DiskWrite;
-                let sec = arg.wrapping_sub(self.offset);
-                seek_sector(self.file.as_mut(), sec)?;
-                self.tx_buf[0] = 0;
-                self.tx_cnt = 1;
+            (_, 8) => {
+                // SEND_IF_COND: R7 echoes voltage and check pattern
+                if arg >> 8 & 0xF == 1 {
+                    self.if_cond = true;
+                    self.respond(&[self.r1(0), 0, 0, 1, arg & 0xFF]);
+                } else {
+                    self.respond(&[self.r1(R1_PARAMETER_ERROR), 0, 0, 0, arg & 0xFF]);
+                }
+            }
+            (_, 1) | (true, 41) => {
+                // SEND_OP_COND: an SDHC card stays busy for hosts without CMD8/HCS
+                if self.idle && self.if_cond && arg & HCS != 0 {
+                    if self.init_polls >= INIT_POLLS {
+                        self.idle = false;
+                    }
+                    self.init_polls += 1;
+                }
+                self.respond(&[self.r1(0)]);
+            }
+            (_, 55) => {
+                // APP_CMD
+                self.app_cmd = true;
+                self.respond(&[self.r1(0)]);
+            }
+            (_, 58) => {
+                // READ_OCR
+                let ocr = if self.idle { OCR_VOLTAGE } else { OCR_READY };
+                let [a, b, c, d] = ocr.to_be_bytes();
+                self.respond(&[self.r1(0), a as u32, b as u32, c as u32, d as u32]);
+            }
+            (_, 59) => {
+                // CRC_ON_OFF
+                self.crc_on = arg & 1 != 0;
+                self.respond(&[self.r1(0)]);
+            }
+            (_, 9) => {
+                // SEND_CSD
+                let csd = self.csd()?;
+                self.respond_register(&csd);
+            }
+            (_, 10) => {
+                // SEND_CID: OEM "OB", product "RISC5", rev 1.0, serial 1, 2024-01
+                let mut cid = [0x00, b'O', b'B', b'R', b'I', b'S', b'C', b'5', 0x10, 0, 0, 0, 1, 0x01, 0x81, 0];
+                cid[15] = crc7(&cid[..15]) << 1 | 1;
+                self.respond_register(&cid);
+            }
+            (_, 12) => {
+                // STOP_TRANSMISSION: stuff byte, R1, one busy byte
+                self.state = DiskState::DiskCommand;
+                self.respond(&[0xFF, 0, 0]);
+            }
+            (_, 13) => {
+                // SEND_STATUS: R2; error bits clear when read
+                let wp = if std::mem::take(&mut self.wp_violation) { R2_WP_VIOLATION } else { 0 };
+                self.respond(&[0, wp]);
             }
-            _ => {
-                // default ack
+            (_, 16) => {
+                // SET_BLOCKLEN: fixed at 512 on SDHC
+                let flags = if arg == 512 { 0 } else { R1_PARAMETER_ERROR };
+                self.respond(&[flags]);
+            }
+            (_, 17 | 18) => {
+                // READ_SINGLE_BLOCK / READ_MULTIPLE_BLOCK
+                let Some(sec) = self.sector(arg) else {
+                    self.respond(&[R1_PARAMETER_ERROR]);
+                    return Ok(());
+                };
+                self.block = sec;
                 self.tx_buf[0] = 0;
-                self.tx_cnt = 1;
+                self.load_block(1)?;
+                self.tx_idx = -1;
+                self.state = DiskState::DiskRead { multi: cmd == 18 };
+            }
+            (_, 24 | 25) => {
+                // WRITE_BLOCK / WRITE_MULTIPLE_BLOCK
+                let Some(sec) = self.sector(arg) else {
+                    self.respond(&[R1_PARAMETER_ERROR]);
+                    return Ok(());
+                };
+                self.block = sec;
+                self.rx_idx = 0;
+                self.state = DiskState::DiskWrite { multi: cmd == 25 };
+                self.respond(&[0]);
+            }
+            (true, 23) => {
+                // SET_WR_BLK_ERASE_COUNT: only a hint
+                self.respond(&[0]);
             }
+            _ => self.respond(&[self.r1(R1_ILLEGAL_COMMAND)]),
         }
+        Ok(())
+    }
 
-        self.tx_idx = -1;
+    /// Puts block `self.block` as a data packet at `tx_buf[start..]`.
+    fn load_block(&mut self, start: usize) -> BusResult<()> {
+        let mut words = [0u32; 128];
+        self.read_block(self.block, &mut words)?;
+        let crc = crc16(&words_to_bytes(&words));
+
+        self.tx_buf[start] = TOKEN_START_BLOCK;
+        self.tx_buf[start + 1..start + 129].copy_from_slice(&words);
+        self.tx_buf[start + 129] = (crc >> 8) as u32;
+        self.tx_buf[start + 130] = (crc & 0xFF) as u32;
+        self.tx_cnt = start + 131;
+        self.block = self.block.wrapping_add(1);
+        Ok(())
+    }
+
+    fn read_block(&mut self, block: u32, words: &mut [u32; 128]) -> BusResult<()> {
+        if let Some(bytes) = self.overlay.as_ref().and_then(|o| o.read(block)) {
+            *words = bytes_to_words(&bytes);
+            return Ok(());
+        }
+        seek_sector(self.file.as_mut(), block)?;
+        read_sector(self.file.as_mut(), words)
+    }
+
+    fn write_block(&mut self, block: u32, words: &[u32; 128]) -> BusResult<()> {
+        if let Some(o) = &self.overlay {
+            return o.write(block, &words_to_bytes(words));
+        }
+        seek_sector(self.file.as_mut(), block)?;
+        write_sector(self.file.as_mut(), words)
+    }
+
+    /// R1 followed by a 16-byte register as a data packet.
+    fn respond_register(&mut self, reg: &[u8; 16]) {
+        let crc = crc16(reg);
+        let mut out = [0u32; 20];
+        out[0] = 0;
+        out[1] = TOKEN_START_BLOCK;
+        for (o, &b) in out[2..18].iter_mut().zip(reg) {
+            *o = b as u32;
+        }
+        out[18] = (crc >> 8) as u32;
+        out[19] = (crc & 0xFF) as u32;
+        self.respond(&out);
+    }
+
+    /// CSD version 2.0; the capacity covers the image.
+    fn csd(&mut self) -> BusResult<[u8; 16]> {
+        let len = match &self.file {
+            Some(f) => f.metadata().map_err(|e| BusError::Device(format!("stat failed: {e}")))?.len(),
+            None => 0,
+        };
+        let blocks = self.offset as u64 + len.div_ceil(512);
+        // capacity = (C_SIZE + 1) * 512 KiB
+        let c_size = blocks.div_ceil(1024).saturating_sub(1).min(0x3F_FFFF) as u32;
+        let mut csd = [
+            0x40, 0x0E, 0x00, 0x32, 0x5B, 0x59, 0x00,
+            (c_size >> 16) as u8 & 0x3F, (c_size >> 8) as u8, c_size as u8,
+            0x7F, 0x80, 0x0A, 0x40, 0x00, 0x00,
+        ];
+        if self.write_protect {
+            csd[14] |= 0x10; // TMP_WRITE_PROTECT
+        }
+        csd[15] = crc7(&csd[..15]) << 1 | 1;
+        Ok(csd)
+    }
+
+    fn data_word(&mut self, value: u32, multi: bool) -> BusResult<()> {
+        self.rx_buf[self.rx_idx] = value;
+        self.rx_idx += 1;
+        if self.rx_idx < 130 {
+            return Ok(());
+        }
+        self.rx_idx = 0;
+
+        let words: [u32; 128] = self.rx_buf[..128].try_into().unwrap();
+        let crc = (self.rx_buf[128] & 0xFF) << 8 | (self.rx_buf[129] & 0xFF);
+        let status = if self.crc_on && crc != crc16(&words_to_bytes(&words)) as u32 {
+            DATA_CRC_ERROR
+        } else if self.write_protect {
+            self.wp_violation = true;
+            DATA_WRITE_ERROR
+        } else {
+            // skriv 512 bytes (128 words)
+            self.write_block(self.block, &words)?;
+            self.block = self.block.wrapping_add(1);
+            DATA_ACCEPTED
+        };
+
+        // data response, then one busy byte
+        self.respond(&[status, 0]);
+        self.state = if multi && status == DATA_ACCEPTED {
+            DiskState::DiskWrite { multi: true }
+        } else {
+            DiskState::DiskCommand
+        };
         Ok(())
     }
 }
@@ -108,58 +510,87 @@ impl SpiDevice for Disk {
         self.tx_idx += 1;
 
         match self.state {
-            DiskState::DiskCommand => {
-                // Ignorer 0xFF “idle bytes” medmindre vi allerede er i gang med en kommando
-                if (value as u8) != 0xFF || self.rx_idx != 0 {
-                    self.rx_buf[self.rx_idx] = value;
-                    self.rx_idx += 1;
-                    if self.rx_idx == 6 {
-                        self.run_command()?;
-                        self.rx_idx = 0;
+            DiskState::DiskCommand => self.command_byte(value)?,
+
+            DiskState::DiskRead { multi } => {
+                if multi {
+                    // CMD12 arrives while data is still flowing
+                    self.command_byte(value)?;
+                    if self.state != (DiskState::DiskRead { multi: true }) {
+                        return Ok(());
                     }
                 }
-            }
-
-            DiskState::DiskRead => {
                 if self.tx_idx >= self.tx_cnt as i32 {
-                    self.state = DiskState::DiskCommand;
-                    self.tx_cnt = 0;
-                    self.tx_idx = 0;
+                    if multi {
+                        // one gap byte, then the next block
+                        self.tx_buf[0] = 0xFF;
+                        self.load_block(1)?;
+                        self.tx_idx = 0;
+                    } else {
+                        self.state = DiskState::DiskCommand;
+                        self.tx_cnt = 0;
+                        self.tx_idx = 0;
+                        self.command_byte(value)?;
+                    }
                 }
             }
 
-            DiskState::DiskWrite => {
-                // vent på data token 0xFE
-                if value == 254 {
-                    self.state = DiskState::DiskWriting;
+            DiskState::DiskWrite { multi } => {
+                // vent på data token
+                match (value & 0xFF, multi) {
+                    (TOKEN_START_BLOCK, false) | (TOKEN_START_MULTI_WRITE, true) => {
+                        self.rx_idx = 0;
+                        self.state = DiskState::DiskWriting { multi };
+                    }
+                    (TOKEN_STOP_TRAN, true) => {
+                        self.state = DiskState::DiskCommand;
+                        self.respond(&[0xFF, 0]);
+                    }
+                    _ => {}
                 }
             }
 
-            DiskState::DiskWriting => {
-                if self.rx_idx < 128 {
-                    self.rx_buf[self.rx_idx] = value;
-                }
-                self.rx_idx += 1;
-
-                if self.rx_idx == 128 {
-                    // skriv 512 bytes (128 words)
-                    write_sector(self.file.as_mut(), &self.rx_buf)?;
-                }
-
-                if self.rx_idx == 130 {
-                    // done: send status byte 5 (som C)
-                    self.tx_buf[0] = 5;
-                    self.tx_cnt = 1;
-                    self.tx_idx = -1;
-                    self.rx_idx = 0;
-                    self.state = DiskState::DiskCommand;
-                }
-            }
+            DiskState::DiskWriting { multi } => self.data_word(value, multi)?,
         }
 
         Ok(())
     }
 
+    fn take_command(&mut self) -> Option<SdCommand> {
+        self.last_cmd.take()
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = Writer::default();
+        let (tag, multi) = match self.state {
+            DiskState::DiskCommand => (0, false),
+            DiskState::DiskRead { multi } => (1, multi),
+            DiskState::DiskWrite { multi } => (2, multi),
+            DiskState::DiskWriting { multi } => (3, multi),
+        };
+        w.u8(tag);
+        w.bool(multi);
+        w.bool(self.idle);
+        w.bool(self.if_cond);
+        w.bool(self.app_cmd);
+        w.u32(self.init_polls);
+        w.bool(self.crc_on);
+        w.bool(self.wp_violation);
+        w.u32(self.block);
+        w.bytes(&self.cmd_buf);
+        w.u8(self.cmd_idx as u8);
+        for &v in &self.rx_buf {
+            w.u32(v);
+        }
+        w.u32(self.rx_idx as u32);
+        for &v in &self.tx_buf {
+            w.u32(v);
+        }
+        w.u32(self.tx_cnt as u32);
+        w.u32(self.tx_idx as u32);
+        Some(w.into_bytes())
+    }
+
     fn read_data(&mut self) -> BusResult<u32> {
         if self.tx_idx >= 0 && (self.tx_idx as usize) < self.tx_cnt {
             Ok(self.tx_buf[self.tx_idx as usize])
@@ -169,8 +600,47 @@ impl SpiDevice for Disk {
     }
 }
 
+/// CRC7 of a command frame or register (polynomial x^7 + x^3 + 1).
+pub fn crc7(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        for bit in (0..8).rev() {
+            let top = (crc >> 6) & 1;
+            crc = (crc << 1) & 0x7F;
+            if top ^ (byte >> bit) & 1 != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// CRC16-CCITT of a data block, as sent after it.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in bytes {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
 // --- helpers (1:1 med C) ---
 
+fn words_to_bytes(buf: &[u32; 128]) -> [u8; 512] {
+    let mut bytes = [0u8; 512];
+    for (chunk, w) in bytes.chunks_exact_mut(4).zip(buf) {
+        chunk.copy_from_slice(&w.to_le_bytes());
+    }
+    bytes
+}
+
+fn bytes_to_words(bytes: &[u8; 512]) -> [u32; 128] {
+    std::array::from_fn(|i| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()))
+}
+
 fn seek_sector(file: Option<&mut File>, secnum: u32) -> BusResult<()> {
     if let Some(f) = file {
         f.seek(SeekFrom::Start(secnum as u64 * 512))
@@ -179,11 +649,19 @@ fn seek_sector(file: Option<&mut File>, secnum: u32) -> BusResult<()> {
     Ok(())
 }
 
+/// Blocks past the end of the image read as zeros, like an erased card.
 fn read_sector(file: Option<&mut File>, buf: &mut [u32; 128]) -> BusResult<()> {
     let mut bytes = [0u8; 512];
     if let Some(f) = file {
-        f.read_exact(&mut bytes)
-            .map_err(|e| BusError::Device(format!("read failed: {e}")))?;
+        let mut got = 0;
+        while got < bytes.len() {
+            match f.read(&mut bytes[got..]) {
+                Ok(0) => break,
+                Ok(n) => got += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(BusError::Device(format!("read failed: {e}"))),
+            }
+        }
     }
     for i in 0..128 {
         buf[i] = (bytes[i * 4 + 0] as u32)
@@ -196,15 +674,7 @@ fn read_sector(file: Option<&mut File>, buf: &mut [u32; 128]) -> BusResult<()> {
 
 fn write_sector(file: Option<&mut File>, buf: &[u32; 128]) -> BusResult<()> {
     if let Some(f) = file {
-        let mut bytes = [0u8; 512];
-        for i in 0..128 {
-            let w = buf[i];
-            bytes[i * 4 + 0] = (w & 0xFF) as u8;
-            bytes[i * 4 + 1] = ((w >> 8) & 0xFF) as u8;
-            bytes[i * 4 + 2] = ((w >> 16) & 0xFF) as u8;
-            bytes[i * 4 + 3] = ((w >> 24) & 0xFF) as u8;
-        }
-        f.write_all(&bytes)
+        f.write_all(&words_to_bytes(buf))
             .map_err(|e| BusError::Device(format!("write failed: {e}")))?;
     }
     Ok(())
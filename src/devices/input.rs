@@ -1,5 +1,6 @@
 use crate::{bus::BusResult, devices::IoDevice};
 use crate::bus::BusError;
+use crate::savestate::{Reader, Writer};
 
 #[derive(Debug, Default)]
 pub struct Input {
@@ -35,6 +36,23 @@ impl Input {
         Ok(())
     }
 
+    pub(crate) fn save_state(&self, w: &mut Writer) {
+        w.u32(self.mouse);
+        w.u8(self.key_cnt as u8);
+        w.bytes(&self.key_buf);
+    }
+
+    pub(crate) fn load_state(r: &mut Reader) -> BusResult<Self> {
+        let mouse = r.u32()?;
+        let key_cnt = (r.u8()? as usize).min(16);
+        let key_buf = r.array()?;
+        Ok(Self { mouse, key_buf, key_cnt })
+    }
+
+    pub fn key_pending(&self) -> bool {
+        self.key_cnt > 0
+    }
+
     fn read_mouse_and_kb_status(&self) -> u32 {
         let mut m = self.mouse;
         if self.key_cnt > 0 {
@@ -0,0 +1,26 @@
+use crate::{bus::BusResult, devices::IoDevice};
+use crate::devices::panel::FrontPanel;
+
+/// The 8 LEDs at IO offset 4 (write).
+#[derive(Debug, Default)]
+pub struct Leds {
+    pub panel: FrontPanel,
+}
+
+impl Leds {
+    pub fn new(panel: FrontPanel) -> Self {
+        Self { panel }
+    }
+}
+
+impl IoDevice for Leds {
+    fn read(&mut self, _offset: u32) -> BusResult<u32> {
+        Ok(0)
+    }
+    fn write(&mut self, offset: u32, value: u32) -> BusResult<()> {
+        if offset == 4 {
+            self.panel.write_leds(value as u8);
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,22 @@
 pub mod timer;
 pub mod switches;
+pub mod leds;
+pub mod panel;
 pub mod input;
 pub mod spi;
 pub mod disk;
+pub mod overlay;
 pub mod clipboard;
+pub mod serial;
 
 use crate::bus::BusResult;
 
 pub trait IoDevice: std::fmt::Debug {
     fn read(&mut self, offset: u32) -> BusResult<u32>;
     fn write(&mut self, offset: u32, value: u32) -> BusResult<()>;
+
+    /// Level-triggered interrupt request; the device clears it when serviced.
+    fn irq_pending(&self) -> bool {
+        false
+    }
 }
\ No newline at end of file
@@ -0,0 +1,162 @@
+// src/devices/overlay.rs
+//
+// Copy-on-write layer over a disk image. Written blocks are kept in memory
+// and, for a sidecar overlay, also appended to a delta file:
+//
+//   "RISCDLT1" { block: u32 LE, data: [u8; 512] }
+//
+// Later records win when the delta is loaded again. `commit` copies the
+// blocks into the image, `discard` drops them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::bus::{BusError, BusResult};
+
+const MAGIC: &[u8; 8] = b"RISCDLT1";
+
+struct OverlayState {
+    image: PathBuf,
+    blocks: BTreeMap<u32, [u8; 512]>,
+    sidecar: Option<(PathBuf, File)>,
+}
+
+/// Pending writes to a disk image. Clones share state, so the host can
+/// commit or discard while the disk sits on the SPI bus.
+#[derive(Clone)]
+pub struct Overlay {
+    inner: Arc<Mutex<OverlayState>>,
+}
+
+impl fmt::Debug for Overlay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.inner.lock().unwrap();
+        f.debug_struct("Overlay")
+            .field("image", &s.image)
+            .field("blocks", &s.blocks.len())
+            .field("sidecar", &s.sidecar.as_ref().map(|(p, _)| p))
+            .finish()
+    }
+}
+
+fn io_err(what: &str, path: &Path, e: std::io::Error) -> BusError {
+    BusError::Device(format!("{what} \"{}\": {e}", path.display()))
+}
+
+impl Overlay {
+    /// Overlay that lives only as long as the process.
+    pub fn memory(image: &Path) -> Self {
+        Self::from_state(OverlayState { image: image.to_path_buf(), blocks: BTreeMap::new(), sidecar: None })
+    }
+
+    /// Overlay backed by the delta file `delta`; an existing delta is loaded,
+    /// so a session can be resumed.
+    pub fn sidecar(image: &Path, delta: &Path) -> BusResult<Self> {
+        let mut f = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(delta)
+            .map_err(|e| io_err("Can't open delta", delta, e))?;
+
+        let mut bytes = Vec::new();
+        f.read_to_end(&mut bytes).map_err(|e| io_err("Can't read delta", delta, e))?;
+        let mut blocks = BTreeMap::new();
+        if bytes.is_empty() {
+            f.write_all(MAGIC).map_err(|e| io_err("Can't write delta", delta, e))?;
+        } else {
+            let body = bytes
+                .strip_prefix(MAGIC)
+                .ok_or_else(|| BusError::Device(format!("\"{}\" is not a disk delta file", delta.display())))?;
+            // a torn last record (crash mid-write) is ignored
+            for rec in body.chunks_exact(4 + 512) {
+                let block = u32::from_le_bytes(rec[..4].try_into().unwrap());
+                blocks.insert(block, rec[4..].try_into().unwrap());
+            }
+        }
+
+        Ok(Self::from_state(OverlayState {
+            image: image.to_path_buf(),
+            blocks,
+            sidecar: Some((delta.to_path_buf(), f)),
+        }))
+    }
+
+    fn from_state(state: OverlayState) -> Self {
+        Self { inner: Arc::new(Mutex::new(state)) }
+    }
+
+    pub fn image(&self) -> PathBuf {
+        self.inner.lock().unwrap().image.clone()
+    }
+
+    pub fn sidecar_path(&self) -> Option<PathBuf> {
+        self.inner.lock().unwrap().sidecar.as_ref().map(|(p, _)| p.clone())
+    }
+
+    /// Number of blocks that differ from the image.
+    pub fn dirty_blocks(&self) -> usize {
+        self.inner.lock().unwrap().blocks.len()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_blocks() > 0
+    }
+
+    /// Pending blocks in block order.
+    pub(crate) fn blocks(&self) -> Vec<(u32, [u8; 512])> {
+        self.inner.lock().unwrap().blocks.iter().map(|(&b, d)| (b, *d)).collect()
+    }
+
+    pub(crate) fn read(&self, block: u32) -> Option<[u8; 512]> {
+        self.inner.lock().unwrap().blocks.get(&block).copied()
+    }
+
+    pub(crate) fn write(&self, block: u32, data: &[u8; 512]) -> BusResult<()> {
+        let mut s = self.inner.lock().unwrap();
+        if let Some((path, f)) = &mut s.sidecar {
+            f.seek(SeekFrom::End(0))
+                .and_then(|_| f.write_all(&block.to_le_bytes()))
+                .and_then(|_| f.write_all(data))
+                .map_err(|e| io_err("Can't write delta", path, e))?;
+        }
+        s.blocks.insert(block, *data);
+        Ok(())
+    }
+
+    /// Writes all pending blocks into the image and empties the overlay.
+    pub fn commit(&self) -> BusResult<()> {
+        let mut s = self.inner.lock().unwrap();
+        let mut f = File::options()
+            .write(true)
+            .open(&s.image)
+            .map_err(|e| io_err("Can't open file", &s.image, e))?;
+        for (&block, data) in &s.blocks {
+            f.seek(SeekFrom::Start(block as u64 * 512))
+                .and_then(|_| f.write_all(data))
+                .map_err(|e| io_err("Can't write", &s.image, e))?;
+        }
+        f.sync_all().map_err(|e| io_err("Can't write", &s.image, e))?;
+        s.blocks.clear();
+        reset_sidecar(&mut s)
+    }
+
+    /// Drops all pending blocks; the image is left as it was.
+    pub fn discard(&self) -> BusResult<()> {
+        let mut s = self.inner.lock().unwrap();
+        s.blocks.clear();
+        reset_sidecar(&mut s)
+    }
+}
+
+fn reset_sidecar(s: &mut OverlayState) -> BusResult<()> {
+    if let Some((path, f)) = &mut s.sidecar {
+        f.set_len(MAGIC.len() as u64).map_err(|e| io_err("Can't write delta", path, e))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::bus::{BusError, BusResult};
+
+/// LED changes kept for `led_history`.
+pub const HISTORY_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedChange {
+    /// Number of LED writes by the guest before this one.
+    pub write: u64,
+    pub value: u8,
+}
+
+type Listener = Box<dyn FnMut(u8) + Send>;
+
+#[derive(Default)]
+struct PanelState {
+    leds: u8,
+    switches: u32,
+    writes: u64,
+    history: VecDeque<LedChange>,
+    listeners: Vec<Listener>,
+}
+
+/// Board front panel: the 8 LEDs (IO offset 4 write) and the switch bank
+/// (IO offset 4 read). Clones share state, so the host can watch and flip
+/// them while the devices sit in the `IoBus`.
+#[derive(Clone, Default)]
+pub struct FrontPanel {
+    inner: Arc<Mutex<PanelState>>,
+}
+
+impl fmt::Debug for FrontPanel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.inner.lock().unwrap();
+        f.debug_struct("FrontPanel")
+            .field("leds", &s.leds)
+            .field("switches", &s.switches)
+            .finish()
+    }
+}
+
+impl FrontPanel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn leds(&self) -> u8 {
+        self.inner.lock().unwrap().leds
+    }
+
+    pub fn switches(&self) -> u32 {
+        self.inner.lock().unwrap().switches
+    }
+
+    pub fn set_switches(&self, value: u32) {
+        self.inner.lock().unwrap().switches = value;
+    }
+
+    /// Flips switch `bit` (0..32).
+    pub fn set_switch(&self, bit: u32, on: bool) -> BusResult<()> {
+        let mask = 1u32
+            .checked_shl(bit)
+            .ok_or_else(|| BusError::Device(format!("no switch {bit} (0..31)")))?;
+        let mut s = self.inner.lock().unwrap();
+        if on { s.switches |= mask; } else { s.switches &= !mask; }
+        Ok(())
+    }
+
+    /// Last `HISTORY_LEN` LED changes, oldest first.
+    pub fn led_history(&self) -> Vec<LedChange> {
+        self.inner.lock().unwrap().history.iter().copied().collect()
+    }
+
+    pub fn clear_led_history(&self) {
+        self.inner.lock().unwrap().history.clear();
+    }
+
+    /// Calls `f` with the new value whenever the guest changes the LEDs.
+    pub fn on_leds_changed(&self, f: impl FnMut(u8) + Send + 'static) {
+        self.inner.lock().unwrap().listeners.push(Box::new(f));
+    }
+
+    pub(crate) fn write_leds(&self, value: u8) {
+        let mut listeners = {
+            let mut s = self.inner.lock().unwrap();
+            let write = s.writes;
+            s.writes += 1;
+            if s.leds == value {
+                return;
+            }
+            s.leds = value;
+            if s.history.len() == HISTORY_LEN {
+                s.history.pop_front();
+            }
+            s.history.push_back(LedChange { write, value });
+            std::mem::take(&mut s.listeners)
+        };
+        // unlocked, so listeners may use the panel themselves
+        for f in &mut listeners {
+            f(value);
+        }
+        let mut s = self.inner.lock().unwrap();
+        listeners.append(&mut s.listeners);
+        s.listeners = listeners;
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::{
+    bus::{BusError, BusResult},
+    devices::IoDevice,
+};
+
+// Project Oberon RS-232 status bits (IO offset 12)
+pub const STATUS_RX_READY: u32 = 1;
+pub const STATUS_TX_READY: u32 = 2;
+
+/// Guest output kept while a pty or socket peer is not reading; more is
+/// dropped.
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Host end of the serial line.
+pub trait SerialBackend: fmt::Debug + Send {
+    /// Next byte from the host, if one is waiting. Must not block.
+    fn try_recv(&mut self) -> Option<u8>;
+    /// Byte sent by the guest.
+    fn send(&mut self, byte: u8) -> BusResult<()>;
+}
+
+/// RS-232 device: data at IO offset 8, status at offset 12.
+#[derive(Debug)]
+pub struct Serial {
+    backend: Box<dyn SerialBackend>,
+    rx: Option<u8>,
+}
+
+impl Serial {
+    pub fn new(backend: Box<dyn SerialBackend>) -> Self {
+        Self { backend, rx: None }
+    }
+
+    fn poll(&mut self) {
+        if self.rx.is_none() {
+            self.rx = self.backend.try_recv();
+        }
+    }
+}
+
+impl IoDevice for Serial {
+    fn read(&mut self, offset: u32) -> BusResult<u32> {
+        self.poll();
+        match offset {
+            8 => Ok(self.rx.take().unwrap_or(0) as u32),
+            12 => Ok(STATUS_TX_READY | if self.rx.is_some() { STATUS_RX_READY } else { 0 }),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, offset: u32, value: u32) -> BusResult<()> {
+        if offset == 8 {
+            self.backend.send(value as u8)?;
+        }
+        Ok(())
+    }
+}
+
+/// In-memory line for host code running in the same process (PCLink, tests).
+/// `backend()` gives the guest end; the pipe itself is the host end.
+#[derive(Debug, Clone, Default)]
+pub struct SerialPipe {
+    to_guest: Arc<Mutex<VecDeque<u8>>>,
+    from_guest: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl SerialPipe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn backend(&self) -> PipeBackend {
+        PipeBackend { pipe: self.clone() }
+    }
+
+    pub fn write(&self, bytes: &[u8]) {
+        self.to_guest.lock().unwrap().extend(bytes);
+    }
+
+    pub fn read_byte(&self) -> Option<u8> {
+        self.from_guest.lock().unwrap().pop_front()
+    }
+
+    /// Drains everything the guest has sent so far.
+    pub fn read_all(&self) -> Vec<u8> {
+        self.from_guest.lock().unwrap().drain(..).collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct PipeBackend {
+    pipe: SerialPipe,
+}
+
+impl SerialBackend for PipeBackend {
+    fn try_recv(&mut self) -> Option<u8> {
+        self.pipe.to_guest.lock().unwrap().pop_front()
+    }
+
+    fn send(&mut self, byte: u8) -> BusResult<()> {
+        self.pipe.from_guest.lock().unwrap().push_back(byte);
+        Ok(())
+    }
+}
+
+/// Backend over host streams. A reader thread feeds received bytes into a
+/// channel so the guest side never blocks; listening sockets accept one
+/// client at a time. Ptys and sockets are written by a writer thread, so a
+/// peer that stops reading can't stall the guest either.
+pub struct StreamBackend {
+    name: String,
+    rx: Receiver<u8>,
+    tx: Option<Box<dyn Write + Send>>,
+    clients: Option<Receiver<Box<dyn Write + Send>>>,
+    // pty: keep the slave side open so the master does not see EOF/EIO
+    _keep: Option<File>,
+}
+
+impl fmt::Debug for StreamBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamBackend")
+            .field("name", &self.name)
+            .field("connected", &self.tx.is_some())
+            .finish()
+    }
+}
+
+impl StreamBackend {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Guest output to stdout, host stdin to the guest.
+    pub fn stdio() -> Self {
+        let (tx, rx) = mpsc::channel();
+        spawn_reader(io::stdin(), tx);
+        Self {
+            name: "stdio".into(),
+            rx,
+            tx: Some(Box::new(io::stdout())),
+            clients: None,
+            _keep: None,
+        }
+    }
+
+    /// Reads `input` (if it exists) as host data and writes guest output to `output`.
+    pub fn files(input: &Path, output: &Path) -> BusResult<Self> {
+        let (tx, rx) = mpsc::channel();
+        if input.exists() {
+            let f = File::open(input).map_err(|e| {
+                BusError::Device(format!("Can't open serial input \"{}\": {e}", input.display()))
+            })?;
+            spawn_reader(f, tx);
+        }
+        let out = File::create(output).map_err(|e| {
+            BusError::Device(format!("Can't create serial output \"{}\": {e}", output.display()))
+        })?;
+        Ok(Self {
+            name: format!("file {} -> {}", input.display(), output.display()),
+            rx,
+            tx: Some(Box::new(out)),
+            clients: None,
+            _keep: None,
+        })
+    }
+
+    /// Listens on 127.0.0.1:`port`.
+    pub fn tcp(port: u16) -> BusResult<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .map_err(|e| BusError::Device(format!("Can't listen on port {port}: {e}")))?;
+        let name = format!("tcp 127.0.0.1:{}", listener.local_addr().map(|a| a.port()).unwrap_or(port));
+        Ok(Self::listening(name, move || {
+            let (s, _) = listener.accept()?;
+            let _ = s.set_nodelay(true);
+            Ok((s.try_clone()?, s))
+        }))
+    }
+
+    /// Listens on a Unix domain socket at `path` (replacing a stale socket file).
+    #[cfg(unix)]
+    pub fn unix_socket(path: &Path) -> BusResult<Self> {
+        use std::os::unix::net::UnixListener;
+
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).map_err(|e| {
+            BusError::Device(format!("Can't bind \"{}\": {e}", path.display()))
+        })?;
+        Ok(Self::listening(format!("unix {}", path.display()), move || {
+            let (s, _) = listener.accept()?;
+            Ok((s.try_clone()?, s))
+        }))
+    }
+
+    /// Allocates a pseudo-terminal; connect a terminal program to `slave_path`.
+    #[cfg(unix)]
+    pub fn pty() -> BusResult<(Self, PathBuf)> {
+        use std::ffi::CStr;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        let err = |what: &str| BusError::Device(format!("pty: {what}: {}", io::Error::last_os_error()));
+
+        // SAFETY: plain libc calls on a descriptor we own; ptsname's static
+        // buffer is copied out before any other pty call.
+        let (master, slave_path) = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(err("posix_openpt"));
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(err("grantpt/unlockpt"));
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(err("ptsname"));
+            }
+            (master, PathBuf::from(CStr::from_ptr(name).to_string_lossy().into_owned()))
+        };
+
+        let slave = File::options()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&slave_path)
+            .map_err(|e| BusError::Device(format!("pty: open {}: {e}", slave_path.display())))?;
+
+        // raw mode: no echo, no CR/LF translation
+        // SAFETY: termios is plain data, filled in by tcgetattr before use.
+        unsafe {
+            let mut t: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut t) == 0 {
+                libc::cfmakeraw(&mut t);
+                libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &t);
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let reader = master
+            .try_clone()
+            .map_err(|e| BusError::Device(format!("pty: {e}")))?;
+        spawn_reader(reader, tx);
+
+        let backend = Self {
+            name: format!("pty {}", slave_path.display()),
+            rx,
+            tx: Some(Box::new(spawn_writer(master))),
+            clients: None,
+            _keep: Some(slave),
+        };
+        Ok((backend, slave_path))
+    }
+
+    fn listening<S, F>(name: String, mut accept: F) -> Self
+    where
+        S: Read + Write + Send + 'static,
+        F: FnMut() -> io::Result<(S, S)> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let (client_tx, client_rx) = mpsc::channel::<Box<dyn Write + Send>>();
+        thread::spawn(move || loop {
+            let Ok((reader, writer)) = accept() else { break };
+            if client_tx.send(Box::new(spawn_writer(writer))).is_err() {
+                break;
+            }
+            if !read_into(reader, &tx) {
+                break;
+            }
+        });
+        Self {
+            name,
+            rx,
+            tx: None,
+            clients: Some(client_rx),
+            _keep: None,
+        }
+    }
+
+    fn accept_pending(&mut self) {
+        if let Some(clients) = &self.clients {
+            while let Ok(w) = clients.try_recv() {
+                self.tx = Some(w);
+            }
+        }
+    }
+}
+
+impl SerialBackend for StreamBackend {
+    fn try_recv(&mut self) -> Option<u8> {
+        self.accept_pending();
+        self.rx.try_recv().ok()
+    }
+
+    fn send(&mut self, byte: u8) -> BusResult<()> {
+        self.accept_pending();
+        if let Some(w) = &mut self.tx {
+            // a vanished client is like an unplugged cable: drop the byte
+            if w.write_all(&[byte]).and_then(|_| w.flush()).is_err() {
+                self.tx = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Guest end of a writer thread: bytes are queued, and dropped while the
+/// queue is full. Fails once the thread has given up on the stream.
+struct QueuedWriter {
+    tx: SyncSender<u8>,
+}
+
+impl Write for QueuedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            if let Err(TrySendError::Disconnected(_)) = self.tx.try_send(b) {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn spawn_writer<W: Write + Send + 'static>(mut writer: W) -> QueuedWriter {
+    let (tx, rx) = mpsc::sync_channel(MAX_PENDING);
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        while let Ok(b) = rx.recv() {
+            buf.push(b);
+            buf.extend(rx.try_iter());
+            if writer.write_all(&buf).and_then(|_| writer.flush()).is_err() {
+                break;
+            }
+            buf.clear();
+        }
+    });
+    QueuedWriter { tx }
+}
+
+fn spawn_reader<R: Read + Send + 'static>(reader: R, tx: Sender<u8>) {
+    thread::spawn(move || {
+        read_into(reader, &tx);
+    });
+}
+
+/// Copies bytes until EOF; returns false once the receiving side is gone.
+fn read_into<R: Read>(mut reader: R, tx: &Sender<u8>) -> bool {
+    let mut buf = [0u8; 256];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return true,
+            Ok(n) => {
+                for &b in &buf[..n] {
+                    if tx.send(b).is_err() {
+                        return false;
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return true,
+        }
+    }
+}
+
+/// Backend selection as given on the command line:
+/// `stdio`, `pty`, `unix:PATH`, `tcp:PORT` or `file:IN,OUT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialSpec {
+    Stdio,
+    Pty,
+    Unix(PathBuf),
+    Tcp(u16),
+    Files { input: PathBuf, output: PathBuf },
+}
+
+impl FromStr for SerialSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "stdio" => Ok(SerialSpec::Stdio),
+            None if s == "pty" => Ok(SerialSpec::Pty),
+            Some(("unix", path)) if !path.is_empty() => Ok(SerialSpec::Unix(path.into())),
+            Some(("tcp", port)) => port
+                .parse()
+                .map(SerialSpec::Tcp)
+                .map_err(|_| format!("invalid TCP port \"{port}\"")),
+            Some(("file", files)) => match files.split_once(',') {
+                Some((i, o)) if !i.is_empty() && !o.is_empty() => Ok(SerialSpec::Files {
+                    input: i.into(),
+                    output: o.into(),
+                }),
+                _ => Err("expected file:IN,OUT".into()),
+            },
+            _ => Err(format!(
+                "unknown serial backend \"{s}\" (stdio, pty, unix:PATH, tcp:PORT, file:IN,OUT)"
+            )),
+        }
+    }
+}
+
+impl SerialSpec {
+    pub fn open(&self) -> BusResult<StreamBackend> {
+        match self {
+            SerialSpec::Stdio => Ok(StreamBackend::stdio()),
+            #[cfg(unix)]
+            SerialSpec::Pty => StreamBackend::pty().map(|(b, _)| b),
+            #[cfg(unix)]
+            SerialSpec::Unix(path) => StreamBackend::unix_socket(path),
+            #[cfg(not(unix))]
+            SerialSpec::Pty | SerialSpec::Unix(_) => {
+                Err(BusError::Device("pty and unix sockets need a Unix host".into()))
+            }
+            SerialSpec::Tcp(port) => StreamBackend::tcp(*port),
+            SerialSpec::Files { input, output } => StreamBackend::files(input, output),
+        }
+    }
+}
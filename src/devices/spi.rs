@@ -1,6 +1,27 @@
 use crate::bus::BusResult;
 
+/// An SD command frame as the card received it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdCommand {
+    pub cmd: u8,
+    /// Preceded by CMD55 (ACMD).
+    pub app: bool,
+    pub arg: u32,
+    /// Image sector addressed by a block read or write.
+    pub sector: Option<u32>,
+}
+
 pub trait SpiDevice: std::fmt::Debug {
     fn read_data(&mut self) -> BusResult<u32>;
     fn write_data(&mut self, value: u32) -> BusResult<()>;
+
+    /// Command completed by the last `write_data`, for device breakpoints.
+    fn take_command(&mut self) -> Option<SdCommand> {
+        None
+    }
+
+    /// Device state for save states, if it has any.
+    fn save_state(&self) -> Option<Vec<u8>> {
+        None
+    }
 }
\ No newline at end of file
@@ -1,13 +1,21 @@
 use crate::{bus::BusResult, devices::IoDevice};
+use crate::devices::panel::FrontPanel;
 
+/// Switch bank at IO offset 4 (read).
 #[derive(Debug, Default)]
 pub struct Switches {
-    pub value: u32,
+    pub panel: FrontPanel,
+}
+
+impl Switches {
+    pub fn new(panel: FrontPanel) -> Self {
+        Self { panel }
+    }
 }
 
 impl IoDevice for Switches {
     fn read(&mut self, offset: u32) -> BusResult<u32> {
-        if offset == 4 { Ok(self.value) } else { Ok(0) }
+        if offset == 4 { Ok(self.panel.switches()) } else { Ok(0) }
     }
     fn write(&mut self, _offset: u32, _value: u32) -> BusResult<()> {
         Ok(())
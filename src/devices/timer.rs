@@ -1,13 +1,124 @@
+use std::time::{Duration, Instant};
+
+use crate::savestate::{Reader, Writer};
 use crate::{bus::BusResult, devices::IoDevice};
 
-#[derive(Debug, Default)]
+/// Instructions per millisecond of a 25 MHz RISC5.
+pub const DEFAULT_INSTRUCTIONS_PER_MS: u32 = 25_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// Host milliseconds since the timer was created.
+    WallClock,
+    /// Milliseconds derived from executed instructions (reproducible runs).
+    Virtual { instructions_per_ms: u32 },
+}
+
+impl TimerMode {
+    pub fn deterministic() -> Self {
+        TimerMode::Virtual { instructions_per_ms: DEFAULT_INSTRUCTIONS_PER_MS }
+    }
+}
+
+/// Millisecond counter at IO offset 0.
+#[derive(Debug)]
 pub struct Timer {
     pub current_tick: u32,
+    mode: TimerMode,
+    start: Instant,
+    instructions: u64,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new(TimerMode::WallClock)
+    }
+}
+
+impl Timer {
+    pub fn new(mode: TimerMode) -> Self {
+        Self {
+            current_tick: 0,
+            mode,
+            start: Instant::now(),
+            instructions: 0,
+        }
+    }
+
+    pub fn mode(&self) -> TimerMode {
+        self.mode
+    }
+
+    /// Instructions executed since the timer was created.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Called by the CPU for every executed instruction.
+    #[inline]
+    pub fn advance(&mut self, instructions: u32) {
+        self.instructions += instructions as u64;
+        self.virtual_tick();
+    }
+
+    /// Undoes `advance` (reverse execution).
+    pub fn rewind(&mut self, instructions: u32) {
+        self.instructions = self.instructions.saturating_sub(instructions as u64);
+        self.virtual_tick();
+    }
+
+    #[inline]
+    fn virtual_tick(&mut self) {
+        if let TimerMode::Virtual { instructions_per_ms } = self.mode {
+            self.current_tick = (self.instructions / instructions_per_ms.max(1) as u64) as u32;
+        }
+    }
+
+    pub(crate) fn save_state(&self, w: &mut Writer) {
+        match self.mode {
+            TimerMode::WallClock => {
+                w.u8(0);
+                w.u32(0);
+            }
+            TimerMode::Virtual { instructions_per_ms } => {
+                w.u8(1);
+                w.u32(instructions_per_ms);
+            }
+        }
+        w.u64(self.instructions);
+        w.u32(self.current_tick);
+    }
+
+    /// A wall clock timer goes on from the saved tick.
+    pub(crate) fn load_state(r: &mut Reader) -> BusResult<Self> {
+        let mode = match (r.u8()?, r.u32()?) {
+            (0, _) => TimerMode::WallClock,
+            (_, instructions_per_ms) => TimerMode::Virtual { instructions_per_ms },
+        };
+        let mut timer = Self::new(mode);
+        timer.instructions = r.u64()?;
+        timer.current_tick = r.u32()?;
+        if let Some(start) = Instant::now().checked_sub(Duration::from_millis(timer.current_tick as u64)) {
+            timer.start = start;
+        }
+        Ok(timer)
+    }
+
+    fn update(&mut self) {
+        if self.mode == TimerMode::WallClock {
+            self.current_tick = self.start.elapsed().as_millis() as u32;
+        }
+    }
 }
 
 impl IoDevice for Timer {
     fn read(&mut self, offset: u32) -> BusResult<u32> {
-        if offset == 0 { Ok(self.current_tick) } else { Ok(0) }
+        if offset == 0 {
+            self.update();
+            Ok(self.current_tick)
+        } else {
+            Ok(0)
+        }
     }
     fn write(&mut self, _offset: u32, _value: u32) -> BusResult<()> {
         Ok(())
@@ -254,6 +254,22 @@ pub fn disassemble_at(addr: u32, ir: u32) -> DisasmLine {
     }
 
     // Branch format
+    if !u && !v && (ir & 0x30) != 0 {
+        // interrupt extension: IR[5] => STI/CLI (enable := IR[0]), IR[4] => RTI
+        let text = if (ir & 0x20) != 0 {
+            if (ir & 1) != 0 { "STI" } else { "CLI" }
+        } else {
+            "RTI"
+        };
+        return DisasmLine {
+            addr,
+            raw: ir,
+            text: text.to_string(),
+            kind: InstrKind::Branch,
+            branch_target: None,
+        };
+    }
+
     let t_invert = ((ir >> 27) & 1) != 0;
     let cond = (ir >> 24) & 7;
     let link = v;
@@ -0,0 +1,109 @@
+// src/fs/dir.rs
+//
+// The directory B-tree. Lookups walk it like FileDir.Search; changes
+// rebuild it bottom-up from the sorted entry list into fresh sectors and
+// write the root (always DiskAdr 29) last, so an interrupted update leaves
+// the old tree intact. The old pages become free at the next boot.
+
+use std::collections::HashSet;
+
+use super::image::Image;
+use super::layout::{DirEntry, DirPage, DIR_PG_SIZE, DIR_ROOT_ADR};
+use super::{FsError, FsResult, SectorMap};
+
+// deeper than any tree of 64K sectors can be
+const MAX_DEPTH: usize = 16;
+
+/// FileDir.Search: DiskAdr of the header of `name`.
+pub(crate) fn search(img: &mut Image, name: &str) -> FsResult<Option<u32>> {
+    let mut adr = DIR_ROOT_ADR;
+    for _ in 0..MAX_DEPTH {
+        let page = DirPage::decode(&img.read_sector(adr)?, adr)?;
+        // first entry >= name
+        let i = page.entries.partition_point(|e| e.name.as_str() < name);
+        if let Some(e) = page.entries.get(i).filter(|e| e.name == name) {
+            return Ok(Some(e.adr));
+        }
+        adr = if i == 0 { page.p0 } else { page.entries[i - 1].p };
+        if adr == 0 {
+            return Ok(None);
+        }
+    }
+    Err(FsError::Corrupt("directory too deep".into()))
+}
+
+/// All entries in name order (with `p` cleared) and the DiskAdrs of all
+/// directory pages.
+pub(crate) fn walk(img: &mut Image) -> FsResult<(Vec<DirEntry>, Vec<u32>)> {
+    let mut entries = Vec::new();
+    let mut pages = Vec::new();
+    let mut seen = HashSet::new();
+    walk_page(img, DIR_ROOT_ADR, 0, &mut seen, &mut entries, &mut pages)?;
+    Ok((entries, pages))
+}
+
+fn walk_page(
+    img: &mut Image,
+    adr: u32,
+    depth: usize,
+    seen: &mut HashSet<u32>,
+    entries: &mut Vec<DirEntry>,
+    pages: &mut Vec<u32>,
+) -> FsResult<()> {
+    if depth > MAX_DEPTH {
+        return Err(FsError::Corrupt("directory too deep".into()));
+    }
+    if !seen.insert(adr) {
+        return Err(FsError::Corrupt(format!("directory page {adr} is reachable twice")));
+    }
+    pages.push(adr);
+    let page = DirPage::decode(&img.read_sector(adr)?, adr)?;
+    if page.p0 != 0 {
+        walk_page(img, page.p0, depth + 1, seen, entries, pages)?;
+    }
+    for e in page.entries {
+        entries.push(DirEntry { p: 0, ..e.clone() });
+        if e.p != 0 {
+            walk_page(img, e.p, depth + 1, seen, entries, pages)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a new tree holding `entries` (sorted, unique names). Non-root
+/// pages get between DIR_PG_SIZE/2 and DIR_PG_SIZE entries, as FileDir
+/// expects.
+pub(crate) fn rebuild(img: &mut Image, map: &mut SectorMap, entries: Vec<DirEntry>) -> FsResult<()> {
+    let mut level: Vec<DirEntry> = entries.into_iter().map(|e| DirEntry { p: 0, ..e }).collect();
+    let mut p0 = 0;
+
+    // each pass turns one level into pages and returns the separators
+    while level.len() > DIR_PG_SIZE {
+        let groups = (level.len() + 1).div_ceil(DIR_PG_SIZE + 1);
+        let total = level.len() - (groups - 1);
+        let mut items = level.into_iter();
+        let mut next = Vec::with_capacity(groups - 1);
+        let mut child_p0 = p0;
+        let mut sep: Option<DirEntry> = None;
+
+        for g in 0..groups {
+            let size = total / groups + usize::from(g < total % groups);
+            let page = DirPage { p0: child_p0, entries: items.by_ref().take(size).collect() };
+            let adr = map.alloc()?;
+            img.write_sector(adr, &page.encode())?;
+
+            match sep.take() {
+                None => p0 = adr,
+                Some(s) => next.push(DirEntry { p: adr, ..s }),
+            }
+            if g + 1 < groups {
+                let s = items.next().expect("separator");
+                child_p0 = s.p;
+                sep = Some(s);
+            }
+        }
+        level = next;
+    }
+
+    img.write_sector(DIR_ROOT_ADR, &DirPage { p0, entries: level }.encode())
+}
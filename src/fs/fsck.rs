@@ -0,0 +1,450 @@
+// src/fs/fsck.rs
+//
+// Consistency check of an Oberon file system: walks the directory B-tree
+// and every file's header, sector table and index sectors, and claims each
+// sector for its owner so that cross-links show up. Unclaimed sectors that
+// still carry a header mark are old versions of files or lost files.
+//
+// Repair truncates files at the first unusable sector, gives the second
+// owner of a cross-linked sector its own copy, drops entries whose header
+// is unusable and rewrites the directory (see dir::rebuild).
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use super::dir;
+use super::layout::*;
+use super::{FileSystem, FsResult, SectorMap};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsckOptions {
+    /// Fix what can be fixed and rewrite the directory.
+    pub repair: bool,
+    /// With `repair`: link lost files back into the directory.
+    pub recover_lost: bool,
+}
+
+/// What a sector is used for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Owner {
+    DirPage(u32),
+    Header(String),
+    /// Sector `n` of a file.
+    Data(String, usize),
+    /// Index sector `ext[n]` of a file.
+    Index(String, usize),
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::DirPage(adr) => write!(f, "directory page {adr}"),
+            Owner::Header(name) => write!(f, "header of {name}"),
+            Owner::Data(name, n) => write!(f, "sector {n} of {name}"),
+            Owner::Index(name, n) => write!(f, "index sector {n} of {name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Unusable directory page; the names below it are lost.
+    BadPage { adr: u32, reason: String },
+    /// Usable page that breaks B-tree rules (fill, order, depth).
+    BadTree { adr: u32, reason: String },
+    DuplicateName(String),
+    /// Entry whose header is unusable; repair removes it.
+    BadEntry { name: String, adr: u32, reason: String },
+    NameMismatch { name: String, header: String },
+    BadLength { name: String, aleng: u32, bleng: u32 },
+    /// Unusable sector table entry; repair truncates the file before it.
+    BadSector { name: String, index: usize, adr: u32, reason: String },
+    CrossLinked { adr: u32, owner: Owner, other: Owner },
+    /// Valid header of a file that is not in the directory.
+    LostFile { name: String, adr: u32, length: usize },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadPage { adr, reason } => write!(f, "directory page {adr}: {reason}"),
+            Problem::BadTree { adr, reason } => write!(f, "directory page {adr}: {reason}"),
+            Problem::DuplicateName(name) => write!(f, "{name}: listed more than once"),
+            Problem::BadEntry { name, adr, reason } => write!(f, "{name}: header {adr}: {reason}"),
+            Problem::NameMismatch { name, header } => write!(f, "{name}: header says \"{header}\""),
+            Problem::BadLength { name, aleng, bleng } => {
+                write!(f, "{name}: bad length (aleng {aleng}, bleng {bleng})")
+            }
+            Problem::BadSector { name, index, adr, reason } => write!(f, "{name}: sector {index} ({adr}): {reason}"),
+            Problem::CrossLinked { adr, owner, other } => write!(f, "sector {adr}: {other} is also {owner}"),
+            Problem::LostFile { name, adr, length } => write!(f, "lost file {name} ({length} bytes) at {adr}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+    pub files: usize,
+    pub dir_pages: usize,
+    pub used_sectors: usize,
+    /// Unreferenced headers of names that are in the directory (old versions).
+    pub stale_headers: usize,
+    pub repaired: bool,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl FileSystem {
+    pub fn fsck(&mut self, opts: FsckOptions) -> FsResult<Report> {
+        let capacity = self.image().sector_capacity()?;
+        let mut ck = Check { capacity, owners: HashMap::new(), problems: Vec::new() };
+
+        // directory
+        let mut w = Walk::default();
+        ck.walk(self, DIR_ROOT_ADR, 0, None, None, &mut w)?;
+        for &adr in &w.pages {
+            ck.owners.insert(adr, Owner::DirPage(adr));
+        }
+        w.entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut entries: Vec<(String, u32)> = Vec::with_capacity(w.entries.len());
+        for (name, adr) in w.entries {
+            if entries.last().is_some_and(|e| e.0 == name) {
+                ck.problems.push(Problem::DuplicateName(name));
+            } else {
+                entries.push((name, adr));
+            }
+        }
+
+        // files
+        let mut files = Vec::new();
+        for (name, adr) in entries {
+            if let Some(f) = ck.file(self, &name, adr)? {
+                files.push(f);
+            }
+        }
+
+        // headers nobody references; keep the newest per lost name
+        let names: HashSet<String> = files.iter().map(|f| f.header.name.clone()).collect();
+        let mut stale = 0;
+        let mut lost: BTreeMap<String, (u32, FileHeader)> = BTreeMap::new();
+        for k in RESERVED_SECTORS..capacity {
+            let adr = k * SECTOR_MULT;
+            if ck.owners.contains_key(&adr) {
+                continue;
+            }
+            let s = self.image().read_sector(adr)?;
+            if word(&s, 0) != HEADER_MARK {
+                continue;
+            }
+            let Ok(h) = FileHeader::decode(&s, adr) else { continue };
+            if check_name(&h.name).is_err() || h.length().is_none() {
+                continue;
+            }
+            if names.contains(&h.name) {
+                stale += 1;
+            } else if lost.get(&h.name).is_none_or(|(_, old)| h.date >= old.date) {
+                lost.insert(h.name.clone(), (adr, h));
+            }
+        }
+        let listed = files.len();
+        for (name, (adr, h)) in lost {
+            ck.problems.push(Problem::LostFile { name: name.clone(), adr, length: h.length().unwrap_or(0) });
+            if opts.repair && opts.recover_lost {
+                if let Some(f) = ck.file(self, &name, adr)? {
+                    files.push(f);
+                }
+            }
+        }
+
+        let fixable = ck.problems.iter().any(|p| !matches!(p, Problem::LostFile { .. })) || files.len() > listed;
+        let mut map = SectorMap::new();
+        for &adr in ck.owners.keys() {
+            map.mark(adr)?;
+        }
+        let mut report = Report {
+            problems: ck.problems,
+            files: files.len(),
+            dir_pages: w.pages.len(),
+            used_sectors: map.used_count(),
+            stale_headers: stale,
+            repaired: false,
+        };
+        if opts.repair && fixable {
+            for f in &mut files {
+                f.write(self, &mut map)?;
+            }
+            files.sort_by(|a, b| a.header.name.cmp(&b.header.name));
+            let entries = files
+                .iter()
+                .map(|f| DirEntry { name: f.header.name.clone(), adr: f.adr, p: 0 })
+                .collect();
+            dir::rebuild(self.image(), &mut map, entries)?;
+            self.image().flush()?;
+            report.repaired = true;
+        }
+        Ok(report)
+    }
+}
+
+#[derive(Default)]
+struct Walk {
+    pages: Vec<u32>,
+    entries: Vec<(String, u32)>,
+    seen: HashSet<u32>,
+    leaf_depth: Option<usize>,
+}
+
+struct Check {
+    capacity: u32,
+    owners: HashMap<u32, Owner>,
+    problems: Vec<Problem>,
+}
+
+struct FileCheck {
+    adr: u32,
+    header: FileHeader,
+    secs: Vec<u32>,
+    index: Vec<u32>,
+    // positions whose sector belongs to someone else: Ok(sector) / Err(ext)
+    clones: Vec<Result<usize, usize>>,
+    changed: bool,
+}
+
+// deeper than any tree of 64K sectors can be
+const MAX_DEPTH: usize = 16;
+
+impl Check {
+    /// Why `adr` cannot hold a sector, if it can't.
+    fn bad_adr(&self, adr: u32) -> Option<&'static str> {
+        let k = adr / SECTOR_MULT;
+        if adr == 0 || adr % SECTOR_MULT != 0 {
+            Some("not a sector address")
+        } else if k >= MAP_SIZE {
+            Some("out of range")
+        } else if k < RESERVED_SECTORS {
+            Some("in the boot area")
+        } else if k >= self.capacity {
+            Some("beyond the end of the image")
+        } else {
+            None
+        }
+    }
+
+    fn walk(
+        &mut self,
+        fs: &mut FileSystem,
+        adr: u32,
+        depth: usize,
+        lo: Option<&str>,
+        hi: Option<&str>,
+        w: &mut Walk,
+    ) -> FsResult<()> {
+        let bad = |reason: &str| Problem::BadPage { adr, reason: reason.to_string() };
+        if adr != DIR_ROOT_ADR {
+            if let Some(reason) = self.bad_adr(adr) {
+                self.problems.push(bad(reason));
+                return Ok(());
+            }
+        }
+        if depth > MAX_DEPTH {
+            self.problems.push(bad("directory too deep"));
+            return Ok(());
+        }
+        if !w.seen.insert(adr) {
+            self.problems.push(bad("reachable more than once"));
+            return Ok(());
+        }
+        let page = match DirPage::decode(&fs.image().read_sector(adr)?, adr) {
+            Ok(p) => p,
+            Err(e) => {
+                self.problems.push(bad(&e.to_string()));
+                return Ok(());
+            }
+        };
+        w.pages.push(adr);
+
+        let tree = |reason: &str| Problem::BadTree { adr, reason: reason.to_string() };
+        if adr != DIR_ROOT_ADR && page.entries.len() < DIR_PG_MIN {
+            self.problems.push(tree(&format!("only {} entries", page.entries.len())));
+        }
+        let mut prev = lo;
+        for e in &page.entries {
+            if prev.is_some_and(|p| e.name.as_str() <= p) || hi.is_some_and(|h| e.name.as_str() >= h) {
+                self.problems.push(tree(&format!("{} is out of order", e.name)));
+            }
+            prev = Some(&e.name);
+            w.entries.push((e.name.clone(), e.adr));
+        }
+
+        if page.p0 == 0 {
+            if page.entries.iter().any(|e| e.p != 0) {
+                self.problems.push(tree("leaf page with descendants"));
+            }
+            match w.leaf_depth {
+                None => w.leaf_depth = Some(depth),
+                Some(d) if d != depth => self.problems.push(tree("leaves at different depths")),
+                _ => {}
+            }
+            return Ok(());
+        }
+        let first = page.entries.first().map(|e| e.name.as_str());
+        self.walk(fs, page.p0, depth + 1, lo, first.or(hi), w)?;
+        for (i, e) in page.entries.iter().enumerate() {
+            let next = page.entries.get(i + 1).map(|e| e.name.as_str()).or(hi);
+            if e.p == 0 {
+                self.problems.push(tree(&format!("{} has no descendant", e.name)));
+                continue;
+            }
+            self.walk(fs, e.p, depth + 1, Some(&e.name), next, w)?;
+        }
+        Ok(())
+    }
+
+    fn claim(&mut self, adr: u32, owner: Owner) -> bool {
+        if let Some(other) = self.owners.get(&adr) {
+            self.problems.push(Problem::CrossLinked { adr, owner: other.clone(), other: owner });
+            return false;
+        }
+        self.owners.insert(adr, owner);
+        true
+    }
+
+    /// Checks one file; None if its entry has to go.
+    fn file(&mut self, fs: &mut FileSystem, name: &str, adr: u32) -> FsResult<Option<FileCheck>> {
+        let bad_entry = |reason: String| Problem::BadEntry { name: name.to_string(), adr, reason };
+        if let Some(reason) = self.bad_adr(adr) {
+            self.problems.push(bad_entry(reason.to_string()));
+            return Ok(None);
+        }
+        if let Some(other) = self.owners.get(&adr) {
+            self.problems.push(bad_entry(format!("also used as {other}")));
+            return Ok(None);
+        }
+        let mut h = match FileHeader::decode(&fs.image().read_sector(adr)?, adr) {
+            Ok(h) => h,
+            Err(e) => {
+                self.problems.push(bad_entry(e.to_string()));
+                return Ok(None);
+            }
+        };
+        if h.aleng as usize >= MAX_FILE_SECTORS {
+            self.problems.push(bad_entry(format!("aleng {} out of range", h.aleng)));
+            return Ok(None);
+        }
+        self.owners.insert(adr, Owner::Header(name.to_string()));
+
+        let mut f = FileCheck { adr, header: h.clone(), secs: Vec::new(), index: Vec::new(), clones: Vec::new(), changed: false };
+        if h.name != name {
+            self.problems.push(Problem::NameMismatch { name: name.to_string(), header: h.name.clone() });
+            h.name = name.to_string();
+            f.changed = true;
+        }
+        let min_b = if h.aleng == 0 { HEADER_SIZE as u32 } else { 1 };
+        if !(min_b..=SECTOR_SIZE as u32).contains(&h.bleng) {
+            self.problems.push(Problem::BadLength { name: name.to_string(), aleng: h.aleng, bleng: h.bleng });
+            h.bleng = h.bleng.clamp(min_b, SECTOR_SIZE as u32);
+            f.changed = true;
+        }
+        if h.sec[0] != adr {
+            self.problems.push(Problem::BadSector {
+                name: name.to_string(),
+                index: 0,
+                adr: h.sec[0],
+                reason: "does not point to the header".into(),
+            });
+            h.sec[0] = adr;
+            f.changed = true;
+        }
+        f.secs.push(adr);
+
+        let n = h.aleng as usize + 1;
+        let mut index_sector = [0u8; SECTOR_SIZE];
+        let mut i = 1;
+        while i < n {
+            let j = i.checked_sub(SEC_TAB_SIZE);
+            if let Some(j) = j.filter(|j| j % INDEX_SIZE == 0) {
+                let x = j / INDEX_SIZE;
+                let xa = h.ext[x];
+                if let Some(reason) = self.bad_adr(xa) {
+                    self.truncate(&mut f, &mut h, i, xa, format!("index sector {x} {reason}"));
+                    break;
+                }
+                if !self.claim(xa, Owner::Index(name.to_string(), x)) {
+                    f.clones.push(Err(x));
+                }
+                index_sector = fs.image().read_sector(xa)?;
+                f.index.push(xa);
+            }
+            let sa = match j {
+                None => h.sec[i],
+                Some(j) => word(&index_sector, j % INDEX_SIZE * 4),
+            };
+            if let Some(reason) = self.bad_adr(sa) {
+                self.truncate(&mut f, &mut h, i, sa, reason.to_string());
+                break;
+            }
+            if !self.claim(sa, Owner::Data(name.to_string(), i)) {
+                f.clones.push(Ok(i));
+            }
+            f.secs.push(sa);
+            i += 1;
+        }
+        f.changed |= !f.clones.is_empty();
+        f.header = h;
+        Ok(Some(f))
+    }
+
+    fn truncate(&mut self, f: &mut FileCheck, h: &mut FileHeader, at: usize, adr: u32, reason: String) {
+        self.problems.push(Problem::BadSector { name: h.name.clone(), index: at, adr, reason });
+        h.aleng = at as u32 - 1;
+        h.bleng = SECTOR_SIZE as u32;
+        f.changed = true;
+    }
+}
+
+impl FileCheck {
+    /// Writes back a changed file: copies cross-linked sectors, then the
+    /// index sectors and the header.
+    fn write(&mut self, fs: &mut FileSystem, map: &mut SectorMap) -> FsResult<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        for c in std::mem::take(&mut self.clones) {
+            let new = map.alloc()?;
+            match c {
+                Ok(i) => {
+                    let s = fs.image().read_sector(self.secs[i])?;
+                    fs.image().write_sector(new, &s)?;
+                    self.secs[i] = new;
+                }
+                // contents are rewritten below
+                Err(x) => self.index[x] = new,
+            }
+        }
+
+        let h = &mut self.header;
+        h.sec = [0; SEC_TAB_SIZE];
+        h.ext = [0; EX_TAB_SIZE];
+        for (i, &adr) in self.secs.iter().take(SEC_TAB_SIZE).enumerate() {
+            h.sec[i] = adr;
+        }
+        let rest = self.secs.get(SEC_TAB_SIZE..).unwrap_or(&[]);
+        for (x, chunk) in rest.chunks(INDEX_SIZE).enumerate() {
+            let mut s = [0u8; SECTOR_SIZE];
+            for (i, &adr) in chunk.iter().enumerate() {
+                put_word(&mut s, i * 4, adr);
+            }
+            h.ext[x] = self.index[x];
+            fs.image().write_sector(h.ext[x], &s)?;
+        }
+
+        let mut s = fs.image().read_sector(self.adr)?;
+        self.header.encode_into(&mut s);
+        fs.image().write_sector(self.adr, &s)
+    }
+}
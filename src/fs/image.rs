@@ -0,0 +1,103 @@
+// src/fs/image.rs
+//
+// Sector access to a disk image file. Sector k of the file system sits at
+// SD block 2k + FS_OFFSET. A full image is a dump of the whole card; a
+// filesystem-only image starts at sector 1 (SD block FS_OFFSET + 2), which
+// Disk::new recognises by the directory mark in its first block.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::layout::{Sector, DIR_MARK, DIR_ROOT_ADR, MAP_SIZE, SECTOR_MULT, SECTOR_SIZE};
+use super::{FsError, FsResult};
+
+/// SD block of file system sector 0 (Kernel.FSoffset).
+pub const FS_OFFSET: u32 = 0x80000;
+/// SD block of the first block of a filesystem-only image.
+pub const FS_ONLY_OFFSET: u32 = FS_OFFSET + 2;
+
+#[derive(Debug)]
+pub struct Image {
+    file: File,
+    /// SD block of the first 512 bytes of the file.
+    offset: u32,
+}
+
+impl Image {
+    pub fn open(path: &Path) -> FsResult<Self> {
+        Self::from_file(File::options().read(true).write(true).open(path)?)
+    }
+
+    pub fn open_read_only(path: &Path) -> FsResult<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    /// Finds the layout of `file` from where the root directory page is.
+    pub fn from_file(mut file: File) -> FsResult<Self> {
+        for offset in [FS_ONLY_OFFSET, 0] {
+            let mut img = Self { file, offset };
+            if super::layout::word(&img.read_sector(DIR_ROOT_ADR)?, 0) == DIR_MARK {
+                return Ok(img);
+            }
+            file = img.file;
+        }
+        Err(FsError::NoFileSystem)
+    }
+
+    /// Image with a known layout (`FS_ONLY_OFFSET` or 0), e.g. one being created.
+    pub fn with_offset(file: File, offset: u32) -> Self {
+        Self { file, offset }
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn is_fs_only(&self) -> bool {
+        self.offset == FS_ONLY_OFFSET
+    }
+
+    /// Number of sectors (from 0) the file covers; later ones read as zeros.
+    pub fn sector_capacity(&self) -> FsResult<u32> {
+        let blocks = self.file.metadata()?.len() / 512 + self.offset as u64;
+        Ok((blocks.saturating_sub(FS_OFFSET as u64) / 2).min(MAP_SIZE as u64) as u32)
+    }
+
+    /// Byte position of the sector at DiskAdr `adr` in the file.
+    pub fn position(&self, adr: u32) -> FsResult<u64> {
+        let k = adr / SECTOR_MULT;
+        if adr % SECTOR_MULT != 0 || k >= MAP_SIZE {
+            return Err(FsError::BadAddress(adr));
+        }
+        let block = (2 * k + FS_OFFSET).checked_sub(self.offset).ok_or(FsError::BadAddress(adr))?;
+        Ok(block as u64 * 512)
+    }
+
+    /// Sectors past the end of the file read as zeros.
+    pub fn read_sector(&mut self, adr: u32) -> FsResult<Sector> {
+        let pos = self.position(adr)?;
+        let mut s = [0u8; SECTOR_SIZE];
+        self.file.seek(SeekFrom::Start(pos))?;
+        let mut got = 0;
+        while got < SECTOR_SIZE {
+            match self.file.read(&mut s[got..])? {
+                0 => break,
+                n => got += n,
+            }
+        }
+        Ok(s)
+    }
+
+    pub fn write_sector(&mut self, adr: u32, s: &Sector) -> FsResult<()> {
+        let pos = self.position(adr)?;
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.file.write_all(s)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> FsResult<()> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
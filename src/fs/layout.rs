@@ -0,0 +1,229 @@
+// src/fs/layout.rs
+//
+// On-disk records of FileDir.Mod. All words are little-endian.
+//
+//   DirPage    = mark, m, p0, fill[52], e[24] of (name[32], adr, p)
+//   FileHeader = mark, name[32], aleng, bleng, date, ext[12], sec[64],
+//                then the first SECTOR_SIZE - HEADER_SIZE bytes of data
+
+use super::{FsError, FsResult};
+
+pub const SECTOR_SIZE: usize = 1024;
+/// A DiskAdr is the sector number times 29.
+pub const SECTOR_MULT: u32 = 29;
+pub const FN_LENGTH: usize = 32;
+pub const SEC_TAB_SIZE: usize = 64;
+pub const EX_TAB_SIZE: usize = 12;
+pub const INDEX_SIZE: usize = SECTOR_SIZE / 4;
+pub const HEADER_SIZE: usize = 352;
+pub const DIR_ROOT_ADR: u32 = 29;
+pub const DIR_PG_SIZE: usize = 24;
+/// Minimum fill of a non-root directory page.
+pub const DIR_PG_MIN: usize = DIR_PG_SIZE / 2;
+pub const DIR_MARK: u32 = 0x9B1E_A38D;
+pub const HEADER_MARK: u32 = 0x9BA7_1D86;
+/// Sectors 0..63 hold the boot file and are never allocated (Kernel.InitSecMap).
+pub const RESERVED_SECTORS: u32 = 64;
+/// First sector of the boot file read by BootLoad (SD block FS_OFFSET + 4).
+pub const BOOT_FIRST_SECTOR: u32 = 2;
+/// Largest boot file that fits before the first allocatable sector.
+pub const MAX_BOOT_SIZE: usize = (RESERVED_SECTORS - BOOT_FIRST_SECTOR) as usize * SECTOR_SIZE;
+/// Sectors covered by the kernel's allocation map (64 MB).
+pub const MAP_SIZE: u32 = 0x10000;
+pub const MAX_FILE_SECTORS: usize = SEC_TAB_SIZE + EX_TAB_SIZE * INDEX_SIZE;
+/// Largest file the header can describe.
+pub const MAX_FILE_SIZE: usize = MAX_FILE_SECTORS * SECTOR_SIZE - HEADER_SIZE;
+
+const FILLER_SIZE: usize = 52;
+const DIR_ENTRY_SIZE: usize = FN_LENGTH + 8;
+
+pub type Sector = [u8; SECTOR_SIZE];
+
+pub fn word(s: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(s[at..at + 4].try_into().unwrap())
+}
+
+pub fn put_word(s: &mut [u8], at: usize, v: u32) {
+    s[at..at + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+/// Files.Check: a letter, then letters, digits and dots; at most 31 chars.
+pub fn check_name(name: &str) -> FsResult<()> {
+    let ok = !name.is_empty()
+        && name.len() < FN_LENGTH
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.');
+    if ok { Ok(()) } else { Err(FsError::InvalidName(name.to_string())) }
+}
+
+fn decode_name(b: &[u8]) -> String {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    String::from_utf8_lossy(&b[..end]).into_owned()
+}
+
+fn encode_name(name: &str, out: &mut [u8]) {
+    out[..FN_LENGTH].fill(0);
+    let n = name.len().min(FN_LENGTH - 1);
+    out[..n].copy_from_slice(&name.as_bytes()[..n]);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    /// DiskAdr of the file header.
+    pub adr: u32,
+    /// DiskAdr of the page with the names between this entry and the next.
+    pub p: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DirPage {
+    /// DiskAdr of the page with the names before the first entry.
+    pub p0: u32,
+    pub entries: Vec<DirEntry>,
+}
+
+impl DirPage {
+    pub fn decode(s: &Sector, adr: u32) -> FsResult<Self> {
+        if word(s, 0) != DIR_MARK {
+            return Err(FsError::BadMark { what: "directory", adr });
+        }
+        let m = word(s, 4) as usize;
+        if m > DIR_PG_SIZE {
+            return Err(FsError::Corrupt(format!("directory page {adr} has {m} entries")));
+        }
+        let entries = (0..m)
+            .map(|i| {
+                let e = &s[12 + FILLER_SIZE + i * DIR_ENTRY_SIZE..][..DIR_ENTRY_SIZE];
+                DirEntry { name: decode_name(&e[..FN_LENGTH]), adr: word(e, FN_LENGTH), p: word(e, FN_LENGTH + 4) }
+            })
+            .collect();
+        Ok(Self { p0: word(s, 8), entries })
+    }
+
+    pub fn encode(&self) -> Sector {
+        assert!(self.entries.len() <= DIR_PG_SIZE);
+        let mut s = [0u8; SECTOR_SIZE];
+        put_word(&mut s, 0, DIR_MARK);
+        put_word(&mut s, 4, self.entries.len() as u32);
+        put_word(&mut s, 8, self.p0);
+        for (i, e) in self.entries.iter().enumerate() {
+            let at = 12 + FILLER_SIZE + i * DIR_ENTRY_SIZE;
+            encode_name(&e.name, &mut s[at..]);
+            put_word(&mut s, at + FN_LENGTH, e.adr);
+            put_word(&mut s, at + FN_LENGTH + 4, e.p);
+        }
+        s
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHeader {
+    pub name: String,
+    /// Index of the last sector in use.
+    pub aleng: u32,
+    /// Bytes used in the last sector (counting the header in sector 0).
+    pub bleng: u32,
+    pub date: u32,
+    /// DiskAdrs of index sectors for sectors 64 and up.
+    pub ext: [u32; EX_TAB_SIZE],
+    pub sec: [u32; SEC_TAB_SIZE],
+}
+
+impl FileHeader {
+    pub fn new(name: &str, len: usize, date: u32) -> Self {
+        // like Files.Mod: a full last sector has bleng = SECTOR_SIZE
+        let total = len + HEADER_SIZE;
+        let (aleng, bleng) = if len > 0 && total % SECTOR_SIZE == 0 {
+            (total / SECTOR_SIZE - 1, SECTOR_SIZE)
+        } else {
+            (total / SECTOR_SIZE, total % SECTOR_SIZE)
+        };
+        Self {
+            name: name.to_string(),
+            aleng: aleng as u32,
+            bleng: bleng as u32,
+            date,
+            ext: [0; EX_TAB_SIZE],
+            sec: [0; SEC_TAB_SIZE],
+        }
+    }
+
+    pub fn decode(s: &Sector, adr: u32) -> FsResult<Self> {
+        if word(s, 0) != HEADER_MARK {
+            return Err(FsError::BadMark { what: "file header", adr });
+        }
+        let at = 4 + FN_LENGTH;
+        Ok(Self {
+            name: decode_name(&s[4..at]),
+            aleng: word(s, at),
+            bleng: word(s, at + 4),
+            date: word(s, at + 8),
+            ext: std::array::from_fn(|i| word(s, at + 12 + i * 4)),
+            sec: std::array::from_fn(|i| word(s, at + 12 + EX_TAB_SIZE * 4 + i * 4)),
+        })
+    }
+
+    /// Writes the header into the first HEADER_SIZE bytes of `s`.
+    pub fn encode_into(&self, s: &mut Sector) {
+        s[..HEADER_SIZE].fill(0);
+        put_word(s, 0, HEADER_MARK);
+        encode_name(&self.name, &mut s[4..]);
+        let at = 4 + FN_LENGTH;
+        put_word(s, at, self.aleng);
+        put_word(s, at + 4, self.bleng);
+        put_word(s, at + 8, self.date);
+        for (i, &a) in self.ext.iter().enumerate() {
+            put_word(s, at + 12 + i * 4, a);
+        }
+        for (i, &a) in self.sec.iter().enumerate() {
+            put_word(s, at + 12 + EX_TAB_SIZE * 4 + i * 4, a);
+        }
+    }
+
+    /// File length in bytes, or None if aleng/bleng are out of range.
+    pub fn length(&self) -> Option<usize> {
+        let (a, b) = (self.aleng as usize, self.bleng as usize);
+        if a >= MAX_FILE_SECTORS || b > SECTOR_SIZE {
+            return None;
+        }
+        (a * SECTOR_SIZE + b).checked_sub(HEADER_SIZE)
+    }
+}
+
+/// Kernel.Clock format: year-2000 (6 bits), month, day, hour, min, sec.
+pub fn encode_date(year: u32, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> u32 {
+    (((((year.saturating_sub(2000) & 0x3F) * 16 + month) * 32 + day) * 32 + hour) * 64 + min) * 64 + sec
+}
+
+/// (year, month, day, hour, min, sec) of a Kernel.Clock value.
+pub fn decode_date(d: u32) -> (u32, u32, u32, u32, u32, u32) {
+    (
+        (d >> 26) + 2000,
+        (d >> 22) & 0xF,
+        (d >> 17) & 0x1F,
+        (d >> 12) & 0x1F,
+        (d >> 6) & 0x3F,
+        d & 0x3F,
+    )
+}
+
+/// Current UTC time as a Kernel.Clock value.
+pub fn now() -> u32 {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = ((secs / 86_400) as i64, (secs % 86_400) as u32);
+    // civil_from_days (H. Hinnant)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = (yoe + era * 400 + (month <= 2) as i64) as u32;
+    encode_date(year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
@@ -0,0 +1,396 @@
+// src/fs/mod.rs
+//
+// Project Oberon file system (FileDir.Mod, Files.Mod) inside a disk image,
+// so files can be listed, extracted and placed without booting the image.
+//
+// Space is handed out in 1 KiB sectors addressed by DiskAdr = sector * 29.
+// Like the kernel, free space is not stored on disk: the sector map is
+// rebuilt by walking the directory and every file's sector tables.
+
+mod dir;
+pub mod fsck;
+pub mod image;
+pub mod layout;
+
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+
+pub use image::Image;
+pub use layout::{DirEntry, DirPage, FileHeader};
+
+use layout::*;
+
+pub type FsResult<T> = Result<T, FsError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FsError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("no Oberon file system found (no directory mark at sector 1)")]
+    NoFileSystem,
+
+    #[error("bad {what} mark at DiskAdr {adr}")]
+    BadMark { what: &'static str, adr: u32 },
+
+    #[error("invalid DiskAdr {0}")]
+    BadAddress(u32),
+
+    #[error("invalid Oberon file name \"{0}\"")]
+    InvalidName(String),
+
+    #[error("file not found: {0}")]
+    NotFound(String),
+
+    #[error("file too large: {0} bytes (max {MAX_FILE_SIZE})")]
+    TooLarge(usize),
+
+    #[error("disk full")]
+    DiskFull,
+
+    #[error("corrupt file system: {0}")]
+    Corrupt(String),
+
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Layout of a new image, see `image`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Whole SD card; the file system starts at block FS_OFFSET.
+    Full,
+    /// File system only, starting at sector 1.
+    FsOnly,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Layout::Full),
+            "fs-only" => Ok(Layout::FsOnly),
+            _ => Err(format!("unknown layout \"{s}\" (full, fs-only)")),
+        }
+    }
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Layout::Full => "full",
+            Layout::FsOnly => "fs-only",
+        })
+    }
+}
+
+/// Sectors in use, as Kernel.InitSecMap + FileDir.Init would find them.
+#[derive(Debug, Clone)]
+pub struct SectorMap {
+    used: Vec<bool>,
+    next: u32,
+}
+
+impl SectorMap {
+    /// Map with only the reserved boot sectors in use.
+    pub fn new() -> Self {
+        let mut used = vec![false; MAP_SIZE as usize];
+        used[..RESERVED_SECTORS as usize].fill(true);
+        Self { used, next: RESERVED_SECTORS }
+    }
+
+    pub fn is_used(&self, adr: u32) -> bool {
+        self.used.get((adr / SECTOR_MULT) as usize).copied().unwrap_or(true)
+    }
+
+    /// Marks `adr`; returns false if it was already in use.
+    pub fn mark(&mut self, adr: u32) -> FsResult<bool> {
+        if adr % SECTOR_MULT != 0 || adr / SECTOR_MULT >= MAP_SIZE {
+            return Err(FsError::BadAddress(adr));
+        }
+        let k = (adr / SECTOR_MULT) as usize;
+        Ok(!std::mem::replace(&mut self.used[k], true))
+    }
+
+    /// Lowest free sector, like Kernel.AllocSector.
+    pub fn alloc(&mut self) -> FsResult<u32> {
+        let k = (self.next..MAP_SIZE).find(|&k| !self.used[k as usize]).ok_or(FsError::DiskFull)?;
+        self.used[k as usize] = true;
+        self.next = k + 1;
+        Ok(k * SECTOR_MULT)
+    }
+
+    pub fn used_count(&self) -> usize {
+        self.used.iter().filter(|&&u| u).count()
+    }
+}
+
+impl Default for SectorMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Directory listing entry with the header fields `ls -l` needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub name: String,
+    /// DiskAdr of the header.
+    pub adr: u32,
+    pub length: usize,
+    pub date: u32,
+}
+
+pub struct FileSystem {
+    img: Image,
+}
+
+impl FileSystem {
+    pub fn open(path: &Path) -> FsResult<Self> {
+        Ok(Self { img: Image::open(path)? })
+    }
+
+    pub fn open_read_only(path: &Path) -> FsResult<Self> {
+        Ok(Self { img: Image::open_read_only(path)? })
+    }
+
+    /// Creates an image with room for `sectors` file system sectors
+    /// (including the reserved boot area) and an empty root directory.
+    /// An existing file is only replaced if `overwrite` is set.
+    pub fn create(path: &Path, layout: Layout, sectors: u32, overwrite: bool) -> FsResult<Self> {
+        if !(RESERVED_SECTORS + 1..=MAP_SIZE).contains(&sectors) {
+            return Err(FsError::Invalid(format!(
+                "image size must be {} to {MAP_SIZE} sectors of 1 KiB, got {sectors}",
+                RESERVED_SECTORS + 1
+            )));
+        }
+        let mut opts = File::options();
+        opts.read(true).write(true);
+        if overwrite {
+            opts.create(true).truncate(true);
+        } else {
+            opts.create_new(true);
+        }
+        let f = opts.open(path)?;
+
+        let (offset, len) = match layout {
+            Layout::Full => (0, (image::FS_OFFSET as u64 + 2 * sectors as u64) * 512),
+            Layout::FsOnly => (image::FS_ONLY_OFFSET, (sectors as u64 - 1) * SECTOR_SIZE as u64),
+        };
+        // sparse where the platform allows it
+        f.set_len(len)?;
+        let mut img = Image::with_offset(f, offset);
+        img.write_sector(DIR_ROOT_ADR, &DirPage::default().encode())?;
+        img.flush()?;
+        Ok(Self { img })
+    }
+
+    /// Writes an inner-core boot file (as linked by ORL) into the boot
+    /// sectors, where BootLoad.LoadFromDisk reads it.
+    pub fn install_boot_file(&mut self, data: &[u8]) -> FsResult<()> {
+        if data.len() > MAX_BOOT_SIZE {
+            return Err(FsError::Invalid(format!(
+                "boot file is {} bytes, the boot area holds {MAX_BOOT_SIZE}",
+                data.len()
+            )));
+        }
+        for (i, chunk) in data.chunks(SECTOR_SIZE).enumerate() {
+            let mut s = [0u8; SECTOR_SIZE];
+            s[..chunk.len()].copy_from_slice(chunk);
+            self.img.write_sector((BOOT_FIRST_SECTOR + i as u32) * SECTOR_MULT, &s)?;
+        }
+        self.img.flush()
+    }
+
+    pub fn from_image(img: Image) -> Self {
+        Self { img }
+    }
+
+    pub fn image(&mut self) -> &mut Image {
+        &mut self.img
+    }
+
+    /// Directory entries in name order.
+    pub fn entries(&mut self) -> FsResult<Vec<DirEntry>> {
+        Ok(dir::walk(&mut self.img)?.0)
+    }
+
+    /// DiskAdrs of all directory pages, root first.
+    pub fn dir_pages(&mut self) -> FsResult<Vec<u32>> {
+        Ok(dir::walk(&mut self.img)?.1)
+    }
+
+    pub fn list(&mut self) -> FsResult<Vec<FileInfo>> {
+        self.entries()?
+            .into_iter()
+            .map(|e| {
+                let h = self.header(e.adr)?;
+                let length = h.length().ok_or_else(|| bad_length(&h))?;
+                Ok(FileInfo { name: e.name, adr: e.adr, length, date: h.date })
+            })
+            .collect()
+    }
+
+    /// DiskAdr of the header of `name`.
+    pub fn search(&mut self, name: &str) -> FsResult<Option<u32>> {
+        dir::search(&mut self.img, name)
+    }
+
+    pub fn header(&mut self, adr: u32) -> FsResult<FileHeader> {
+        FileHeader::decode(&self.img.read_sector(adr)?, adr)
+    }
+
+    /// DiskAdrs of sectors 0..=aleng of a file, and of its index sectors.
+    pub fn file_sectors(&mut self, h: &FileHeader) -> FsResult<(Vec<u32>, Vec<u32>)> {
+        let n = h.aleng as usize + 1;
+        if n > MAX_FILE_SECTORS {
+            return Err(bad_length(h));
+        }
+        let mut secs: Vec<u32> = h.sec[..n.min(SEC_TAB_SIZE)].to_vec();
+        let mut index = Vec::new();
+        let mut rest = n.saturating_sub(SEC_TAB_SIZE);
+        for &x in &h.ext {
+            if rest == 0 {
+                break;
+            }
+            let s = self.img.read_sector(x)?;
+            let k = rest.min(INDEX_SIZE);
+            secs.extend((0..k).map(|i| word(&s, i * 4)));
+            index.push(x);
+            rest -= k;
+        }
+        Ok((secs, index))
+    }
+
+    /// Sector map after Kernel.InitSecMap and FileDir.Init.
+    pub fn sector_map(&mut self) -> FsResult<SectorMap> {
+        let mut map = SectorMap::new();
+        let (entries, pages) = dir::walk(&mut self.img)?;
+        for adr in pages {
+            map.mark(adr)?;
+        }
+        for e in entries {
+            let h = self.header(e.adr)?;
+            let (secs, index) = self.file_sectors(&h)?;
+            map.mark(e.adr)?;
+            for adr in secs.into_iter().chain(index) {
+                map.mark(adr)?;
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn read_file(&mut self, name: &str) -> FsResult<Vec<u8>> {
+        let adr = self.search(name)?.ok_or_else(|| FsError::NotFound(name.to_string()))?;
+        let h = self.header(adr)?;
+        let len = h.length().ok_or_else(|| bad_length(&h))?;
+        let (secs, _) = self.file_sectors(&h)?;
+
+        let mut data = Vec::with_capacity(secs.len() * SECTOR_SIZE);
+        for (i, &adr) in secs.iter().enumerate() {
+            let s = self.img.read_sector(adr)?;
+            data.extend_from_slice(if i == 0 { &s[HEADER_SIZE..] } else { &s[..] });
+        }
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Creates or replaces file `name` (Files.Register semantics: the old
+    /// file's sectors are simply no longer referenced).
+    pub fn write_file(&mut self, name: &str, data: &[u8]) -> FsResult<()> {
+        self.write_file_dated(name, data, now())
+    }
+
+    pub fn write_file_dated(&mut self, name: &str, data: &[u8], date: u32) -> FsResult<()> {
+        check_name(name)?;
+        if data.len() > MAX_FILE_SIZE {
+            return Err(FsError::TooLarge(data.len()));
+        }
+        let mut map = self.sector_map()?;
+        let mut h = FileHeader::new(name, data.len(), date);
+        let n = h.aleng as usize + 1;
+
+        let secs = (0..n).map(|_| map.alloc()).collect::<FsResult<Vec<_>>>()?;
+        for (i, &adr) in secs.iter().take(SEC_TAB_SIZE).enumerate() {
+            h.sec[i] = adr;
+        }
+        for (x, chunk) in secs.get(SEC_TAB_SIZE..).unwrap_or(&[]).chunks(INDEX_SIZE).enumerate() {
+            let mut s = [0u8; SECTOR_SIZE];
+            for (i, &adr) in chunk.iter().enumerate() {
+                put_word(&mut s, i * 4, adr);
+            }
+            h.ext[x] = map.alloc()?;
+            self.img.write_sector(h.ext[x], &s)?;
+        }
+
+        // data sectors first, the header last
+        let first = SECTOR_SIZE - HEADER_SIZE;
+        for (i, &adr) in secs.iter().enumerate().skip(1) {
+            let start = first + (i - 1) * SECTOR_SIZE;
+            let mut s = [0u8; SECTOR_SIZE];
+            let end = (start + SECTOR_SIZE).min(data.len());
+            s[..end - start].copy_from_slice(&data[start..end]);
+            self.img.write_sector(adr, &s)?;
+        }
+        let mut s = [0u8; SECTOR_SIZE];
+        h.encode_into(&mut s);
+        let n0 = data.len().min(first);
+        s[HEADER_SIZE..HEADER_SIZE + n0].copy_from_slice(&data[..n0]);
+        self.img.write_sector(secs[0], &s)?;
+
+        self.insert(&mut map, name, secs[0])?;
+        self.img.flush()
+    }
+
+    /// Removes `name` from the directory (FileDir.Delete).
+    pub fn delete(&mut self, name: &str) -> FsResult<()> {
+        let mut map = self.sector_map()?;
+        let mut entries = self.entries()?;
+        let i = entries
+            .binary_search_by(|e| e.name.as_str().cmp(name))
+            .map_err(|_| FsError::NotFound(name.to_string()))?;
+        entries.remove(i);
+        dir::rebuild(&mut self.img, &mut map, entries)?;
+        self.img.flush()
+    }
+
+    /// Files.Rename: a file called `new` is replaced.
+    pub fn rename(&mut self, old: &str, new: &str) -> FsResult<()> {
+        check_name(new)?;
+        let mut map = self.sector_map()?;
+        let mut entries = self.entries()?;
+        let i = entries
+            .binary_search_by(|e| e.name.as_str().cmp(old))
+            .map_err(|_| FsError::NotFound(old.to_string()))?;
+        let adr = entries.remove(i).adr;
+
+        let mut s = self.img.read_sector(adr)?;
+        let mut h = FileHeader::decode(&s, adr)?;
+        h.name = new.to_string();
+        h.encode_into(&mut s);
+        self.img.write_sector(adr, &s)?;
+
+        insert_sorted(&mut entries, new, adr);
+        dir::rebuild(&mut self.img, &mut map, entries)?;
+        self.img.flush()
+    }
+
+    fn insert(&mut self, map: &mut SectorMap, name: &str, adr: u32) -> FsResult<()> {
+        let mut entries = self.entries()?;
+        insert_sorted(&mut entries, name, adr);
+        dir::rebuild(&mut self.img, map, entries)
+    }
+}
+
+fn insert_sorted(entries: &mut Vec<DirEntry>, name: &str, adr: u32) {
+    match entries.binary_search_by(|e| e.name.as_str().cmp(name)) {
+        Ok(i) => entries[i].adr = adr,
+        Err(i) => entries.insert(i, DirEntry { name: name.to_string(), adr, p: 0 }),
+    }
+}
+
+fn bad_length(h: &FileHeader) -> FsError {
+    FsError::Corrupt(format!("{}: aleng {} / bleng {} out of range", h.name, h.aleng, h.bleng))
+}
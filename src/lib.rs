@@ -1,4 +1,5 @@
 pub mod cpu;
+pub mod decode;
 pub mod fp;
 
 pub mod bus;
@@ -9,5 +10,10 @@ pub mod machine;
 pub use crate::machine::Machine;
 
 pub mod boot;
+pub mod debug;
 pub mod disasm;
+pub mod pclink;
+pub mod savestate;
+pub mod translate;
+pub mod fs;
 pub mod ui;
\ No newline at end of file
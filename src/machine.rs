@@ -1,14 +1,23 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use crate::boot::BOOTLOADER;
 use crate::bus::io_bus::IoBus;
 use crate::bus::system_bus::SystemBus;
 use crate::bus::{BusError, BusResult};
-use crate::cpu::Cpu;
+use crate::cpu::{Cpu, RunOutcome, StopReason};
+use crate::debug::{Access, History, WatchHit};
 use crate::devices;
-use crate::devices::disk::Disk;
+use crate::devices::clipboard::ClipboardDevice;
+use crate::devices::disk::{Disk, DiskMode};
+use crate::devices::leds::Leds;
+use crate::devices::overlay::Overlay;
+use crate::devices::panel::FrontPanel;
+use crate::devices::serial::{Serial, SerialBackend};
+use crate::devices::timer::{Timer, TimerMode};
 use crate::memory::framebuffer::Damage;
 use crate::memory::ram::Ram;
 use crate::memory::rom::Rom;
+use crate::pclink::{self, MachineLink};
+use crate::translate::{ExecMode, Translator};
 
 pub const DEFAULT_MEM_SIZE: u32 = 0x0010_0000;
 pub const DEFAULT_DISPLAY_START: u32 = 0x000E_7F00;
@@ -18,17 +27,33 @@ pub const ROM_START: u32 = 0xFFFF_F800;
 pub struct Machine {
     pub cpu: Cpu,
     pub bus: SystemBus,
+    /// LEDs and switches, shared with the devices on the `IoBus`.
+    pub panel: FrontPanel,
+    /// Used by `run`.
+    pub exec: ExecMode,
+    // pending writes of overlay disks, by SPI slot
+    pub(crate) overlays: [Option<Overlay>; 3],
+    // image and mode of attached disks, by SPI slot
+    pub(crate) disks: [Option<(PathBuf, DiskMode)>; 3],
+    pub(crate) translator: Translator,
 }
 
 impl Machine {
     pub fn new(fb_width_px: i32, fb_height: i32) -> Self {
+        Self::with_timer_mode(fb_width_px, fb_height, TimerMode::WallClock)
+    }
+
+    /// Like `new`, but with the millisecond timer driven by `mode`.
+    pub fn with_timer_mode(fb_width_px: i32, fb_height: i32, mode: TimerMode) -> Self {
         let fb_width_words = fb_width_px / 32;
         let ram = Ram::new(DEFAULT_MEM_SIZE);
         let rom = Rom::new(ROM_START, BOOTLOADER.to_vec());
 
-        let timer = Box::new(devices::timer::Timer::default());
-        let switches = Box::new(devices::switches::Switches::default());
-        let io = IoBus::new(IO_START, timer, switches);
+        let panel = FrontPanel::new();
+        let timer = Timer::new(mode);
+        let switches = Box::new(devices::switches::Switches::new(panel.clone()));
+        let mut io = IoBus::new(IO_START, timer, switches);
+        io.leds = Some(Box::new(Leds::new(panel.clone())));
 
         let bus = SystemBus::new(
             DEFAULT_MEM_SIZE,
@@ -44,7 +69,7 @@ impl Machine {
         let mut cpu = Cpu::default();
         cpu.reset();
 
-        Self { cpu, bus }
+        Self { cpu, bus, panel, exec: ExecMode::default(), overlays: Default::default(), disks: Default::default(), translator: Translator::default() }
     }
 
     pub fn new_for_tests(
@@ -56,17 +81,19 @@ impl Machine {
     ) -> Self {
         use crate::{
             bus::{io_bus::IoBus, system_bus::SystemBus},
-            devices::{switches::Switches, timer::Timer},
+            devices::switches::Switches,
             memory::{framebuffer::Damage, ram::Ram, rom::Rom},
         };
 
         let ram = Ram::new(mem_size);
         let rom = Rom::new(crate::machine::ROM_START, boot_rom_words);
 
-        let timer = Box::new(Timer::default());
-        let switches = Box::new(Switches::default());
+        let panel = FrontPanel::new();
+        let timer = Timer::new(TimerMode::deterministic());
+        let switches = Box::new(Switches::new(panel.clone()));
 
-        let io = IoBus::new(crate::machine::IO_START, timer, switches);
+        let mut io = IoBus::new(crate::machine::IO_START, timer, switches);
+        io.leds = Some(Box::new(Leds::new(panel.clone())));
 
         let bus = SystemBus::new(
             mem_size,
@@ -82,11 +109,66 @@ impl Machine {
         let mut cpu = crate::cpu::Cpu::default();
         cpu.reset();
 
-        Self { cpu, bus }
+        Self { cpu, bus, panel, exec: ExecMode::default(), overlays: Default::default(), disks: Default::default(), translator: Translator::default() }
+    }
+
+    /// Test machine running `prog` from RAM address 0. The boot ROM only
+    /// branches to itself; an 8x8 display takes the upper half of RAM.
+    pub fn new_for_tests_in_ram(prog: &[u32], mem_size: u32) -> Self {
+        use crate::bus::Bus;
+
+        let mut m = Self::new_for_tests(vec![0xE7FF_FFFF], mem_size, mem_size / 2, 8, 8);
+        for (i, w) in prog.iter().enumerate() {
+            m.bus.write_word(i as u32 * 4, *w).expect("program fits in RAM");
+        }
+        m.cpu.pc = 0;
+        m
     }
 }
 
 impl Machine {
+    /// Runs up to `cycles` instructions the way `exec` says; stops early
+    /// when the guest idles, faults or hits one of `cpu.breakpoints`.
+    pub fn run(&mut self, cycles: u32) -> RunOutcome {
+        self.translator.run(self.exec, &mut self.cpu, &mut self.bus, cycles)
+    }
+
+    /// Starts recording instructions for `step_back`, keeping the last
+    /// `limit`; `None` stops and forgets the recording.
+    pub fn set_history(&mut self, limit: Option<usize>) {
+        self.cpu.history = limit.map(|n| Box::new(History::new(n)));
+    }
+
+    /// Takes back the last recorded instruction; false when there is none.
+    pub fn step_back(&mut self) -> bool {
+        self.cpu.step_back(&mut self.bus).is_some()
+    }
+
+    /// Steps back at most `cycles` instructions, stopping where a forward
+    /// run would have stopped: before an instruction with a breakpoint, or
+    /// before a store a write or change watchpoint would report. Loads are
+    /// not recorded, so read watchpoints are passed over.
+    pub fn reverse_continue(&mut self, cycles: u32) -> RunOutcome {
+        for done in 0..cycles {
+            let Some(undone) = self.cpu.step_back(&mut self.bus) else {
+                return RunOutcome::new(done, StopReason::HistoryStart);
+            };
+            let watched = undone.stores.iter().find(|s| {
+                self.bus.watchpoints.matches(Access::Write, s.addr, s.size as u32, s.old, s.new)
+            });
+            if let Some(s) = watched {
+                let hit = WatchHit { pc: undone.pc, addr: s.addr, size: s.size, access: Access::Write, old: s.old, new: s.new };
+                return RunOutcome::new(done + 1, StopReason::Watchpoint(hit));
+            }
+            let view = self.cpu.view();
+            let bus = &mut self.bus;
+            if self.cpu.breakpoints.stops_at(&view, &mut |a| bus.peek_word_le(a).ok()) {
+                return RunOutcome::new(done + 1, StopReason::Breakpoint(view.pc));
+            }
+        }
+        RunOutcome::new(cycles, StopReason::Budget)
+    }
+
     pub fn mouse_moved(&mut self, x: i32, y: i32) {
         self.bus.io.input.mouse_moved(x, y);
     }
@@ -95,24 +177,80 @@ impl Machine {
         self.bus.io.input.mouse_button(button, down);
     }
 
-    pub fn keyboard_ps2(&mut self, bytes: &[u8]) {
-        let _ = self.bus.io.input.keyboard_input(bytes);
+    /// Queues PS/2 bytes; fails without queueing anything when the guest's
+    /// buffer cannot take all of them.
+    pub fn keyboard_ps2(&mut self, bytes: &[u8]) -> BusResult<()> {
+        self.bus.io.input.keyboard_input(bytes)
+    }
+
+    pub fn attach_serial(&mut self, backend: Box<dyn SerialBackend>) {
+        self.bus.io.serial = Some(Box::new(Serial::new(backend)));
+    }
+
+    pub fn detach_serial(&mut self) {
+        self.bus.io.serial = None;
+    }
+
+    pub fn attach_clipboard(&mut self, clipboard: ClipboardDevice) {
+        self.bus.io.clipboard = Some(Box::new(clipboard));
+    }
+
+    /// Pushes a host file into the running guest over PCLink.
+    pub fn pclink_push(&mut self, name: &str, data: &[u8]) -> BusResult<()> {
+        pclink::push(&mut MachineLink::attach(self), name, data)
+    }
+
+    /// Pulls a file out of the running guest over PCLink.
+    pub fn pclink_pull(&mut self, name: &str) -> BusResult<Vec<u8>> {
+        pclink::pull(&mut MachineLink::attach(self), name)
     }
 
-    pub fn attach_disk(&mut self, slot: usize, path: &Path) -> BusResult<()> {
+    /// Inserts the image at `path` as the SD card in `slot`; `mode` decides
+    /// whether guest writes reach the file. Writes held only in a memory
+    /// overlay of the disk there now must be committed or discarded first.
+    pub fn attach_disk(&mut self, slot: usize, path: &Path, mode: DiskMode) -> BusResult<()> {
         if slot != 1 && slot != 2 {
             return Err(BusError::Device(format!("disk slot must be 1 or 2, got {slot}")));
         }
-        let disk = Disk::new(Some(path))?;
+        self.check_overlay_saved(slot)?;
+        let disk = Disk::with_mode(path, mode.clone())?;
+        self.overlays[slot] = disk.overlay();
         self.bus.io.set_spi(slot, Box::new(disk))?;
+        self.disks[slot] = Some((std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()), mode));
         Ok(())
     }
 
+    /// Image file of the disk in `slot`.
+    pub fn disk_image(&self, slot: usize) -> Option<&Path> {
+        Some(self.disks.get(slot)?.as_ref()?.0.as_path())
+    }
+
+    /// Pending writes of the disk in `slot`, if it was attached with an overlay.
+    pub fn disk_overlay(&self, slot: usize) -> Option<&Overlay> {
+        self.overlays.get(slot)?.as_ref()
+    }
+
+    /// Fails if the disk in `slot` has writes only a memory overlay holds.
+    fn check_overlay_saved(&self, slot: usize) -> BusResult<()> {
+        match self.disk_overlay(slot).filter(|o| o.is_dirty() && o.sidecar_path().is_none()) {
+            Some(o) => Err(BusError::Device(format!(
+                "disk {slot} has {} uncommitted blocks; commit or discard them first",
+                o.dirty_blocks()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the disk in `slot`. Writes held only in a memory overlay must
+    /// be committed or discarded first.
     pub fn eject_disk(&mut self, slot: usize) -> BusResult<()> {
         if slot != 1 && slot != 2 {
             return Err(BusError::Device(format!("disk slot must be 1 or 2, got {slot}")));
         }
+        self.check_overlay_saved(slot)?;
         self.bus.io.clear_spi(slot)?; // vi laver den lige nedenfor
+        self.overlays[slot] = None;
+        self.disks[slot] = None;
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,7 +1,765 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use risc_emulator::cpu::StopReason;
+use risc_emulator::debug::{
+    AddrRange, BreakSpec, CallTracer, Coverage, DeviceBreak, OpClass, Profiler, Symbols, Timebase, TraceReader, TraceRecord,
+    TraceWriter, WatchSpec,
+};
+use risc_emulator::disasm::format_line;
+use risc_emulator::devices::disk::DiskMode;
+use risc_emulator::devices::timer::TimerMode;
+use risc_emulator::fs::fsck::FsckOptions;
+use risc_emulator::fs::{layout, FileSystem, Layout};
+use risc_emulator::pclink::{self, Link, StreamLink};
+use risc_emulator::translate::ExecMode;
 use risc_emulator::Machine;
 
-fn main() {
+type CliResult = Result<(), Box<dyn Error>>;
+
+#[derive(Parser, Debug)]
+#[command(about = "Headless RISC5 / Project Oberon emulator and tools")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the machine without a window and report how it stopped
+    Run(RunArgs),
+    /// Transfer files to or from a running Oberon (PCLink1.Run must be active)
+    Pclink(PclinkArgs),
+    /// List or change the files in a disk image without booting it
+    Fs(FsArgs),
+    /// Create a blank, formatted disk image
+    Mkimage(MkimageArgs),
+    /// Check the file system of a disk image for damage
+    Fsck(FsckArgs),
+    /// Print or compare instruction traces written by run --trace
+    Trace(TraceArgs),
+    /// Report or merge code coverage written by run --coverage
+    Coverage(CoverageArgs),
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Disk image for SPI1
+    #[arg(long)]
+    disk: Option<PathBuf>,
+
+    /// How disk writes are stored: rw, ro, overlay (discarded) or sidecar[:PATH]
+    #[arg(long, default_value = "rw")]
+    disk_mode: DiskMode,
+
+    /// How guest code runs: interp, translate or lockstep
+    #[arg(long, default_value = "interp")]
+    exec: ExecMode,
+
+    /// Instructions to run
+    #[arg(long, default_value_t = 100_000_000)]
+    instructions: u64,
+
+    /// Start from a save state (which brings its own disks)
+    #[arg(long, value_name = "FILE", conflicts_with = "disk")]
+    load_state: Option<PathBuf>,
+
+    /// Save the machine state to FILE when the run stops
+    #[arg(long, value_name = "FILE")]
+    save_state: Option<PathBuf>,
+
+    /// Record every executed instruction in FILE (runs interpreted)
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+
+    /// Profile the run (interpreted): print the instruction mix and hot
+    /// spots, and write folded call stacks for flamegraph.pl to FILE
+    #[arg(long, value_name = "FILE")]
+    profile: Option<PathBuf>,
+
+    /// With --profile: also total the instructions run in this range
+    #[arg(long = "profile-range", value_name = "NAME=FIRST-LAST", requires = "profile", value_parser = parse_named_range)]
+    profile_ranges: Vec<(String, AddrRange)>,
+
+    /// Write calls and returns (interpreted) to FILE as Chrome trace-event
+    /// JSON, for chrome://tracing or Perfetto
+    #[arg(long, value_name = "FILE")]
+    calls: Option<PathBuf>,
+
+    /// Time stamps of --calls: instructions (one per microsecond) or
+    /// virtual[:INSTRUCTIONS_PER_MS]
+    #[arg(long, default_value = "instructions", requires = "calls")]
+    calls_time: Timebase,
+
+    /// Record which RAM instructions ran and which way conditional branches
+    /// went (interpreted), and save it in FILE
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<PathBuf>,
+
+    /// Names for code addresses (one "ADDR NAME" per line, hex) for --profile
+    /// and --calls; names of Oberon modules and commands are found in memory
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+
+    /// Stop before the instruction at ADDR (hex), after passing it IGNORE
+    /// times, where COND holds; with "log MSG" print MSG and go on instead
+    #[arg(long = "break", value_name = "ADDR[:IGNORE] [if COND] [log MSG]")]
+    breaks: Vec<BreakSpec>,
+
+    /// Like --break, but removed once it stops
+    #[arg(long = "tbreak", value_name = "ADDR")]
+    tbreaks: Vec<BreakSpec>,
+
+    /// Stop after an access to LEN bytes (default 4) of RAM at ADDR (hex);
+    /// KIND is read, write (default), access or change
+    #[arg(long = "watch", value_name = "ADDR[+LEN][:KIND]")]
+    watches: Vec<WatchSpec>,
+
+    /// Stop on a device event: io:OFFSET[:KIND], sd[:CMD|*[:SECTOR]],
+    /// key[:BYTE] or fb:X,Y,W,H
+    #[arg(long = "break-on", value_name = "EVENT")]
+    device_breaks: Vec<DeviceBreak>,
+}
+
+#[derive(Args, Debug)]
+struct TraceArgs {
+    #[command(subcommand)]
+    op: TraceOp,
+}
+
+#[derive(Subcommand, Debug)]
+enum TraceOp {
+    /// Print records, disassembled
+    Show {
+        file: PathBuf,
+        /// Only instructions at these addresses (hex FIRST-LAST or ADDR)
+        #[arg(long, value_name = "RANGE")]
+        pc: Option<AddrRange>,
+        /// Only loads and stores in this range (hex FIRST-LAST or ADDR)
+        #[arg(long, value_name = "RANGE")]
+        addr: Option<AddrRange>,
+        /// Stop after printing N records
+        #[arg(long, value_name = "N")]
+        limit: Option<u64>,
+    },
+    /// Find the first record where two traces differ
+    Diff {
+        a: PathBuf,
+        b: PathBuf,
+        /// Common records to show before the difference
+        #[arg(long, default_value_t = 3)]
+        context: usize,
+    },
+}
+
+#[derive(Args, Debug)]
+struct CoverageArgs {
+    #[command(subcommand)]
+    op: CoverageOp,
+}
+
+#[derive(Subcommand, Debug)]
+enum CoverageOp {
+    /// Print coverage per module (or range); several files are merged
+    Report {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Report on this range instead of the modules
+        #[arg(long = "range", value_name = "NAME=FIRST-LAST", value_parser = parse_named_range)]
+        ranges: Vec<(String, AddrRange)>,
+        /// Also write an lcov tracefile
+        #[arg(long, value_name = "FILE")]
+        lcov: Option<PathBuf>,
+    },
+    /// Combine coverage files of runs with the same modules at the same places
+    Merge {
+        /// File to write
+        #[arg(short, long)]
+        out: PathBuf,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug)]
+struct FsckArgs {
+    image: PathBuf,
+
+    /// Fix problems and rewrite the directory
+    #[arg(long)]
+    repair: bool,
+
+    /// With --repair: put lost files back into the directory
+    #[arg(long, requires = "repair")]
+    recover_lost: bool,
+}
+
+#[derive(Args, Debug)]
+struct MkimageArgs {
+    /// Image file to create
+    image: PathBuf,
+
+    /// File system size, e.g. 8M or 65536K (at most 64M)
+    #[arg(long, default_value = "64M", value_parser = parse_size)]
+    size: u64,
+
+    /// full (whole SD card) or fs-only
+    #[arg(long, default_value = "fs-only")]
+    layout: Layout,
+
+    /// Inner-core boot file to install in the boot sectors
+    #[arg(long)]
+    boot: Option<PathBuf>,
+
+    /// Replace an existing file
+    #[arg(short, long)]
+    force: bool,
+}
+
+fn parse_named_range(s: &str) -> Result<(String, AddrRange), String> {
+    let (name, range) = s.split_once('=').ok_or_else(|| format!("expected NAME=RANGE, got \"{s}\""))?;
+    Ok((name.to_string(), range.parse()?))
+}
+
+/// Bytes, with an optional K, M or G suffix (powers of 1024).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (num, mult) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    num.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(mult))
+        .ok_or_else(|| format!("invalid size \"{s}\""))
+}
+
+#[derive(Args, Debug)]
+struct PclinkArgs {
+    /// Serial line of a running emulator started with --serial tcp:PORT
+    #[arg(long, value_name = "HOST:PORT", conflicts_with_all = ["unix", "disk"])]
+    tcp: Option<String>,
+
+    /// Serial line of a running emulator started with --serial unix:PATH
+    #[arg(long, value_name = "PATH", conflicts_with = "disk")]
+    unix: Option<PathBuf>,
+
+    /// Boot this image headlessly instead (its startup must run PCLink1)
+    #[arg(long)]
+    disk: Option<PathBuf>,
+
+    /// How --disk writes are stored: rw, ro, overlay (discarded) or sidecar[:PATH]
+    #[arg(long, default_value = "rw")]
+    disk_mode: DiskMode,
+
+    /// How --disk code runs: interp, translate or lockstep
+    #[arg(long, default_value = "interp")]
+    exec: ExecMode,
+
+    /// Instructions to run before talking to the booted image
+    #[arg(long, default_value_t = 500_000_000)]
+    boot_instructions: u64,
+
+    /// Seconds to wait for each reply over a socket
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+
+    #[command(subcommand)]
+    op: PclinkOp,
+}
+
+#[derive(Subcommand, Debug)]
+enum PclinkOp {
+    /// Check that the guest answers
+    Ping,
+    /// Copy host files into Oberon
+    Push {
+        files: Vec<PathBuf>,
+        /// Oberon name (only with a single file; default: host file name)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Copy Oberon files to the host
+    Pull {
+        names: Vec<String>,
+        /// Output directory
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+}
+
+#[derive(Args, Debug)]
+struct FsArgs {
+    /// Disk image (full SD card or filesystem-only)
+    image: PathBuf,
+
+    #[command(subcommand)]
+    op: FsOp,
+}
+
+#[derive(Subcommand, Debug)]
+enum FsOp {
+    /// List files
+    Ls {
+        /// Show length, date and header address
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Copy files out of the image
+    Get {
+        names: Vec<String>,
+        /// Output directory
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Copy host files into the image, replacing files of the same name
+    Put {
+        files: Vec<PathBuf>,
+        /// Oberon name (only with a single file; default: host file name)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Delete files
+    Rm { names: Vec<String> },
+    /// Rename a file (an existing file called NEW is replaced)
+    Mv { old: String, new: String },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let res = match cli.command {
+        None => smoke(),
+        Some(Command::Run(args)) => run_cmd(args),
+        Some(Command::Pclink(args)) => pclink_cmd(args),
+        Some(Command::Fs(args)) => fs_cmd(args),
+        Some(Command::Mkimage(args)) => mkimage_cmd(args),
+        Some(Command::Fsck(args)) => fsck_cmd(args),
+        Some(Command::Trace(args)) => trace_cmd(args),
+        Some(Command::Coverage(args)) => coverage_cmd(args),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn smoke() -> CliResult {
     let mut machine = Machine::new(1024, 768);
+    machine.cpu.run(&mut machine.bus, 1_000).into_result()?;
+    Ok(())
+}
+
+fn run_cmd(args: RunArgs) -> CliResult {
+    let mut machine = Machine::with_timer_mode(1024, 768, TimerMode::deterministic());
+    if let Some(disk) = &args.disk {
+        machine.attach_disk(1, disk, args.disk_mode.clone())?;
+    }
+    if let Some(state) = &args.load_state {
+        machine.load_state(state)?;
+    }
+    machine.exec = args.exec;
+    for b in &args.breaks {
+        b.apply(machine.cpu.breakpoints.insert(b.addr));
+    }
+    for b in &args.tbreaks {
+        b.apply(machine.cpu.breakpoints.insert_temporary(b.addr));
+    }
+    for w in &args.watches {
+        machine.bus.watchpoints.add(w.start, w.len, w.kind);
+    }
+    for &b in &args.device_breaks {
+        machine.bus.device_breaks.add(b);
+    }
+    let trace = match &args.trace {
+        Some(path) => {
+            let t = Arc::new(Mutex::new(TraceWriter::create(path)?));
+            machine.cpu.tracers.push(t.clone());
+            Some(t)
+        }
+        None => None,
+    };
+    let profile = args.profile.as_ref().map(|_| {
+        let p = Arc::new(Mutex::new(Profiler::new()));
+        machine.cpu.tracers.push(p.clone());
+        p
+    });
+    let coverage = args.coverage.as_ref().map(|_| {
+        let c = Arc::new(Mutex::new(Coverage::new(machine.bus.mem_size)));
+        machine.cpu.tracers.push(c.clone());
+        c
+    });
+    let calls = args.calls.as_ref().map(|_| {
+        let c = Arc::new(Mutex::new(CallTracer::new(args.calls_time, machine.bus.io.timer.instructions())));
+        machine.cpu.tracers.push(c.clone());
+        c
+    });
+
+    let mut total = 0u64;
+    let mut idle = 0u64;
+    let mut fault = None;
+    while total < args.instructions {
+        let out = machine.run((args.instructions - total).min(1_000_000) as u32);
+        total += out.instructions as u64;
+        for line in machine.cpu.breakpoints.take_log() {
+            println!("{line}");
+        }
+        match out.stop {
+            StopReason::Budget | StopReason::HistoryStart => {}
+            StopReason::Idle => idle += 1,
+            StopReason::Fault(f) => {
+                // the recordings below matter most when the run ends this way
+                fault = Some(f);
+                break;
+            }
+            StopReason::Breakpoint(pc) => {
+                let hits = machine.cpu.breakpoints.get(pc).map_or(1, |b| b.hits);
+                println!("breakpoint at {pc:08X} (hit {hits} times)");
+                break;
+            }
+            StopReason::Watchpoint(hit) => {
+                println!("watchpoint: {hit}");
+                break;
+            }
+            StopReason::Device(hit) => {
+                println!("device event: {hit}");
+                break;
+            }
+        }
+    }
+    if fault.is_some() {
+        println!("{total} instructions");
+    } else {
+        println!("{total} instructions, PC {:08X}, idle {idle} times", machine.cpu.pc);
+    }
+    if let Some(t) = trace {
+        let mut t = t.lock().unwrap();
+        t.finish()?;
+        println!("{} trace records", t.records());
+    }
+    let mut symbols = Symbols::new();
+    if let Some(path) = &args.symbols {
+        symbols.load_map(path)?;
+    }
+    if profile.is_some() || calls.is_some() || coverage.is_some() {
+        symbols.read_modules(&mut |a| machine.bus.peek_word_le(a).ok());
+    }
+    if let (Some(path), Some(p)) = (&args.profile, profile) {
+        let p = p.lock().unwrap();
+        print_profile(&p, &args.profile_ranges, &symbols, &machine);
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        p.write_folded(&mut out, &|entry| symbols.name(entry))?;
+        std::io::Write::flush(&mut out)?;
+    }
+    if let (Some(path), Some(c)) = (&args.coverage, coverage) {
+        let mut c = c.lock().unwrap();
+        c.scan(&mut |a| machine.bus.peek_word_le(a).ok(), &symbols);
+        c.save(path)?;
+        print_coverage(&c, &[]);
+    }
+    if let (Some(path), Some(c)) = (&args.calls, calls) {
+        let c = c.lock().unwrap();
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        c.write_json(&mut out, &symbols)?;
+        std::io::Write::flush(&mut out)?;
+        println!("{} calls and returns", c.events());
+    }
+    if let Some(f) = fault {
+        return Err(f.into());
+    }
+    if let Some(state) = &args.save_state {
+        machine.save_state(state)?;
+    }
+    Ok(())
+}
+
+fn print_profile(p: &Profiler, ranges: &[(String, AddrRange)], symbols: &Symbols, machine: &Machine) {
+    const TOP: usize = 20;
+    let total = p.total().max(1);
+    let pct = |n: u64| n as f64 * 100.0 / total as f64;
+    println!("instruction mix:");
+    for class in OpClass::ALL {
+        println!("  {:<12} {:>12}  {:5.1}%", class.to_string(), p.mix(class), pct(p.mix(class)));
+    }
+    if !ranges.is_empty() {
+        println!("ranges:");
+        for (name, n) in p.by_range(ranges) {
+            println!("  {name:<12} {n:>12}  {:5.1}%", pct(n));
+        }
+    }
+    println!("procedures (with callees / own):");
+    for proc in p.procedures().iter().take(TOP) {
+        println!("  {:08X} {:>12}  {:5.1}%  {:>12}  {}", proc.entry, proc.total, pct(proc.total), proc.own, symbols.name(proc.entry));
+    }
+    println!("hot spots:");
+    for (pc, s) in p.hot_spots().iter().take(TOP) {
+        let code = machine.bus.peek_word_le(*pc).map(|ir| format_line(*pc, ir)).unwrap_or_default();
+        let branch = if s.taken + s.not_taken > 0 { format!("  taken {}/{}", s.taken, s.count) } else { String::new() };
+        println!("  {:>12}  {:5.1}%  {code}{branch}", s.count, pct(s.count));
+    }
+}
+
+fn print_coverage(c: &Coverage, ranges: &[(String, AddrRange)]) {
+    let pct = |n: u32, of: u32| if of == 0 { "-".to_string() } else { format!("{:.0}%", n as f64 * 100.0 / of as f64) };
+    println!("{:<24} {:<17}  {:<21}  branch directions", "unit", "range", "instructions");
+    for u in c.report(ranges) {
+        println!(
+            "{:<24} {:08X}-{:08X}  {:>8}/{:<7} {:>4}  {:>8}/{:<7} {:>4}",
+            u.name,
+            u.range.first,
+            u.range.last,
+            u.executed,
+            u.instructions,
+            pct(u.executed, u.instructions),
+            u.directions,
+            2 * u.branches,
+            pct(u.directions, 2 * u.branches),
+        );
+    }
+}
+
+fn pclink_cmd(args: PclinkArgs) -> CliResult {
+    let timeout = Duration::from_secs(args.timeout);
+    if let Some(addr) = &args.tcp {
+        return pclink_op(&mut StreamLink::tcp(addr, timeout)?, &args.op);
+    }
+    #[cfg(unix)]
+    if let Some(path) = &args.unix {
+        return pclink_op(&mut StreamLink::unix(path, timeout)?, &args.op);
+    }
+    let Some(disk) = &args.disk else {
+        return Err("one of --tcp, --unix or --disk is required".into());
+    };
+
+    let mut machine = Machine::with_timer_mode(1024, 768, TimerMode::deterministic());
+    machine.attach_disk(1, disk, args.disk_mode.clone())?;
+    machine.exec = args.exec;
+    while machine.bus.io.timer.instructions() < args.boot_instructions {
+        machine.run(100_000).into_result()?;
+    }
+    let mut link = pclink::MachineLink::attach(&mut machine);
+    pclink_op(&mut link, &args.op)
+}
+
+fn pclink_op<L: Link>(link: &mut L, op: &PclinkOp) -> CliResult {
+    match op {
+        PclinkOp::Ping => {
+            pclink::ping(link)?;
+            println!("ok");
+        }
+        PclinkOp::Push { files, name } => {
+            if name.is_some() && files.len() != 1 {
+                return Err("--name needs exactly one file".into());
+            }
+            for file in files {
+                let target = match name {
+                    Some(n) => n.clone(),
+                    None => file
+                        .file_name()
+                        .and_then(|s| s.to_str())
+                        .ok_or_else(|| format!("bad file name {}", file.display()))?
+                        .to_string(),
+                };
+                let data = std::fs::read(file)?;
+                pclink::push(link, &target, &data)?;
+                println!("{} -> {target} ({} bytes)", file.display(), data.len());
+            }
+        }
+        PclinkOp::Pull { names, out } => {
+            for name in names {
+                let data = pclink::pull(link, name)?;
+                let path = out.join(name);
+                std::fs::write(&path, &data)?;
+                println!("{name} -> {} ({} bytes)", path.display(), data.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn fs_cmd(args: FsArgs) -> CliResult {
+    let mut fs = match args.op {
+        FsOp::Ls { .. } | FsOp::Get { .. } => FileSystem::open_read_only(&args.image)?,
+        _ => FileSystem::open(&args.image)?,
+    };
+    match args.op {
+        FsOp::Ls { long: false } => {
+            for e in fs.entries()? {
+                println!("{}", e.name);
+            }
+        }
+        FsOp::Ls { long: true } => {
+            for f in fs.list()? {
+                let (y, mo, d, h, mi, s) = layout::decode_date(f.date);
+                println!(
+                    "{:<32} {:>8}  {y:04}-{mo:02}-{d:02} {h:02}:{mi:02}:{s:02}  @{}",
+                    f.name, f.length, f.adr
+                );
+            }
+        }
+        FsOp::Get { names, out } => {
+            for name in names {
+                let data = fs.read_file(&name)?;
+                let path = out.join(&name);
+                std::fs::write(&path, &data)?;
+                println!("{name} -> {} ({} bytes)", path.display(), data.len());
+            }
+        }
+        FsOp::Put { files, name } => {
+            if name.is_some() && files.len() != 1 {
+                return Err("--name needs exactly one file".into());
+            }
+            for file in &files {
+                let target = match &name {
+                    Some(n) => n.clone(),
+                    None => file
+                        .file_name()
+                        .and_then(|s| s.to_str())
+                        .ok_or_else(|| format!("bad file name {}", file.display()))?
+                        .to_string(),
+                };
+                let data = std::fs::read(file)?;
+                fs.write_file(&target, &data)?;
+                println!("{} -> {target} ({} bytes)", file.display(), data.len());
+            }
+        }
+        FsOp::Rm { names } => {
+            for name in names {
+                fs.delete(&name)?;
+            }
+        }
+        FsOp::Mv { old, new } => fs.rename(&old, &new)?,
+    }
+    Ok(())
+}
+
+fn mkimage_cmd(args: MkimageArgs) -> CliResult {
+    let sectors = u32::try_from(args.size / layout::SECTOR_SIZE as u64).unwrap_or(u32::MAX);
+    let boot = args.boot.as_deref().map(std::fs::read).transpose()?;
+    let mut fs = FileSystem::create(&args.image, args.layout, sectors, args.force)?;
+    if let Some(data) = &boot {
+        fs.install_boot_file(data)?;
+    }
+    println!(
+        "{}: {sectors} sectors, {} layout{}",
+        args.image.display(),
+        args.layout,
+        if boot.is_some() { ", boot file installed" } else { "" }
+    );
+    Ok(())
+}
+
+fn fsck_cmd(args: FsckArgs) -> CliResult {
+    let mut fs = if args.repair { FileSystem::open(&args.image)? } else { FileSystem::open_read_only(&args.image)? };
+    let opts = FsckOptions { repair: args.repair, recover_lost: args.recover_lost };
+    let mut report = fs.fsck(opts)?;
+    for p in &report.problems {
+        println!("{p}");
+    }
+    if report.repaired {
+        println!("repaired");
+        report = fs.fsck(FsckOptions::default())?;
+        for p in &report.problems {
+            println!("after repair: {p}");
+        }
+    }
+    println!(
+        "{} files, {} directory pages, {} sectors in use, {} old file versions",
+        report.files, report.dir_pages, report.used_sectors, report.stale_headers
+    );
+    match report.problems.len() {
+        0 => Ok(()),
+        n => Err(format!("{n} problems found").into()),
+    }
+}
+
+fn trace_cmd(args: TraceArgs) -> CliResult {
+    match args.op {
+        TraceOp::Show { file, pc, addr, limit } => {
+            let mut shown = 0;
+            for (i, rec) in TraceReader::open(&file)?.enumerate() {
+                let rec = rec?;
+                if pc.is_some_and(|r| !r.contains(rec.pc)) {
+                    continue;
+                }
+                if addr.is_some_and(|r| !rec.mem.is_some_and(|m| r.contains(m.addr))) {
+                    continue;
+                }
+                if limit.is_some_and(|n| shown >= n) {
+                    break;
+                }
+                println!("{i:>10}  {rec}");
+                shown += 1;
+            }
+        }
+        TraceOp::Diff { a, b, context } => {
+            let (mut ta, mut tb) = (TraceReader::open(&a)?, TraceReader::open(&b)?);
+            let mut recent = VecDeque::new();
+            let mut i = 0u64;
+            loop {
+                let (ra, rb) = (ta.next().transpose()?, tb.next().transpose()?);
+                if ra == rb {
+                    let Some(rec) = ra else {
+                        println!("traces are identical ({i} records)");
+                        return Ok(());
+                    };
+                    if recent.len() == context {
+                        recent.pop_front();
+                    }
+                    if context > 0 {
+                        recent.push_back(rec);
+                    }
+                    i += 1;
+                    continue;
+                }
+                let first = i - recent.len() as u64;
+                for (j, rec) in recent.iter().enumerate() {
+                    println!("  {:>10}  {rec}", first + j as u64);
+                }
+                let show = |tag: &str, path: &PathBuf, rec: Option<TraceRecord>| match rec {
+                    Some(rec) => println!("{tag} {i:>10}  {rec}"),
+                    None => println!("{tag} {i:>10}  (end of {})", path.display()),
+                };
+                show("a", &a, ra);
+                show("b", &b, rb);
+                return Err(format!("traces differ at record {i}").into());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn load_coverage(files: &[PathBuf]) -> Result<Coverage, Box<dyn Error>> {
+    let mut cov = Coverage::load(&files[0])?;
+    for f in &files[1..] {
+        cov.merge(&Coverage::load(f)?)?;
+    }
+    Ok(cov)
+}
 
-    machine.cpu.run(&mut machine.bus, 1_000).unwrap();
-}
\ No newline at end of file
+fn coverage_cmd(args: CoverageArgs) -> CliResult {
+    match args.op {
+        CoverageOp::Report { files, ranges, lcov } => {
+            let cov = load_coverage(&files)?;
+            print_coverage(&cov, &ranges);
+            if let Some(path) = lcov {
+                let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+                cov.write_lcov(&mut out, &ranges)?;
+                std::io::Write::flush(&mut out)?;
+            }
+        }
+        CoverageOp::Merge { out, files } => {
+            load_coverage(&files)?.save(&out)?;
+            println!("{} files -> {}", files.len(), out.display());
+        }
+    }
+    Ok(())
+}
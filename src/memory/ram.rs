@@ -10,6 +10,14 @@ impl Ram {
         Self { bytes: vec![0; size_bytes as usize] }
     }
 
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
     pub fn len(&self) -> u32 {
         self.bytes.len() as u32
     }
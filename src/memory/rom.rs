@@ -11,6 +11,18 @@ impl Rom {
         Self { start_addr, words }
     }
 
+    pub fn start(&self) -> u32 {
+        self.start_addr
+    }
+
+    pub fn len_bytes(&self) -> u32 {
+        (self.words.len() as u32) * 4
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+
     pub fn contains(&self, addr: u32) -> bool {
         addr >= self.start_addr && addr < self.start_addr + (self.words.len() as u32) * 4
     }
@@ -0,0 +1,217 @@
+// src/pclink.rs
+//
+// Host side of the PCLink protocol spoken by PCLink1.Mod on the guest
+// (run `PCLink1.Run` in Oberon first).
+//
+//   REQ           -> ACK                                   (ping)
+//   REC name 0X   -> ACK, then per block: len data.. -> ACK,
+//                    after the last block (len < 255) one more ACK
+//   SND name 0X   -> ACK | NAK, then per block: len data.. <- ACK
+//
+// "REC"/"SND" are named from the guest's point of view.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::bus::{BusError, BusResult};
+use crate::devices::serial::{Serial, SerialPipe};
+use crate::devices::IoDevice;
+use crate::Machine;
+
+pub const REQ: u8 = 0x20;
+pub const REC: u8 = 0x21;
+pub const SND: u8 = 0x22;
+pub const ACK: u8 = 0x10;
+pub const NAK: u8 = 0x11;
+
+pub const BLOCK_LEN: usize = 255;
+/// FileDir.FnLength - 1
+pub const MAX_NAME_LEN: usize = 31;
+
+/// Byte transport to the guest's PCLink task.
+pub trait Link {
+    fn send(&mut self, bytes: &[u8]) -> BusResult<()>;
+    /// Next byte from the guest; errors when the guest stops answering.
+    fn recv(&mut self) -> BusResult<u8>;
+}
+
+fn err(msg: impl Into<String>) -> BusError {
+    BusError::Device(format!("pclink: {}", msg.into()))
+}
+
+/// Oberon file names: a letter followed by letters, digits and dots.
+pub fn check_name(name: &str) -> BusResult<()> {
+    let ok = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.');
+    if ok { Ok(()) } else { Err(err(format!("invalid Oberon file name \"{name}\""))) }
+}
+
+fn expect_ack<L: Link>(link: &mut L, what: &str) -> BusResult<()> {
+    match link.recv()? {
+        ACK => Ok(()),
+        NAK => Err(err(format!("{what}: refused by guest"))),
+        b => Err(err(format!("{what}: expected ACK, got 0x{b:02X}"))),
+    }
+}
+
+fn send_name<L: Link>(link: &mut L, code: u8, name: &str) -> BusResult<()> {
+    check_name(name)?;
+    let mut msg = Vec::with_capacity(name.len() + 2);
+    msg.push(code);
+    msg.extend_from_slice(name.as_bytes());
+    msg.push(0);
+    link.send(&msg)
+}
+
+/// Checks that a PCLink task is listening.
+pub fn ping<L: Link>(link: &mut L) -> BusResult<()> {
+    link.send(&[REQ])?;
+    expect_ack(link, "ping")
+}
+
+/// Stores `data` as file `name` on the guest.
+pub fn push<L: Link>(link: &mut L, name: &str, data: &[u8]) -> BusResult<()> {
+    send_name(link, REC, name)?;
+    expect_ack(link, name)?;
+
+    let mut rest = data;
+    loop {
+        let n = rest.len().min(BLOCK_LEN);
+        let mut block = Vec::with_capacity(n + 1);
+        block.push(n as u8);
+        block.extend_from_slice(&rest[..n]);
+        link.send(&block)?;
+        expect_ack(link, name)?;
+        rest = &rest[n..];
+        if n < BLOCK_LEN {
+            break;
+        }
+    }
+    // Files.Register done
+    expect_ack(link, name)
+}
+
+/// Fetches file `name` from the guest.
+pub fn pull<L: Link>(link: &mut L, name: &str) -> BusResult<Vec<u8>> {
+    send_name(link, SND, name)?;
+    match link.recv()? {
+        ACK => {}
+        NAK => return Err(err(format!("{name}: no such file on guest"))),
+        b => return Err(err(format!("{name}: expected ACK, got 0x{b:02X}"))),
+    }
+
+    let mut data = Vec::new();
+    loop {
+        let n = link.recv()? as usize;
+        for _ in 0..n {
+            data.push(link.recv()?);
+        }
+        link.send(&[ACK])?;
+        if n < BLOCK_LEN {
+            break;
+        }
+    }
+    Ok(data)
+}
+
+/// Link over a host stream (e.g. a TCP or Unix socket serial backend of a
+/// running emulator).
+pub struct StreamLink<S: Read + Write> {
+    stream: S,
+}
+
+impl<S: Read + Write> StreamLink<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+}
+
+impl StreamLink<std::net::TcpStream> {
+    pub fn tcp(addr: &str, timeout: Duration) -> BusResult<Self> {
+        let s = std::net::TcpStream::connect(addr).map_err(|e| err(format!("connect {addr}: {e}")))?;
+        s.set_read_timeout(Some(timeout)).map_err(|e| err(e.to_string()))?;
+        let _ = s.set_nodelay(true);
+        Ok(Self::new(s))
+    }
+}
+
+#[cfg(unix)]
+impl StreamLink<std::os::unix::net::UnixStream> {
+    pub fn unix(path: &std::path::Path, timeout: Duration) -> BusResult<Self> {
+        let s = std::os::unix::net::UnixStream::connect(path)
+            .map_err(|e| err(format!("connect {}: {e}", path.display())))?;
+        s.set_read_timeout(Some(timeout)).map_err(|e| err(e.to_string()))?;
+        Ok(Self::new(s))
+    }
+}
+
+impl<S: Read + Write> Link for StreamLink<S> {
+    fn send(&mut self, bytes: &[u8]) -> BusResult<()> {
+        self.stream
+            .write_all(bytes)
+            .and_then(|_| self.stream.flush())
+            .map_err(|e| err(e.to_string()))
+    }
+
+    fn recv(&mut self) -> BusResult<u8> {
+        let mut b = [0u8; 1];
+        match self.stream.read_exact(&mut b) {
+            Ok(()) => Ok(b[0]),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                Err(err("timeout waiting for guest"))
+            }
+            Err(e) => Err(err(e.to_string())),
+        }
+    }
+}
+
+/// Guest instructions to wait for a reply byte (~10 s at 25 MHz).
+pub const DEFAULT_REPLY_BUDGET: u64 = 250_000_000;
+const SLICE: u32 = 10_000;
+
+/// Link into an in-process `Machine`: temporarily replaces the serial device
+/// with a pipe and runs the CPU while waiting for replies. The previous
+/// serial device is restored on drop.
+pub struct MachineLink<'a> {
+    machine: &'a mut Machine,
+    pipe: SerialPipe,
+    saved: Option<Box<dyn IoDevice>>,
+    pub reply_budget: u64,
+}
+
+impl<'a> MachineLink<'a> {
+    pub fn attach(machine: &'a mut Machine) -> Self {
+        let pipe = SerialPipe::new();
+        let serial = Serial::new(Box::new(pipe.backend()));
+        let saved = machine.bus.io.serial.replace(Box::new(serial));
+        Self { machine, pipe, saved, reply_budget: DEFAULT_REPLY_BUDGET }
+    }
+}
+
+impl Link for MachineLink<'_> {
+    fn send(&mut self, bytes: &[u8]) -> BusResult<()> {
+        self.pipe.write(bytes);
+        Ok(())
+    }
+
+    fn recv(&mut self) -> BusResult<u8> {
+        let start = self.machine.bus.io.timer.instructions();
+        loop {
+            if let Some(b) = self.pipe.read_byte() {
+                return Ok(b);
+            }
+            if self.machine.bus.io.timer.instructions() - start > self.reply_budget {
+                return Err(err("guest did not answer (is PCLink1.Run active?)"));
+            }
+            self.machine.run(SLICE).into_result()?;
+        }
+    }
+}
+
+impl Drop for MachineLink<'_> {
+    fn drop(&mut self) {
+        self.machine.bus.io.serial = self.saved.take();
+    }
+}
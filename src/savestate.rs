@@ -0,0 +1,403 @@
+// src/savestate.rs
+//
+// Save states: the whole machine in one file, so a booted system can be
+// picked up again without booting it. All numbers are little-endian:
+//
+//   "RISCSAVE" version: u32
+//   { tag: [u8; 4], len: u32, data: [u8; len] }
+//
+// Sections of version 1:
+//
+//   "CPU "  PC, R0..R15, H, SPC, saved flags, progress: u32; flags: u8
+//           (N Z C V, interrupts enabled, interrupt mode, from bit 0)
+//   "MEM "  display start, framebuffer width in words, height: u32;
+//           RAM contents (the rest of the section)
+//   "ROM "  start: u32; ROM words
+//   "TIME"  mode: u8 (0 wall clock, 1 virtual), instructions per ms: u32,
+//           instructions: u64, tick: u32
+//   "PANL"  LEDs: u8, switches: u32
+//   "INPT"  mouse: u32, queued keys: u8, key buffer: [u8; 16]
+//   "SPI "  selected device: u32
+//   "DISK"  one per attached disk: slot: u8, mode: u8 (0 rw, 1 ro,
+//           2 overlay, 3 sidecar), image path, delta path (strings are
+//           u32 length + UTF-8), card state (u32 length + bytes, see
+//           `Disk`), overlay blocks: u32 count + { block: u32, [u8; 512] }
+//
+// Readers skip sections they don't know; changing the layout of a section
+// bumps the version. Disks are saved by reference: the image and any delta
+// file must still be there, unchanged, when the state is loaded. Only the
+// blocks of an in-memory overlay travel in the state file; loading refuses
+// to drop such blocks of the machine loaded into, so commit or discard them
+// first.
+//
+// Not saved: serial and clipboard backends (they belong to the host),
+// breakpoints and watchpoints, and the reverse-execution history, which
+// loading clears.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bus::{BusError, BusResult};
+use crate::devices::disk::{Disk, DiskMode};
+use crate::devices::input::Input;
+use crate::devices::timer::Timer;
+use crate::memory::ram::Ram;
+use crate::memory::rom::Rom;
+use crate::Machine;
+
+pub const MAGIC: &[u8; 8] = b"RISCSAVE";
+pub const VERSION: u32 = 1;
+
+fn err(msg: impl Into<String>) -> BusError {
+    BusError::Device(format!("save state: {}", msg.into()))
+}
+
+/// Builds section data.
+#[derive(Debug, Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    /// u32 length, then the bytes.
+    pub fn blob(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.bytes(v);
+    }
+
+    pub fn path(&mut self, p: &Path) -> BusResult<()> {
+        let s = p.to_str().ok_or_else(|| err(format!("path \"{}\" is not UTF-8", p.display())))?;
+        self.blob(s.as_bytes());
+        Ok(())
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads section data; running past the end is an error naming `what`.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], what: &'static str) -> Self {
+        Self { data, what }
+    }
+
+    pub fn take(&mut self, n: usize) -> BusResult<&'a [u8]> {
+        if n > self.data.len() {
+            return Err(err(format!("{} is cut short", self.what)));
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> BusResult<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> BusResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> BusResult<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u32(&mut self) -> BusResult<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> BusResult<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    pub fn blob(&mut self) -> BusResult<&'a [u8]> {
+        let n = self.u32()? as usize;
+        self.take(n)
+    }
+
+    pub fn path(&mut self) -> BusResult<PathBuf> {
+        let what = self.what;
+        let s = std::str::from_utf8(self.blob()?).map_err(|_| err(format!("bad path in {what}")))?;
+        Ok(PathBuf::from(s))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Whatever is left.
+    pub fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+}
+
+fn section(out: &mut Vec<u8>, tag: &[u8; 4], w: Writer) {
+    let data = w.into_bytes();
+    out.extend_from_slice(tag);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(&data);
+}
+
+fn mode_code(mode: &DiskMode) -> u8 {
+    match mode {
+        DiskMode::ReadWrite => 0,
+        DiskMode::ReadOnly => 1,
+        DiskMode::Overlay => 2,
+        DiskMode::Sidecar(_) => 3,
+    }
+}
+
+impl Machine {
+    /// Writes the machine state to `path` (see `savestate` for the format).
+    pub fn save_state(&self, path: &Path) -> BusResult<()> {
+        let bytes = self.state_bytes()?;
+        fs::write(path, bytes).map_err(|e| err(format!("can't write \"{}\": {e}", path.display())))
+    }
+
+    /// Replaces the machine state with the one saved in `path`. Nothing
+    /// changes when the file can't be used or a disk has writes that exist
+    /// only in memory.
+    pub fn load_state(&mut self, path: &Path) -> BusResult<()> {
+        let bytes = fs::read(path).map_err(|e| err(format!("can't read \"{}\": {e}", path.display())))?;
+        self.restore_state(&bytes)
+    }
+
+    /// The machine state as a save state file.
+    pub fn state_bytes(&self) -> BusResult<Vec<u8>> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        let cpu = &self.cpu;
+        let mut w = Writer::default();
+        for v in [cpu.pc].iter().chain(&cpu.r).chain(&[cpu.h, cpu.spc, cpu.sflags, cpu.progress]) {
+            w.u32(*v);
+        }
+        let flags = [cpu.n, cpu.z, cpu.c, cpu.v, cpu.int_enabled, cpu.int_mode];
+        w.u8(flags.iter().enumerate().fold(0, |f, (i, &b)| f | (b as u8) << i));
+        section(&mut out, b"CPU ", w);
+
+        let bus = &self.bus;
+        let mut w = Writer::default();
+        w.u32(bus.display_start);
+        w.u32(bus.fb_width_words as u32);
+        w.u32(bus.fb_height as u32);
+        w.bytes(bus.ram.as_bytes());
+        section(&mut out, b"MEM ", w);
+
+        let mut w = Writer::default();
+        w.u32(bus.rom.start());
+        for &word in bus.rom.words() {
+            w.u32(word);
+        }
+        section(&mut out, b"ROM ", w);
+
+        let mut w = Writer::default();
+        bus.io.timer.save_state(&mut w);
+        section(&mut out, b"TIME", w);
+
+        let mut w = Writer::default();
+        w.u8(self.panel.leds());
+        w.u32(self.panel.switches());
+        section(&mut out, b"PANL", w);
+
+        let mut w = Writer::default();
+        bus.io.input.save_state(&mut w);
+        section(&mut out, b"INPT", w);
+
+        let mut w = Writer::default();
+        w.u32(bus.io.spi_selected);
+        section(&mut out, b"SPI ", w);
+
+        for slot in 1..=2 {
+            let (Some((image, mode)), Some(dev)) = (&self.disks[slot], &bus.io.spi[slot]) else {
+                continue;
+            };
+            let mut w = Writer::default();
+            w.u8(slot as u8);
+            w.u8(mode_code(mode));
+            w.path(image)?;
+            match mode {
+                DiskMode::Sidecar(delta) => w.path(delta)?,
+                _ => w.blob(&[]),
+            }
+            w.blob(&dev.save_state().unwrap_or_default());
+            let blocks = match (mode, &self.overlays[slot]) {
+                (DiskMode::Overlay, Some(o)) => o.blocks(),
+                _ => Vec::new(),
+            };
+            w.u32(blocks.len() as u32);
+            for (block, data) in blocks {
+                w.u32(block);
+                w.bytes(&data);
+            }
+            section(&mut out, b"DISK", w);
+        }
+        Ok(out)
+    }
+
+    /// Like `load_state`, from the bytes of a save state file.
+    pub fn restore_state(&mut self, bytes: &[u8]) -> BusResult<()> {
+        for slot in 1..=2 {
+            if let Some(o) = self.disk_overlay(slot).filter(|o| o.is_dirty() && o.sidecar_path().is_none()) {
+                return Err(err(format!(
+                    "disk {slot} has {} uncommitted blocks; commit or discard them first",
+                    o.dirty_blocks()
+                )));
+            }
+        }
+
+        let mut r = Reader::new(bytes, "header");
+        if r.take(8).ok() != Some(MAGIC.as_slice()) {
+            return Err(err("not a save state file"));
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(err(format!("version {version} is not supported (expected {VERSION})")));
+        }
+
+        let mut sections: Vec<([u8; 4], &[u8])> = Vec::new();
+        while !r.is_empty() {
+            let tag = r.array::<4>()?;
+            let data = r.blob()?;
+            sections.push((tag, data));
+        }
+        let find = |tag: &[u8; 4], what: &'static str| {
+            sections
+                .iter()
+                .find(|(t, _)| t == tag)
+                .map(|&(_, data)| Reader::new(data, what))
+                .ok_or_else(|| err(format!("no {what} section")))
+        };
+
+        // everything is read and checked before the machine is touched
+        let mut cpu = find(b"CPU ", "CPU")?;
+        let mut regs = [0u32; 21];
+        for v in &mut regs {
+            *v = cpu.u32()?;
+        }
+        let flags = cpu.u8()?;
+
+        let mut mem = find(b"MEM ", "memory")?;
+        let display_start = mem.u32()?;
+        let fb_width_words = mem.u32()? as i32;
+        let fb_height = mem.u32()? as i32;
+        if (fb_width_words, fb_height) != (self.bus.fb_width_words, self.bus.fb_height) {
+            return Err(err(format!(
+                "saved with a {}x{} display, this machine has {}x{}",
+                fb_width_words * 32,
+                fb_height,
+                self.bus.fb_width_words * 32,
+                self.bus.fb_height
+            )));
+        }
+        let ram = mem.rest();
+        if ram.len() % 4 != 0 || ram.len() > u32::MAX as usize || (display_start as usize) > ram.len() {
+            return Err(err("bad memory section"));
+        }
+
+        let mut rom = find(b"ROM ", "ROM")?;
+        let rom_start = rom.u32()?;
+        let rom = rom.rest();
+        if rom.len() % 4 != 0 || rom_start % 4 != 0 || rom_start as u64 + rom.len() as u64 > 1 << 32 {
+            return Err(err("bad ROM section"));
+        }
+        if !rom.is_empty() && (rom_start as usize) < ram.len() {
+            return Err(err(format!("ROM at {rom_start:08X} overlaps RAM")));
+        }
+        let rom_words: Vec<u32> = rom.chunks_exact(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect();
+
+        let timer = Timer::load_state(&mut find(b"TIME", "timer")?)?;
+        let mut panel = find(b"PANL", "panel")?;
+        let (leds, switches) = (panel.u8()?, panel.u32()?);
+        let input = Input::load_state(&mut find(b"INPT", "input")?)?;
+        let spi_selected = find(b"SPI ", "SPI")?.u32()?;
+
+        let mut disks = Vec::new();
+        for (_, data) in sections.iter().filter(|(t, _)| t == b"DISK") {
+            let mut d = Reader::new(data, "disk");
+            let slot = d.u8()? as usize;
+            if slot != 1 && slot != 2 {
+                return Err(err(format!("disk in slot {slot}")));
+            }
+            let code = d.u8()?;
+            let image = d.path()?;
+            let delta = d.path()?;
+            let mode = match code {
+                0 => DiskMode::ReadWrite,
+                1 => DiskMode::ReadOnly,
+                2 => DiskMode::Overlay,
+                3 => DiskMode::Sidecar(delta),
+                _ => return Err(err(format!("unknown disk mode {code}"))),
+            };
+            let mut disk = Disk::with_mode(&image, mode.clone())?;
+            disk.load_state(d.blob()?)?;
+            let overlay = disk.overlay();
+            for _ in 0..d.u32()? {
+                let block = d.u32()?;
+                let data = d.array::<512>()?;
+                if let Some(o) = &overlay {
+                    o.write(block, &data)?;
+                }
+            }
+            disks.push((slot, disk, image, mode));
+        }
+
+        let c = &mut self.cpu;
+        c.pc = regs[0];
+        c.r.copy_from_slice(&regs[1..17]);
+        [c.h, c.spc, c.sflags, c.progress] = [regs[17], regs[18], regs[19], regs[20]];
+        [c.n, c.z, c.c, c.v, c.int_enabled, c.int_mode] = std::array::from_fn(|i| flags & (1 << i) != 0);
+        if let Some(h) = c.history.as_deref_mut() {
+            h.clear();
+        }
+
+        self.bus.replace_memory(Ram::from_bytes(ram.to_vec()), Rom::new(rom_start, rom_words), display_start);
+        self.translator.flush();
+
+        let io = &mut self.bus.io;
+        io.timer = timer;
+        io.input = input;
+        io.spi_selected = spi_selected;
+        self.panel.write_leds(leds);
+        self.panel.set_switches(switches);
+
+        for slot in 1..=2 {
+            io.spi[slot] = None;
+            self.overlays[slot] = None;
+            self.disks[slot] = None;
+        }
+        for (slot, disk, image, mode) in disks {
+            self.overlays[slot] = disk.overlay();
+            io.spi[slot] = Some(Box::new(disk));
+            self.disks[slot] = Some((image, mode));
+        }
+        Ok(())
+    }
+}
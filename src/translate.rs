@@ -0,0 +1,509 @@
+// src/translate.rs
+//
+// Basic-block translator. A straight-line run of RISC5 code (up to and
+// including the first branch) becomes a list of closures with registers,
+// immediates and return addresses bound at translation time. Blocks leave
+// early, with PC and timer exactly where the interpreter would have them:
+//
+//   - after any load/store outside RAM (IO, ROM data), so `progress`
+//     and device state are seen between instructions;
+//   - after a store that hits translated code;
+//   - before an instruction with a breakpoint (`Cpu::breakpoints`).
+//
+// With interrupts enabled the interpreter runs instead, since it samples
+// the IRQ line before every instruction; likewise while any watchpoint or
+// device breakpoint is set, as blocks access RAM without going through the
+// CPU bus, and while instructions are recorded for reverse execution or
+// traced.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::bus::system_bus::SystemBus;
+use crate::bus::{Bus, BusError, BusResult, CpuBus};
+use crate::cpu::{Cpu, CpuView, Fault, RunOutcome, StopReason};
+use crate::debug::Breakpoints;
+use crate::decode::{Decoded, Op, IMM};
+
+/// Longest block, in instructions.
+const MAX_BLOCK: usize = 64;
+
+/// How `Machine::run` executes guest code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecMode {
+    /// One `Cpu::step` per instruction.
+    #[default]
+    Interpret,
+    /// Translated blocks.
+    Translate,
+    /// Translated blocks, each checked against the interpreter.
+    Lockstep,
+}
+
+impl FromStr for ExecMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interp" | "interpret" => Ok(ExecMode::Interpret),
+            "translate" => Ok(ExecMode::Translate),
+            "lockstep" => Ok(ExecMode::Lockstep),
+            _ => Err(format!("unknown execution mode \"{s}\" (interp, translate or lockstep)")),
+        }
+    }
+}
+
+impl fmt::Display for ExecMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExecMode::Interpret => "interp",
+            ExecMode::Translate => "translate",
+            ExecMode::Lockstep => "lockstep",
+        })
+    }
+}
+
+enum Flow {
+    Next,
+    /// PC is set, leave the block.
+    Exit,
+}
+
+type OpFn = Box<dyn Fn(&mut Cpu, &mut SystemBus) -> BusResult<Flow>>;
+
+struct Inst {
+    run: OpFn,
+    // may touch IO: the timer must be current before it runs
+    mem: bool,
+    // for fault records
+    d: Decoded,
+}
+
+struct Block {
+    start: u32,
+    ops: Vec<Inst>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Ram(usize),
+    Rom(usize),
+}
+
+/// Translated blocks of one machine, by start address.
+#[derive(Default)]
+pub struct Translator {
+    ram: Vec<Option<Box<Block>>>,
+    rom: Vec<Option<Box<Block>>>,
+    live: Vec<Slot>,
+    epoch: u64,
+    bp_generation: u64,
+}
+
+impl fmt::Debug for Translator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Translator")
+            .field("blocks", &self.live.len())
+            .finish()
+    }
+}
+
+impl Translator {
+    /// Number of translated blocks.
+    pub fn blocks(&self) -> usize {
+        self.live.len()
+    }
+
+    pub fn flush(&mut self) {
+        for slot in self.live.drain(..) {
+            match slot {
+                Slot::Ram(i) => self.ram[i] = None,
+                Slot::Rom(i) => self.rom[i] = None,
+            }
+        }
+    }
+
+    /// Runs at most `cycles` instructions, like `Cpu::run`.
+    pub fn run(&mut self, mode: ExecMode, cpu: &mut Cpu, bus: &mut SystemBus, cycles: u32) -> RunOutcome {
+        cpu.progress = 20;
+        bus.take_debug_hit();
+        let mut done = 0;
+        while done < cycles {
+            // ahead of the idle stop: the next run passes over its first PC
+            if done > 0 && !cpu.breakpoints.is_empty() && cpu.breakpoint_hit(bus) {
+                return RunOutcome::new(done, StopReason::Breakpoint(cpu.pc));
+            }
+            if cpu.progress == 0 {
+                return RunOutcome::new(done, StopReason::Idle);
+            }
+            let left = cycles - done;
+            let ran = match mode {
+                ExecMode::Interpret => Ok(0),
+                _ if cpu.int_enabled && !cpu.int_mode => Ok(0),
+                _ if bus.debug_checks() || cpu.recording() => Ok(0),
+                ExecMode::Translate => self.run_block(cpu, bus, left),
+                ExecMode::Lockstep => self.run_lockstep(cpu, bus, left),
+            };
+            let ran = match ran {
+                Ok(0) => {
+                    let pc = cpu.pc;
+                    if let Err(f) = cpu.try_step(bus) {
+                        return RunOutcome::new(done, StopReason::Fault(f));
+                    }
+                    if let Some(hit) = bus.take_debug_hit() {
+                        return RunOutcome::new(done + 1, StopReason::debug_hit(hit, pc));
+                    }
+                    Ok(1)
+                }
+                other => other,
+            };
+            match ran {
+                Ok(n) => done += n,
+                Err((n, f)) => return RunOutcome::new(done + n, StopReason::Fault(f)),
+            }
+        }
+        RunOutcome::new(done, StopReason::Budget)
+    }
+
+    fn slot(&mut self, bus: &SystemBus, pc: u32) -> Option<Slot> {
+        if pc & 3 != 0 {
+            return None;
+        }
+        if pc < bus.mem_size {
+            let words = (bus.mem_size / 4) as usize;
+            if self.ram.len() != words {
+                self.ram.resize_with(words, || None);
+            }
+            return Some(Slot::Ram((pc / 4) as usize));
+        }
+        if bus.rom.contains(pc) {
+            let words = (bus.rom.len_bytes() / 4) as usize;
+            if self.rom.len() != words {
+                self.rom.resize_with(words, || None);
+            }
+            return Some(Slot::Rom(((pc - bus.rom.start()) / 4) as usize));
+        }
+        None
+    }
+
+    fn block(&self, slot: Slot) -> Option<&Block> {
+        match slot {
+            Slot::Ram(i) => self.ram[i].as_deref(),
+            Slot::Rom(i) => self.rom[i].as_deref(),
+        }
+    }
+
+    /// The block at PC, translated if needed. `None` where there is
+    /// nothing to translate (IO space, unaligned PC).
+    fn lookup(&mut self, bus: &mut SystemBus, pc: u32, bps: &Breakpoints) -> Result<Option<Slot>, Ran> {
+        if bus.code_epoch() != self.epoch || bps.generation() != self.bp_generation {
+            self.epoch = bus.code_epoch();
+            self.bp_generation = bps.generation();
+            self.flush();
+        }
+        let Some(slot) = self.slot(bus, pc) else {
+            return Ok(None);
+        };
+        if self.block(slot).is_none() {
+            let block = Self::translate(bus, pc, bps).map_err(|error| (0, Fault { pc, ir: None, addr: pc, error }))?;
+            let block = Some(Box::new(block));
+            match slot {
+                Slot::Ram(i) => self.ram[i] = block,
+                Slot::Rom(i) => self.rom[i] = block,
+            }
+            self.live.push(slot);
+        }
+        Ok(Some(slot))
+    }
+
+    fn translate(bus: &mut SystemBus, start: u32, bps: &Breakpoints) -> BusResult<Block> {
+        let in_ram = start < bus.mem_size;
+        let mut ops = Vec::new();
+        let mut addr = start;
+        let mut progress = 0;
+        while ops.len() < MAX_BLOCK {
+            if addr != start && bps.contains(addr) {
+                break;
+            }
+            if (in_ram && addr >= bus.mem_size) || (!in_ram && !bus.rom.contains(addr)) {
+                break;
+            }
+            let d = match bus.fetch_decoded(addr, &mut progress) {
+                Ok(d) => d,
+                // the interpreter reports it if execution gets there
+                Err(_) if addr != start => break,
+                Err(e) => return Err(e),
+            };
+            if in_ram {
+                bus.mark_translated(addr);
+            }
+            ops.push(compile(d, addr));
+            addr = addr.wrapping_add(4);
+            if matches!(d.op, Op::Br | Op::BrLink | Op::IntCtl) {
+                break;
+            }
+        }
+        Ok(Block { start, ops })
+    }
+
+    fn run_block(&mut self, cpu: &mut Cpu, bus: &mut SystemBus, left: u32) -> Result<u32, Ran> {
+        match self.lookup(bus, cpu.pc, &cpu.breakpoints)? {
+            Some(slot) => exec(self.block(slot).unwrap(), cpu, bus, left),
+            None => Ok(0),
+        }
+    }
+
+    /// Runs the block on a shadow of the machine with the interpreter
+    /// first, then translated, and compares. Instructions that touch IO
+    /// can't be repeated, so those are left to the interpreter alone.
+    fn run_lockstep(&mut self, cpu: &mut Cpu, bus: &mut SystemBus, left: u32) -> Result<u32, Ran> {
+        let Some(slot) = self.lookup(bus, cpu.pc, &cpu.breakpoints)? else {
+            return Ok(0);
+        };
+        let block = self.block(slot).unwrap();
+        let n = block.ops.len().min(left as usize);
+
+        let mut shadow = ShadowBus { bus, step: 0, writes: Vec::new() };
+        let mut reference = cpu.clone();
+        let mut states = Vec::with_capacity(n);
+        while states.len() < n {
+            shadow.step = states.len();
+            if reference.step(&mut shadow).is_err() {
+                break;
+            }
+            states.push(reference.view());
+        }
+        let writes = std::mem::take(&mut shadow.writes);
+        if states.is_empty() {
+            return Ok(0);
+        }
+
+        let start = cpu.pc;
+        let ran = exec(block, cpu, bus, states.len() as u32)?;
+        let last = &block.ops[ran as usize - 1].d;
+        let mismatch = |msg: String| {
+            let pc = start.wrapping_add(4 * (ran - 1));
+            (ran, Fault { pc, ir: Some(last.ir), addr: pc, error: BusError::Device(msg) })
+        };
+        let expected = &states[ran as usize - 1];
+        let got = cpu.view();
+        if !same_state(expected, &got) {
+            return Err(mismatch(format!(
+                "lockstep mismatch in block {start:08X}: interpreter {expected:?}, translated {got:?}"
+            )));
+        }
+        let mut memory = BTreeMap::new();
+        for &(step, addr, value) in &writes {
+            if step < ran as usize {
+                memory.insert(addr, value);
+            }
+        }
+        for (addr, value) in memory {
+            let actual = bus.peek_word_le(addr).ok();
+            if actual != Some(value) {
+                return Err(mismatch(format!(
+                    "lockstep mismatch in block {start:08X}: word {addr:08X} is {actual:08X?}, interpreter wrote {value:08X}"
+                )));
+            }
+        }
+        Ok(ran)
+    }
+}
+
+fn same_state(a: &CpuView, b: &CpuView) -> bool {
+    (a.pc, a.r, a.h, a.z, a.n, a.c, a.v, a.int_enabled, a.int_mode)
+        == (b.pc, b.r, b.h, b.z, b.n, b.c, b.v, b.int_enabled, b.int_mode)
+}
+
+/// Instructions completed before a fault, and the fault.
+type Ran = (u32, Fault);
+
+/// Runs up to `left` instructions of `block`; returns how many ran.
+fn exec(block: &Block, cpu: &mut Cpu, bus: &mut SystemBus, left: u32) -> Result<u32, Ran> {
+    let n = block.ops.len().min(left as usize) as u32;
+    let mut clock = 0;
+    for (i, inst) in block.ops[..n as usize].iter().enumerate() {
+        let retired = i as u32 + 1;
+        if inst.mem {
+            bus.advance_clock(retired - clock);
+            clock = retired;
+        }
+        match (inst.run)(cpu, bus) {
+            Ok(Flow::Next) => {}
+            Ok(Flow::Exit) => {
+                bus.advance_clock(retired - clock);
+                return Ok(retired);
+            }
+            Err(e) => {
+                cpu.pc = block.start.wrapping_add(4 * retired);
+                bus.advance_clock(retired - clock);
+                return Err((retired - 1, cpu.fault(cpu.pc.wrapping_sub(4), &inst.d, e)));
+            }
+        }
+    }
+    bus.advance_clock(n - clock);
+    cpu.pc = block.start.wrapping_add(4 * n);
+    Ok(n)
+}
+
+fn inst(mem: bool, f: impl Fn(&mut Cpu, &mut SystemBus) -> BusResult<Flow> + 'static) -> Inst {
+    Inst { run: Box::new(f), mem, d: Decoded::EMPTY }
+}
+
+/// `a := f(b, c)` with `c` a register or bound immediate.
+fn alu(d: &Decoded, f: impl Fn(&mut Cpu, u32, u32) -> u32 + Copy + 'static) -> Inst {
+    let (a, b) = (d.a as usize, d.b as usize);
+    if d.c == IMM {
+        let k = d.imm;
+        inst(false, move |cpu, _| {
+            let v = f(cpu, cpu.r[b], k);
+            cpu.set_reg(a, v);
+            Ok(Flow::Next)
+        })
+    } else {
+        let c = d.c as usize;
+        inst(false, move |cpu, _| {
+            let v = f(cpu, cpu.r[b], cpu.r[c]);
+            cpu.set_reg(a, v);
+            Ok(Flow::Next)
+        })
+    }
+}
+
+fn load(d: &Decoded, next: u32, byte: bool) -> Inst {
+    let (a, b, off) = (d.a as usize, d.b as usize, d.imm);
+    inst(true, move |cpu, bus| {
+        let addr = cpu.r[b].wrapping_add(off);
+        if addr < bus.mem_size {
+            let w = bus.ram.read_word_le(addr & !3)?;
+            let v = if byte { (w >> ((addr & 3) * 8)) & 0xFF } else { w };
+            cpu.set_reg(a, v);
+            return Ok(Flow::Next);
+        }
+        cpu.pc = next;
+        let v = if byte { cpu.load_byte(bus, addr)? as u32 } else { cpu.load_word(bus, addr)? };
+        cpu.set_reg(a, v);
+        Ok(Flow::Exit)
+    })
+}
+
+fn store(d: &Decoded, next: u32, byte: bool) -> Inst {
+    let (a, b, off) = (d.a as usize, d.b as usize, d.imm);
+    inst(true, move |cpu, bus| {
+        let addr = cpu.r[b].wrapping_add(off);
+        if addr < bus.mem_size {
+            let epoch = bus.code_epoch();
+            if byte {
+                Bus::write_byte(bus, addr, cpu.r[a] as u8)?;
+            } else {
+                Bus::write_word(bus, addr, cpu.r[a])?;
+            }
+            if bus.code_epoch() == epoch {
+                return Ok(Flow::Next);
+            }
+        } else if byte {
+            cpu.store_byte(bus, addr, cpu.r[a] as u8)?;
+        } else {
+            cpu.store_word(bus, addr, cpu.r[a])?;
+        }
+        cpu.pc = next;
+        Ok(Flow::Exit)
+    })
+}
+
+fn branch(d: &Decoded, next: u32) -> Inst {
+    let cond = d.a;
+    let link = d.op == Op::BrLink;
+    if d.c == IMM {
+        let target = next.wrapping_add(d.imm);
+        inst(false, move |cpu, _| {
+            cpu.pc = next;
+            if cpu.condition(cond) {
+                if link {
+                    cpu.set_reg(15, next);
+                }
+                cpu.pc = target;
+            }
+            Ok(Flow::Exit)
+        })
+    } else {
+        let c = d.c as usize;
+        inst(false, move |cpu, _| {
+            cpu.pc = next;
+            if cpu.condition(cond) {
+                if link {
+                    cpu.set_reg(15, next);
+                }
+                cpu.pc = cpu.r[c];
+            }
+            Ok(Flow::Exit)
+        })
+    }
+}
+
+fn compile(d: Decoded, addr: u32) -> Inst {
+    let next = addr.wrapping_add(4);
+    let inst = match d.op {
+        Op::Mov => alu(&d, |_, _, c| c),
+        Op::Lsl => alu(&d, |_, b, c| b << (c & 31)),
+        Op::Asr => alu(&d, |_, b, c| ((b as i32) >> (c & 31)) as u32),
+        Op::Ror => alu(&d, |_, b, c| b.rotate_right(c & 31)),
+        Op::And => alu(&d, |_, b, c| b & c),
+        Op::Ann => alu(&d, |_, b, c| b & !c),
+        Op::Ior => alu(&d, |_, b, c| b | c),
+        Op::Xor => alu(&d, |_, b, c| b ^ c),
+        Op::Add => alu(&d, |cpu, b, c| {
+            let a = b.wrapping_add(c);
+            cpu.c = a < b;
+            cpu.v = (((a ^ c) & (a ^ b)) >> 31) != 0;
+            a
+        }),
+        Op::Sub => alu(&d, |cpu, b, c| {
+            let a = b.wrapping_sub(c);
+            cpu.c = a > b;
+            cpu.v = (((b ^ c) & (a ^ b)) >> 31) != 0;
+            a
+        }),
+        Op::Ldw => load(&d, next, false),
+        Op::Ldb => load(&d, next, true),
+        Op::Stw => store(&d, next, false),
+        Op::Stb => store(&d, next, true),
+        Op::Br | Op::BrLink => branch(&d, next),
+        // the rest is rare enough for the interpreter's handler
+        _ => {
+            let exits = d.op == Op::IntCtl;
+            inst(false, move |cpu, bus| {
+                cpu.pc = next;
+                cpu.execute(bus, &d)?;
+                Ok(if exits { Flow::Exit } else { Flow::Next })
+            })
+        }
+    };
+    Inst { d, ..inst }
+}
+
+/// RAM and ROM of a machine with writes held back; IO is refused.
+struct ShadowBus<'a> {
+    bus: &'a SystemBus,
+    step: usize,
+    // (instruction index, address, value)
+    writes: Vec<(usize, u32, u32)>,
+}
+
+impl CpuBus for ShadowBus<'_> {
+    fn read_word_for_cpu(&mut self, addr: u32, _progress: &mut u32) -> BusResult<u32> {
+        if let Some(&(_, _, v)) = self.writes.iter().rev().find(|w| w.1 == addr) {
+            return Ok(v);
+        }
+        self.bus.peek_word_le(addr)
+    }
+
+    fn write_word(&mut self, addr: u32, value: u32) -> BusResult<()> {
+        if addr >= self.bus.mem_size {
+            return Err(BusError::Unmapped(addr));
+        }
+        self.writes.push((self.step, addr, value));
+        Ok(())
+    }
+}
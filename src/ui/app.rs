@@ -1,10 +1,20 @@
-use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use eframe::egui;
 use crate::bus::BusResult;
+use crate::devices::clipboard::ClipboardDevice;
+use crate::devices::disk::DiskMode;
+use crate::devices::overlay::Overlay;
+use crate::devices::serial::SerialBackend;
+use crate::devices::timer::TimerMode;
+use crate::cpu::StopReason;
+use crate::debug::{Profiler, Symbols, Tracer};
+use crate::translate::ExecMode;
 use crate::Machine;
 
-use super::{cpu_panel, debugger, framebuffer, topbar};
+use super::profile::{ProfileView, SortBy};
+use super::{cpu_panel, debugger, framebuffer, input, topbar};
 
 const CPU_HZ: u32 = 25_000_000;
 const FPS: u32 = 60;
@@ -18,10 +28,10 @@ pub(crate) struct EmuState {
     pub(crate) machine: Machine,
     pub(crate) running: bool,
     pub(crate) cycles_per_frame: u32,
-    pub(crate) run_to_target: Option<u32>,
-    pub(crate) breakpoints: HashSet<u32>,
     pub disk1_path: Option<std::path::PathBuf>,
     pub disk2_path: Option<std::path::PathBuf>,
+    /// Mode used when attaching disks.
+    pub disk_mode: DiskMode,
     pub last_error: Option<String>,
 }
 
@@ -43,23 +53,57 @@ pub(crate) struct UiState {
     pub(crate) follow_pc: bool,
     pub(crate) disasm_scroll_to_pc: bool,
     pub(crate) cursor_pc: Option<u32>,
+    // new breakpoint, watchpoint and device breakpoint, in the syntax of
+    // `run --break`/`--watch`/`--break-on`
+    pub(crate) break_input: String,
+    pub(crate) watch_input: String,
+    pub(crate) device_break_input: String,
 
     // right panel tabs
     pub(crate) right_tab: RightTab,
+
+    // logpoint output, newest last
+    pub(crate) bp_log: VecDeque<String>,
+
+    // profile tab; the profile stays for viewing after it is stopped
+    pub(crate) profile: Option<Arc<Mutex<Profiler>>>,
+    pub(crate) profiling: bool,
+    pub(crate) profile_view: ProfileView,
+    pub(crate) profile_sort: SortBy,
+    // names for the procedures table, read again on start, stop and refresh
+    pub(crate) profile_symbols: Symbols,
+
+    // host keyboard/mouse -> guest
+    pub(crate) input: input::InputState,
+
+    // window close held back until overlays are committed or discarded
+    pub(crate) confirm_exit: bool,
+    pub(crate) exit_confirmed: bool,
+    // save state to load once in-memory overlays are committed or discarded
+    pub(crate) confirm_load: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum RightTab {
     Cpu,
     Breakpoints,
+    Panel,
+    Profile,
 }
 
 impl EmuApp {
-    pub fn new(fb_w: usize, fb_h: usize, disk1: Option<PathBuf>, disk2: Option<PathBuf>) -> Self {
+    pub fn new(
+        fb_w: usize,
+        fb_h: usize,
+        disk1: Option<PathBuf>,
+        disk2: Option<PathBuf>,
+        disk_mode: DiskMode,
+        timer_mode: TimerMode,
+    ) -> Self {
         /*let fb_w = 1024;
         let fb_h = 768;*/
 
-        let mut machine = Machine::new(fb_w as i32, fb_h as i32);
+        let mut machine = Machine::with_timer_mode(fb_w as i32, fb_h as i32, timer_mode);
 
         let mut app =
             Self {
@@ -67,10 +111,9 @@ impl EmuApp {
                     machine,
                     running: false,
                     cycles_per_frame: CPU_HZ / FPS,
-                    run_to_target: None,
-                    breakpoints: HashSet::new(),
                     disk1_path: None,
                     disk2_path: None,
+                    disk_mode,
                     last_error: None,
 
                 },
@@ -90,11 +133,33 @@ impl EmuApp {
                     follow_pc: true,
                     disasm_scroll_to_pc: true,
                     cursor_pc: None,
+                    break_input: String::new(),
+                    watch_input: String::new(),
+                    device_break_input: String::new(),
 
                     right_tab: RightTab::Cpu,
+                    bp_log: VecDeque::new(),
+
+                    profile: None,
+                    profiling: false,
+                    profile_view: ProfileView::HotSpots,
+                    profile_sort: SortBy::default(),
+                    profile_symbols: Symbols::new(),
+
+                    input: input::InputState::default(),
+
+                    confirm_exit: false,
+                    exit_confirmed: false,
+                    confirm_load: None,
                 },
             };
 
+        let (clipboard, fallback) = ClipboardDevice::system_or_memory();
+        app.emu.machine.attach_clipboard(clipboard);
+        if let Some(e) = fallback {
+            app.emu.last_error = Some(format!("{e}; using in-memory clipboard"));
+        }
+
         if let Some(p) = disk1 {
             if let Err(e) = app.attach_disk(1, &p) {
                 app.emu.last_error = Some(format!("Attach disk1 failed: {e:?}"));
@@ -114,8 +179,25 @@ impl EmuApp {
 
     }
 
-    fn attach_disk(&mut self, slot: usize, path: &Path) -> BusResult<()> {
-        self.emu.machine.attach_disk(slot, path)?;
+    pub fn set_switches(&mut self, value: u32) {
+        self.emu.machine.panel.set_switches(value);
+    }
+
+    pub fn set_exec_mode(&mut self, mode: ExecMode) {
+        self.emu.machine.exec = mode;
+    }
+
+    /// Records the last `limit` instructions for stepping back.
+    pub fn set_history(&mut self, limit: Option<usize>) {
+        self.emu.machine.set_history(limit);
+    }
+
+    pub fn attach_serial(&mut self, backend: Box<dyn SerialBackend>) {
+        self.emu.machine.attach_serial(backend);
+    }
+
+    pub(crate) fn attach_disk(&mut self, slot: usize, path: &Path) -> BusResult<()> {
+        self.emu.machine.attach_disk(slot, path, self.emu.disk_mode.clone())?;
         match slot {
             1 => self.emu.disk1_path = Some(path.to_path_buf()),
             2 => self.emu.disk2_path = Some(path.to_path_buf()),
@@ -124,13 +206,131 @@ impl EmuApp {
         Ok(())
     }
 
+    pub(crate) fn save_state(&mut self, path: &Path) {
+        if let Err(e) = self.emu.machine.save_state(path) {
+            self.emu.last_error = Some(format!("Save state failed: {e}"));
+        }
+    }
+
+    /// Loads a save state, with the disks it was saved with.
+    pub(crate) fn load_state(&mut self, path: &Path) {
+        self.emu.running = false;
+        if !self.memory_overlays().is_empty() {
+            self.ui.confirm_load = Some(path.to_path_buf());
+            return;
+        }
+        match self.emu.machine.load_state(path) {
+            Ok(()) => {
+                self.emu.disk1_path = self.emu.machine.disk_image(1).map(Path::to_path_buf);
+                self.emu.disk2_path = self.emu.machine.disk_image(2).map(Path::to_path_buf);
+                self.ui.cursor_pc = Some(self.pc_aligned());
+                self.ui.disasm_scroll_to_pc = true;
+            }
+            Err(e) => self.emu.last_error = Some(format!("Load state failed: {e}")),
+        }
+    }
 
     pub(crate) fn pc_aligned(&self) -> u32 {
         self.emu.machine.cpu.view().pc & !3
     }
 
+    /// Stops at `addr` once (run to cursor).
+    pub(crate) fn run_to(&mut self, addr: u32) {
+        self.emu.machine.cpu.breakpoints.insert_temporary(addr & !3);
+        self.emu.running = true;
+    }
+
     pub(crate) fn step_instructions(&mut self, n: u32) {
-        let _ = self.emu.machine.cpu.run(&mut self.emu.machine.bus, n);
+        let out = self.emu.machine.cpu.run(&mut self.emu.machine.bus, n);
+        self.collect_bp_log();
+        match out.stop {
+            StopReason::Fault(f) => self.emu.last_error = Some(format!("CPU fault: {f}")),
+            StopReason::Watchpoint(hit) => self.emu.last_error = Some(format!("Watchpoint: {hit}")),
+            StopReason::Device(hit) => self.emu.last_error = Some(format!("Device event: {hit}")),
+            _ => {}
+        }
+    }
+
+    /// Takes back up to `n` recorded instructions.
+    pub(crate) fn step_back(&mut self, n: u32) {
+        self.emu.running = false;
+        for _ in 0..n {
+            if !self.emu.machine.step_back() {
+                self.emu.last_error = Some("Start of recorded history".into());
+                break;
+            }
+        }
+    }
+
+    /// Goes back to the previous breakpoint or watched store.
+    pub(crate) fn reverse_continue(&mut self) {
+        self.emu.running = false;
+        let out = self.emu.machine.reverse_continue(u32::MAX);
+        match out.stop {
+            StopReason::Watchpoint(hit) => self.emu.last_error = Some(format!("Watchpoint: {hit}")),
+            StopReason::HistoryStart => self.emu.last_error = Some("Start of recorded history".into()),
+            _ => {}
+        }
+    }
+
+    /// Counts instructions into the current profile (a new one if none).
+    pub(crate) fn start_profile(&mut self) {
+        if self.ui.profiling {
+            return;
+        }
+        let p = self.ui.profile.get_or_insert_with(|| Arc::new(Mutex::new(Profiler::new())));
+        self.emu.machine.cpu.tracers.push(p.clone());
+        self.ui.profiling = true;
+        self.ui.profile_symbols = self.symbols();
+    }
+
+    pub(crate) fn stop_profile(&mut self) {
+        if let Some(p) = &self.ui.profile {
+            let p: Arc<Mutex<dyn Tracer>> = p.clone();
+            self.emu.machine.cpu.tracers.retain(|t| !Arc::ptr_eq(t, &p));
+        }
+        self.ui.profiling = false;
+        self.ui.profile_symbols = self.symbols();
+    }
+
+    pub(crate) fn reset_profile(&mut self) {
+        let profiling = self.ui.profiling;
+        self.stop_profile();
+        self.ui.profile = None;
+        if profiling {
+            self.start_profile();
+        }
+    }
+
+    /// Writes the call stacks for flamegraph.pl.
+    pub(crate) fn export_profile(&mut self, path: &Path) {
+        let Some(p) = &self.ui.profile else {
+            self.emu.last_error = Some("Nothing profiled yet".into());
+            return;
+        };
+        let symbols = self.symbols();
+        let res = std::fs::File::create(path).and_then(|f| {
+            let mut out = std::io::BufWriter::new(f);
+            p.lock().unwrap().write_folded(&mut out, &|entry| symbols.name(entry))?;
+            std::io::Write::flush(&mut out)
+        });
+        if let Err(e) = res {
+            self.emu.last_error = Some(format!("Export profile failed: {e}"));
+        }
+    }
+
+    /// Names of the Oberon modules and commands now in memory.
+    pub(crate) fn symbols(&self) -> Symbols {
+        let mut symbols = Symbols::new();
+        symbols.read_modules(&mut |a| self.emu.machine.bus.peek_word_le(a).ok());
+        symbols
+    }
+
+    fn collect_bp_log(&mut self) {
+        const KEEP: usize = 500;
+        self.ui.bp_log.extend(self.emu.machine.cpu.breakpoints.take_log());
+        let excess = self.ui.bp_log.len().saturating_sub(KEEP);
+        self.ui.bp_log.drain(..excess);
     }
 
     pub(crate) fn read_word_at(&mut self, addr: u32) -> Option<u32> {
@@ -146,26 +346,35 @@ impl EmuApp {
         }
 
         let mut remaining = self.emu.cycles_per_frame;
-
+        // an idle guest would end `run` early; keep going for the whole frame
         while remaining > 0 {
-            let pc = self.pc_aligned();
-
-            if self.emu.run_to_target == Some(pc) {
-                self.emu.running = false;
-                self.emu.run_to_target = None;
-                break;
-            }
-
-            if self.emu.breakpoints.contains(&pc) {
-                self.emu.running = false;
-                self.emu.run_to_target = None;
-                break;
+            let out = self.emu.machine.run(remaining);
+            remaining -= out.instructions;
+            match out.stop {
+                StopReason::Budget | StopReason::Idle | StopReason::HistoryStart => {}
+                StopReason::Breakpoint(_) => {
+                    self.emu.running = false;
+                    break;
+                }
+                StopReason::Watchpoint(hit) => {
+                    self.emu.last_error = Some(format!("Watchpoint: {hit}"));
+                    self.emu.running = false;
+                    break;
+                }
+                StopReason::Device(hit) => {
+                    self.emu.last_error = Some(format!("Device event: {hit}"));
+                    self.emu.running = false;
+                    break;
+                }
+                StopReason::Fault(f) => {
+                    self.emu.last_error = Some(format!("CPU fault: {f}"));
+                    self.emu.running = false;
+                    break;
+                }
             }
-
-            self.step_instructions(1);
-            remaining -= 1;
         }
 
+        self.collect_bp_log();
         if self.ui.follow_pc {
             self.ui.cursor_pc = Some(self.pc_aligned());
         }
@@ -179,10 +388,121 @@ impl eframe::App for EmuApp {
         // 1) emulator tick
         self.tick(ctx);
 
+        if ctx.input(|i| i.viewport().close_requested()) && !self.ui.exit_confirmed && self.has_dirty_overlays() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.ui.confirm_exit = true;
+        }
+
         // 2) UI layout
         topbar::show(ctx, self);
         debugger::show(ctx, self);
         cpu_panel::show(ctx, self);
         framebuffer::show(ctx, self);
+        if self.ui.confirm_exit {
+            self.exit_dialog(ctx);
+        }
+        if self.ui.confirm_load.is_some() {
+            self.load_dialog(ctx);
+        }
+    }
+}
+
+impl EmuApp {
+    fn has_dirty_overlays(&self) -> bool {
+        (1..=2).any(|slot| self.emu.machine.disk_overlay(slot).is_some_and(|o| o.is_dirty()))
+    }
+
+    /// Dirty overlays that live only in memory; loading a state drops them.
+    fn memory_overlays(&self) -> Vec<Overlay> {
+        (1..=2)
+            .filter_map(|slot| self.emu.machine.disk_overlay(slot))
+            .filter(|o| o.is_dirty() && o.sidecar_path().is_none())
+            .cloned()
+            .collect()
+    }
+
+    fn load_dialog(&mut self, ctx: &egui::Context) {
+        let dirty = self.memory_overlays();
+        egui::Window::new("Uncommitted disk writes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("Loading the save state drops these writes:");
+                for o in &dirty {
+                    ui.label(format!("{}: {} blocks", o.image().display(), o.dirty_blocks()));
+                }
+                ui.horizontal(|ui| {
+                    let mut load = false;
+                    if ui.button("Commit").clicked() {
+                        load = self.commit_all(&dirty);
+                    }
+                    if ui.button("Discard").clicked() {
+                        for o in &dirty {
+                            let _ = o.discard();
+                        }
+                        load = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.ui.confirm_load = None;
+                    }
+                    if load {
+                        if let Some(path) = self.ui.confirm_load.take() {
+                            self.load_state(&path);
+                        }
+                    }
+                });
+            });
+    }
+
+    /// Commits every overlay; false, with the error shown, if one failed.
+    fn commit_all(&mut self, overlays: &[Overlay]) -> bool {
+        let mut ok = true;
+        for o in overlays {
+            if let Err(e) = o.commit() {
+                self.emu.last_error = Some(format!("Commit failed: {e}"));
+                ok = false;
+            }
+        }
+        ok
+    }
+
+    fn exit_dialog(&mut self, ctx: &egui::Context) {
+        let dirty: Vec<_> = (1..=2)
+            .filter_map(|slot| self.emu.machine.disk_overlay(slot).filter(|o| o.is_dirty()).cloned())
+            .collect();
+        let all_sidecar = dirty.iter().all(|o| o.sidecar_path().is_some());
+
+        egui::Window::new("Uncommitted disk writes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                for o in &dirty {
+                    ui.label(format!("{}: {} blocks", o.image().display(), o.dirty_blocks()));
+                }
+                ui.horizontal(|ui| {
+                    let mut quit = false;
+                    if ui.button("Commit").clicked() {
+                        quit = self.commit_all(&dirty);
+                    }
+                    if ui.button("Discard").clicked() {
+                        for o in &dirty {
+                            let _ = o.discard();
+                        }
+                        quit = true;
+                    }
+                    if all_sidecar && ui.button("Keep delta files").clicked() {
+                        quit = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.ui.confirm_exit = false;
+                    }
+                    if quit {
+                        self.ui.exit_confirmed = true;
+                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                    }
+                });
+            });
     }
 }
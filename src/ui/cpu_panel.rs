@@ -1,6 +1,8 @@
 use eframe::egui;
 
 use super::app::{EmuApp, RightTab};
+use super::profile;
+use crate::debug::{BreakSpec, DeviceBreak, WatchSpec};
 
 pub fn show(ctx: &egui::Context, app: &mut EmuApp) {
     egui::SidePanel::right("right")
@@ -10,12 +12,16 @@ pub fn show(ctx: &egui::Context, app: &mut EmuApp) {
             ui.horizontal(|ui| {
                 ui.selectable_value(&mut app.ui.right_tab, RightTab::Cpu, "CPU");
                 ui.selectable_value(&mut app.ui.right_tab, RightTab::Breakpoints, "BPs");
+                ui.selectable_value(&mut app.ui.right_tab, RightTab::Panel, "Panel");
+                ui.selectable_value(&mut app.ui.right_tab, RightTab::Profile, "Profile");
             });
             ui.separator();
 
             match app.ui.right_tab {
                 RightTab::Cpu => cpu(ui, app),
                 RightTab::Breakpoints => breakpoints(ui, app),
+                RightTab::Panel => front_panel(ui, app),
+                RightTab::Profile => profile::show(ui, app),
             }
         });
 }
@@ -30,6 +36,7 @@ fn cpu(ui: &mut egui::Ui, app: &mut EmuApp) {
         "Flags: N={} Z={} C={} V={}",
         v.n as u8, v.z as u8, v.c as u8, v.v as u8
     ));
+    ui.monospace(format!("IRQ  : E={} M={}", v.int_enabled as u8, v.int_mode as u8));
 
     ui.separator();
     ui.heading("Registers");
@@ -43,18 +50,43 @@ fn cpu(ui: &mut egui::Ui, app: &mut EmuApp) {
 fn breakpoints(ui: &mut egui::Ui, app: &mut EmuApp) {
     ui.heading("Breakpoints");
 
-    let mut bps: Vec<u32> = app.emu.breakpoints.iter().copied().collect();
-    bps.sort_unstable();
+    let bps: Vec<_> = app.emu.machine.cpu.breakpoints.iter().cloned().collect();
 
-    egui::ScrollArea::vertical().show(ui, |ui| {
+    egui::ScrollArea::vertical().id_salt("bp_list").max_height(240.0).show(ui, |ui| {
         if bps.is_empty() {
             ui.label("No breakpoints.");
             return;
         }
 
-        for addr in bps {
+        for bp in bps {
+            let addr = bp.addr;
             ui.horizontal(|ui| {
+                let mut enabled = bp.enabled;
+                if ui.checkbox(&mut enabled, "").changed() {
+                    app.emu.machine.cpu.breakpoints.set_enabled(addr, enabled);
+                }
                 ui.monospace(format!("0x{addr:08X}"));
+                ui.monospace(format!("hits {}", bp.hits)).on_hover_text("Times reached while enabled");
+                if bp.temporary {
+                    ui.label("temp");
+                }
+                if let Some(cond) = &bp.condition {
+                    ui.monospace(format!("if {cond}"));
+                }
+                if let Some(msg) = &bp.log {
+                    ui.monospace(format!("log {msg}")).on_hover_text("Logpoint: prints instead of stopping");
+                }
+
+                let mut ignore = bp.ignore;
+                if ui
+                    .add(egui::DragValue::new(&mut ignore).prefix("ignore ").range(0..=1_000_000))
+                    .on_hover_text("Hits to pass over before stopping")
+                    .changed()
+                {
+                    if let Some(bp) = app.emu.machine.cpu.breakpoints.get_mut(addr) {
+                        bp.ignore = ignore;
+                    }
+                }
 
                 if ui.small_button("Go").clicked() {
                     app.ui.cursor_pc = Some(addr);
@@ -63,12 +95,12 @@ fn breakpoints(ui: &mut egui::Ui, app: &mut EmuApp) {
                 }
 
                 if ui.small_button("Run to").clicked() {
-                    app.emu.run_to_target = Some(addr & !3);
-                    app.emu.running = true;
+                    app.emu.machine.cpu.breakpoints.set_enabled(addr, true);
+                    app.run_to(addr);
                 }
 
                 if ui.small_button("Remove").clicked() {
-                    app.emu.breakpoints.remove(&addr);
+                    app.emu.machine.cpu.breakpoints.remove(addr);
                 }
             });
         }
@@ -76,7 +108,135 @@ fn breakpoints(ui: &mut egui::Ui, app: &mut EmuApp) {
 
     ui.add_space(6.0);
 
-    if ui.button("Clear all").clicked() {
-        app.emu.breakpoints.clear();
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut app.ui.break_input).desired_width(220.0).hint_text("ADDR [if R0 == 1CH] [log {R1:x}]"))
+            .on_hover_text("Hex address, optional :IGNORE, condition over R0..R15, PC, H, N Z C V, [mem], b[mem], hits; log fields in {}");
+        if ui.button("Add").clicked() {
+            match app.ui.break_input.trim().parse::<BreakSpec>() {
+                Ok(b) => {
+                    b.apply(app.emu.machine.cpu.breakpoints.insert(b.addr));
+                    app.ui.break_input.clear();
+                }
+                Err(e) => app.emu.last_error = Some(format!("Breakpoint: {e}")),
+            }
+        }
+        if ui.button("Clear all").clicked() {
+            app.emu.machine.cpu.breakpoints.clear();
+        }
+    });
+
+    if !app.ui.bp_log.is_empty() {
+        ui.horizontal(|ui| {
+            ui.label("Log");
+            if ui.small_button("Clear").clicked() {
+                app.ui.bp_log.clear();
+            }
+        });
+        egui::ScrollArea::vertical().id_salt("bp_log").max_height(160.0).stick_to_bottom(true).show(ui, |ui| {
+            for line in &app.ui.bp_log {
+                ui.monospace(line);
+            }
+        });
+    }
+
+    ui.separator();
+    ui.heading("Watchpoints");
+
+    let wps: Vec<_> = app.emu.machine.bus.watchpoints.iter().cloned().collect();
+    for wp in wps {
+        ui.horizontal(|ui| {
+            let mut enabled = wp.enabled;
+            if ui.checkbox(&mut enabled, "").changed() {
+                app.emu.machine.bus.watchpoints.set_enabled(wp.id, enabled);
+            }
+            ui.monospace(format!("0x{:08X}+{} {}", wp.start, wp.len, wp.kind));
+            ui.monospace(format!("hits {}", wp.hits));
+            if ui.small_button("Remove").clicked() {
+                app.emu.machine.bus.watchpoints.remove(wp.id);
+            }
+        });
+    }
+
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut app.ui.watch_input).desired_width(140.0).hint_text("ADDR[+LEN][:KIND]"))
+            .on_hover_text("Hex address, length in bytes, read/write/access/change");
+        if ui.button("Add").clicked() {
+            match app.ui.watch_input.trim().parse::<WatchSpec>() {
+                Ok(w) => {
+                    app.emu.machine.bus.watchpoints.add(w.start, w.len, w.kind);
+                    app.ui.watch_input.clear();
+                }
+                Err(e) => app.emu.last_error = Some(format!("Watchpoint: {e}")),
+            }
+        }
+    });
+
+    ui.separator();
+    ui.heading("Device events");
+
+    let dbs: Vec<_> = app.emu.machine.bus.device_breaks.iter().cloned().collect();
+    for db in dbs {
+        ui.horizontal(|ui| {
+            let mut enabled = db.enabled;
+            if ui.checkbox(&mut enabled, "").changed() {
+                app.emu.machine.bus.device_breaks.set_enabled(db.id, enabled);
+            }
+            ui.monospace(db.cond.to_string());
+            ui.monospace(format!("hits {}", db.hits));
+            if ui.small_button("Remove").clicked() {
+                app.emu.machine.bus.device_breaks.remove(db.id);
+            }
+        });
     }
+
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut app.ui.device_break_input).desired_width(140.0).hint_text("sd:17, key, io:16:write"))
+            .on_hover_text("io:OFFSET[:KIND], sd[:CMD|*[:SECTOR]], key[:BYTE] or fb:X,Y,W,H");
+        if ui.button("Add").clicked() {
+            match app.ui.device_break_input.trim().parse::<DeviceBreak>() {
+                Ok(b) => {
+                    app.emu.machine.bus.device_breaks.add(b);
+                    app.ui.device_break_input.clear();
+                }
+                Err(e) => app.emu.last_error = Some(format!("Device breakpoint: {e}")),
+            }
+        }
+    });
+}
+
+fn front_panel(ui: &mut egui::Ui, app: &mut EmuApp) {
+    let panel = &app.emu.machine.panel;
+
+    ui.heading("LEDs");
+    let leds = panel.leds();
+    ui.horizontal(|ui| {
+        for bit in (0..8).rev() {
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(18.0, 18.0), egui::Sense::hover());
+            let on = leds & (1 << bit) != 0;
+            let color = if on { egui::Color32::from_rgb(0xff, 0x40, 0x30) } else { egui::Color32::from_gray(60) };
+            ui.painter().circle_filled(rect.center(), 7.0, color);
+        }
+        ui.monospace(format!("0x{leds:02X}"));
+    });
+
+    ui.separator();
+    ui.heading("Switches");
+    let switches = panel.switches();
+    ui.horizontal(|ui| {
+        for bit in (0..8).rev() {
+            let mut on = switches & (1 << bit) != 0;
+            if ui.checkbox(&mut on, "").on_hover_text(format!("SW{bit}")).changed() {
+                let _ = panel.set_switch(bit, on);
+            }
+        }
+        ui.monospace(format!("0x{:02X}", switches & 0xFF));
+    });
+
+    ui.separator();
+    ui.heading("LED history");
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for c in panel.led_history().iter().rev().take(64) {
+            ui.monospace(format!("#{:<8} 0x{:02X}  {:08b}", c.write, c.value, c.value));
+        }
+    });
 }
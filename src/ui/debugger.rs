@@ -46,15 +46,14 @@ fn disasm(ui: &mut egui::Ui, app: &mut EmuApp) {
 
                 ui.horizontal(|ui| {
                     // breakpoint gutter
-                    let has_bp = app.emu.breakpoints.contains(&addr);
-                    let bp_txt = if has_bp { "●" } else { " " };
+                    let bp_txt = match app.emu.machine.cpu.breakpoints.get(addr) {
+                        Some(bp) if bp.enabled => "●",
+                        Some(_) => "○",
+                        None => " ",
+                    };
 
                     if ui.small_button(bp_txt).clicked() {
-                        if has_bp {
-                            app.emu.breakpoints.remove(&addr);
-                        } else {
-                            app.emu.breakpoints.insert(addr);
-                        }
+                        app.emu.machine.cpu.breakpoints.toggle(addr);
                     }
 
                     // line selectable
@@ -78,7 +77,6 @@ fn disasm(ui: &mut egui::Ui, app: &mut EmuApp) {
                         if let Some(tgt) = dd.branch_target {
                             if ui.small_button(format!("→ 0x{tgt:08X}")).clicked() {
                                 app.ui.cursor_pc = Some(tgt & !3);
-                                app.emu.run_to_target = None;
                                 app.ui.disasm_scroll_to_pc = true;
                             }
                         }
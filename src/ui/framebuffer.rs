@@ -1,6 +1,7 @@
 use eframe::egui;
 
 use super::app::EmuApp;
+use super::input;
 
 pub fn show(ctx: &egui::Context, app: &mut EmuApp) {
     egui::CentralPanel::default().show(ctx, |ui| {
@@ -13,7 +14,8 @@ pub fn show(ctx: &egui::Context, app: &mut EmuApp) {
                 .max(0.1);
 
             let size = egui::vec2(app.ui.fb_w as f32 * scale, app.ui.fb_h as f32 * scale);
-            ui.image((tex.id(), size));
+            let image = ui.image((tex.id(), size)).rect;
+            input::forward(ctx, app, image);
         }
     });
 }
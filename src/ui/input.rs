@@ -0,0 +1,294 @@
+use std::collections::VecDeque;
+
+use eframe::egui::{self, Key, PointerButton};
+
+use super::app::EmuApp;
+
+/// PS/2 set-2 scancode; `ext` keys are sent with an E0 prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ps2Key {
+    pub ext: bool,
+    pub code: u8,
+}
+
+const fn k(code: u8) -> Option<Ps2Key> {
+    Some(Ps2Key { ext: false, code })
+}
+
+const fn e0(code: u8) -> Option<Ps2Key> {
+    Some(Ps2Key { ext: true, code })
+}
+
+pub(crate) const LSHIFT: Ps2Key = Ps2Key { ext: false, code: 0x12 };
+pub(crate) const LCTRL: Ps2Key = Ps2Key { ext: false, code: 0x14 };
+pub(crate) const LALT: Ps2Key = Ps2Key { ext: false, code: 0x11 };
+
+/// Set-2 make code for the key at `key`'s position on a US layout.
+pub(crate) fn scancode(key: Key) -> Option<Ps2Key> {
+    use Key::*;
+    match key {
+        A => k(0x1C), B => k(0x32), C => k(0x21), D => k(0x23), E => k(0x24),
+        F => k(0x2B), G => k(0x34), H => k(0x33), I => k(0x43), J => k(0x3B),
+        K => k(0x42), L => k(0x4B), M => k(0x3A), N => k(0x31), O => k(0x44),
+        P => k(0x4D), Q => k(0x15), R => k(0x2D), S => k(0x1B), T => k(0x2C),
+        U => k(0x3C), V => k(0x2A), W => k(0x1D), X => k(0x22), Y => k(0x35),
+        Z => k(0x1A),
+
+        Num0 => k(0x45), Num1 => k(0x16), Num2 => k(0x1E), Num3 => k(0x26), Num4 => k(0x25),
+        Num5 => k(0x2E), Num6 => k(0x36), Num7 => k(0x3D), Num8 => k(0x3E), Num9 => k(0x46),
+
+        // shifted punctuation arrives as its own logical key; map to the base key
+        Backtick => k(0x0E),
+        Minus => k(0x4E),
+        Equals | Plus => k(0x55),
+        Backslash | Pipe => k(0x5D),
+        OpenBracket | OpenCurlyBracket => k(0x54),
+        CloseBracket | CloseCurlyBracket => k(0x5B),
+        Semicolon | Colon => k(0x4C),
+        Quote => k(0x52),
+        Comma => k(0x41),
+        Period => k(0x49),
+        Slash | Questionmark => k(0x4A),
+        Exclamationmark => k(0x16),
+
+        Backspace => k(0x66),
+        Space => k(0x29),
+        Tab => k(0x0D),
+        Enter => k(0x5A),
+        Escape => k(0x76),
+
+        F1 => k(0x05), F2 => k(0x06), F3 => k(0x04), F4 => k(0x0C),
+        F5 => k(0x03), F6 => k(0x0B), F7 => k(0x83), F8 => k(0x0A),
+        F9 => k(0x01), F10 => k(0x09), F11 => k(0x78), F12 => k(0x07),
+
+        Insert => e0(0x70),
+        Delete => e0(0x71),
+        Home => e0(0x6C),
+        End => e0(0x69),
+        PageUp => e0(0x7D),
+        PageDown => e0(0x7A),
+        ArrowUp => e0(0x75),
+        ArrowDown => e0(0x72),
+        ArrowLeft => e0(0x6B),
+        ArrowRight => e0(0x74),
+
+        _ => None,
+    }
+}
+
+/// Make (`down`) or break bytes: `[E0] code` / `[E0] F0 code`.
+pub(crate) fn ps2_bytes(key: Ps2Key, down: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(3);
+    if key.ext {
+        out.push(0xE0);
+    }
+    if !down {
+        out.push(0xF0);
+    }
+    out.push(key.code);
+    out
+}
+
+/// Host modifier that acts as the Oberon middle button (two-button trackpads).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MiddleKey {
+    None,
+    Alt,
+    Ctrl,
+}
+
+/// Host pointer buttons -> Oberon buttons (1 = left, 2 = middle, 3 = right).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MouseMapping {
+    pub primary: u32,
+    pub secondary: u32,
+    pub middle: u32,
+    pub middle_key: MiddleKey,
+}
+
+impl Default for MouseMapping {
+    fn default() -> Self {
+        Self { primary: 1, secondary: 3, middle: 2, middle_key: MiddleKey::Alt }
+    }
+}
+
+impl MouseMapping {
+    fn button(&self, b: PointerButton) -> Option<u32> {
+        match b {
+            PointerButton::Primary => Some(self.primary),
+            PointerButton::Secondary => Some(self.secondary),
+            PointerButton::Middle => Some(self.middle),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct InputState {
+    pub enabled: bool,
+    pub mouse: MouseMapping,
+    modifiers: egui::Modifiers,
+    // bytes the guest's 16-byte buffer could not take yet
+    pending: VecDeque<Vec<u8>>,
+    // Oberon buttons currently held, by source (pointer buttons and middle key)
+    held: [bool; 4],
+    middle_key_down: bool,
+    last_pos: Option<(i32, i32)>,
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mouse: MouseMapping::default(),
+            modifiers: egui::Modifiers::NONE,
+            pending: VecDeque::new(),
+            held: [false; 4],
+            middle_key_down: false,
+            last_pos: None,
+        }
+    }
+}
+
+/// Framebuffer pixel under `pos`, with Oberon's bottom-up Y axis.
+pub(crate) fn fb_coords(pos: egui::Pos2, image: egui::Rect, fb_w: usize, fb_h: usize) -> Option<(i32, i32)> {
+    if !image.contains(pos) {
+        return None;
+    }
+    let sx = fb_w as f32 / image.width();
+    let sy = fb_h as f32 / image.height();
+    let x = (((pos.x - image.min.x) * sx) as i32).clamp(0, fb_w as i32 - 1);
+    let y = (((pos.y - image.min.y) * sy) as i32).clamp(0, fb_h as i32 - 1);
+    Some((x, fb_h as i32 - 1 - y))
+}
+
+/// Forwards this frame's keyboard and pointer events over `image` to the guest.
+pub(crate) fn forward(ctx: &egui::Context, app: &mut EmuApp, image: egui::Rect) {
+    if !app.ui.input.enabled {
+        return;
+    }
+    let (events, modifiers) = ctx.input(|i| (i.events.clone(), i.modifiers));
+    let keyboard = !ctx.wants_keyboard_input();
+    let (fb_w, fb_h) = (app.ui.fb_w, app.ui.fb_h);
+    let st = &mut app.ui.input;
+
+    if keyboard {
+        modifier_changes(st, modifiers);
+    }
+
+    for ev in &events {
+        match ev {
+            egui::Event::Key { key, physical_key, pressed, .. } if keyboard => {
+                // repeats resend the make code, like PS/2 typematic
+                if let Some(sc) = scancode(physical_key.unwrap_or(*key)) {
+                    st.pending.push_back(ps2_bytes(sc, *pressed));
+                }
+            }
+            // egui swallows the key press of these shortcuts
+            egui::Event::Copy if keyboard => st.pending.push_back(ps2_bytes(scancode(Key::C).unwrap(), true)),
+            egui::Event::Cut if keyboard => st.pending.push_back(ps2_bytes(scancode(Key::X).unwrap(), true)),
+            egui::Event::Paste(_) if keyboard => st.pending.push_back(ps2_bytes(scancode(Key::V).unwrap(), true)),
+
+            egui::Event::PointerMoved(pos) => {
+                st.last_pos = fb_coords(*pos, image, fb_w, fb_h);
+                if let Some((x, y)) = st.last_pos {
+                    app.emu.machine.mouse_moved(x, y);
+                }
+            }
+            egui::Event::PointerButton { pos, button, pressed, .. } => {
+                let Some(b) = st.mouse.button(*button) else { continue };
+                // presses only count over the image, releases always do
+                if *pressed && fb_coords(*pos, image, fb_w, fb_h).is_none() {
+                    continue;
+                }
+                set_button(&mut app.emu.machine, st, b, *pressed);
+            }
+            _ => {}
+        }
+    }
+
+    // middle-button emulation from a held modifier
+    let mk = match st.mouse.middle_key {
+        MiddleKey::None => false,
+        MiddleKey::Alt => modifiers.alt,
+        MiddleKey::Ctrl => modifiers.ctrl,
+    };
+    if mk != st.middle_key_down && (st.last_pos.is_some() || !mk) {
+        st.middle_key_down = mk;
+        let middle = st.mouse.middle;
+        set_button(&mut app.emu.machine, st, middle, mk);
+    }
+
+    while let Some(bytes) = st.pending.front() {
+        if app.emu.machine.keyboard_ps2(bytes).is_err() {
+            break;
+        }
+        st.pending.pop_front();
+    }
+}
+
+fn set_button(machine: &mut crate::Machine, st: &mut InputState, button: u32, down: bool) {
+    let i = button as usize;
+    if i < st.held.len() && st.held[i] != down {
+        st.held[i] = down;
+        machine.mouse_button(button, down);
+    }
+}
+
+/// egui reports modifiers as state, PS/2 wants make/break events.
+fn modifier_changes(st: &mut InputState, now: egui::Modifiers) {
+    let prev = std::mem::replace(&mut st.modifiers, now);
+    // the modifier used as middle button is not a key for the guest
+    let ctrl_is_key = st.mouse.middle_key != MiddleKey::Ctrl;
+    let alt_is_key = st.mouse.middle_key != MiddleKey::Alt;
+
+    for (before, after, key, is_key) in [
+        (prev.shift, now.shift, LSHIFT, true),
+        (prev.ctrl, now.ctrl, LCTRL, ctrl_is_key),
+        (prev.alt, now.alt, LALT, alt_is_key),
+    ] {
+        if is_key && before != after {
+            st.pending.push_back(ps2_bytes(key, after));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_and_extended_keys() {
+        let a = scancode(Key::A).unwrap();
+        assert_eq!(ps2_bytes(a, true), [0x1C]);
+        assert_eq!(ps2_bytes(a, false), [0xF0, 0x1C]);
+
+        let up = scancode(Key::ArrowUp).unwrap();
+        assert_eq!(ps2_bytes(up, true), [0xE0, 0x75]);
+        assert_eq!(ps2_bytes(up, false), [0xE0, 0xF0, 0x75]);
+
+        // shifted punctuation goes to its base key
+        assert_eq!(scancode(Key::Colon), scancode(Key::Semicolon));
+        assert_eq!(scancode(Key::F13), None);
+    }
+
+    #[test]
+    fn pointer_buttons_map_to_oberon_buttons() {
+        let m = MouseMapping::default();
+        assert_eq!(m.button(PointerButton::Primary), Some(1));
+        assert_eq!(m.button(PointerButton::Middle), Some(2));
+        assert_eq!(m.button(PointerButton::Secondary), Some(3));
+        assert_eq!(m.button(PointerButton::Extra1), None);
+    }
+
+    #[test]
+    fn coordinates_scale_flip_and_clamp() {
+        // 400x200 framebuffer shown at half size
+        let image = egui::Rect::from_min_size(egui::pos2(10.0, 20.0), egui::vec2(200.0, 100.0));
+        let at = |x, y| fb_coords(egui::pos2(x, y), image, 400, 200);
+        assert_eq!(at(10.0, 20.0), Some((0, 199)));
+        assert_eq!(at(110.0, 70.0), Some((200, 99)));
+        assert_eq!(at(210.0, 120.0), Some((399, 0)));
+        assert_eq!(at(9.0, 50.0), None);
+        assert_eq!(at(100.0, 121.0), None);
+    }
+}
@@ -2,4 +2,6 @@ pub mod app;
 mod topbar;
 mod debugger;
 mod cpu_panel;
-mod framebuffer;
\ No newline at end of file
+mod framebuffer;
+mod input;
+mod profile;
@@ -0,0 +1,189 @@
+use eframe::egui;
+
+use crate::debug::OpClass;
+use crate::disasm::disassemble_at;
+
+use super::app::EmuApp;
+
+/// Rows shown in the hot spot and procedure tables.
+const MAX_ROWS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProfileView {
+    HotSpots,
+    Procedures,
+    Mix,
+}
+
+/// Table sort order: column and whether largest comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SortBy {
+    pub(crate) column: usize,
+    pub(crate) descending: bool,
+}
+
+impl Default for SortBy {
+    fn default() -> Self {
+        Self { column: 1, descending: true }
+    }
+}
+
+/// Sort key of a table cell.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Num(u64),
+    Text(String),
+}
+
+fn nums(keys: &[u64]) -> Vec<Key> {
+    keys.iter().map(|&n| Key::Num(n)).collect()
+}
+
+/// A table row: sort keys and texts, one per column. Rows with `code` get
+/// the disassembly at that address as last column once sorted.
+struct Row {
+    keys: Vec<Key>,
+    cells: Vec<String>,
+    code: Option<u32>,
+}
+
+pub(crate) fn show(ui: &mut egui::Ui, app: &mut EmuApp) {
+    ui.heading("Profile");
+    ui.horizontal(|ui| {
+        let on = app.ui.profiling;
+        if ui
+            .button(if on { "Stop" } else { "Start" })
+            .on_hover_text("Count every instruction from now on (runs interpreted)")
+            .clicked()
+        {
+            if on {
+                app.stop_profile();
+            } else {
+                app.start_profile();
+            }
+        }
+        if ui.button("Reset").clicked() {
+            app.reset_profile();
+        }
+        if ui.button("Refresh names").on_hover_text("Read the module list again").clicked() {
+            app.ui.profile_symbols = app.symbols();
+        }
+        if ui.button("Export folded…").clicked() {
+            if let Some(path) = rfd::FileDialog::new().add_filter("Folded stacks", &["folded"]).save_file() {
+                app.export_profile(&path);
+            }
+        }
+    });
+
+    let Some(profiler) = app.ui.profile.clone() else {
+        ui.label("No profile yet.");
+        return;
+    };
+    let p = profiler.lock().unwrap();
+    let total = p.total().max(1);
+    let pct = |n: u64| format!("{:.1}%", n as f64 * 100.0 / total as f64);
+    ui.monospace(format!("{} instructions", p.total()));
+
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut app.ui.profile_view, ProfileView::HotSpots, "Hot spots");
+        ui.selectable_value(&mut app.ui.profile_view, ProfileView::Procedures, "Procedures");
+        ui.selectable_value(&mut app.ui.profile_view, ProfileView::Mix, "Mix");
+    });
+    ui.separator();
+
+    let (headers, mut rows): (&[&str], Vec<Row>) = match app.ui.profile_view {
+        ProfileView::HotSpots => (
+            &["PC", "Count", "%", "Taken", "Not taken", "Instruction"],
+            p.hot_spots()
+                .into_iter()
+                .map(|(pc, s)| {
+                    let branch = |n: u64| if s.taken + s.not_taken > 0 { n.to_string() } else { String::new() };
+                    Row {
+                        keys: nums(&[pc as u64, s.count, s.count, s.taken, s.not_taken, pc as u64]),
+                        cells: vec![format!("{pc:08X}"), s.count.to_string(), pct(s.count), branch(s.taken), branch(s.not_taken)],
+                        code: Some(pc),
+                    }
+                })
+                .collect(),
+        ),
+        ProfileView::Procedures => (
+            &["Entry", "Total", "%", "Own", "Own %", "Name"],
+            p.procedures()
+                .into_iter()
+                .map(|s| {
+                    let name = app.ui.profile_symbols.name(s.entry);
+                    let mut keys = nums(&[s.entry as u64, s.total, s.total, s.own, s.own]);
+                    keys.push(Key::Text(name.clone()));
+                    Row {
+                        keys,
+                        cells: vec![
+                            format!("{:08X}", s.entry),
+                            s.total.to_string(),
+                            pct(s.total),
+                            s.own.to_string(),
+                            pct(s.own),
+                            name,
+                        ],
+                        code: None,
+                    }
+                })
+                .collect(),
+        ),
+        ProfileView::Mix => (
+            &["Class", "Count", "%"],
+            OpClass::ALL
+                .iter()
+                .enumerate()
+                .map(|(i, &c)| Row {
+                    keys: nums(&[i as u64, p.mix(c), p.mix(c)]),
+                    cells: vec![c.to_string(), p.mix(c).to_string(), pct(p.mix(c))],
+                    code: None,
+                })
+                .collect(),
+        ),
+    };
+    drop(p);
+
+    let sort = &mut app.ui.profile_sort;
+    sort.column = sort.column.min(headers.len() - 1);
+    rows.sort_by(|a, b| {
+        let o = a.keys[sort.column].cmp(&b.keys[sort.column]);
+        if sort.descending { o.reverse() } else { o }
+    });
+    rows.truncate(MAX_ROWS);
+    for row in &mut rows {
+        if let Some(pc) = row.code {
+            row.cells.push(app.read_word_at(pc).map(|ir| disassemble_at(pc, ir).text).unwrap_or_default());
+        }
+    }
+    table(ui, &mut app.ui.profile_sort, headers, &rows);
+}
+
+fn table(ui: &mut egui::Ui, sort: &mut SortBy, headers: &[&str], rows: &[Row]) {
+    egui::ScrollArea::both().id_salt("profile_table").auto_shrink([false; 2]).show(ui, |ui| {
+        egui::Grid::new("profile_grid").striped(true).show(ui, |ui| {
+            for (i, h) in headers.iter().enumerate() {
+                let arrow = match (sort.column == i, sort.descending) {
+                    (true, true) => " ⏷",
+                    (true, false) => " ⏶",
+                    _ => "",
+                };
+                if ui.button(format!("{h}{arrow}")).clicked() {
+                    if sort.column == i {
+                        sort.descending = !sort.descending;
+                    } else {
+                        *sort = SortBy { column: i, descending: i != 0 };
+                    }
+                }
+            }
+            ui.end_row();
+
+            for row in rows {
+                for cell in &row.cells {
+                    ui.monospace(cell);
+                }
+                ui.end_row();
+            }
+        });
+    });
+}
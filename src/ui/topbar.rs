@@ -1,6 +1,12 @@
+use std::path::PathBuf;
+
 use eframe::egui;
 
+use crate::debug::history::DEFAULT_HISTORY;
+use crate::devices::disk::DiskMode;
+
 use super::app::EmuApp;
+use super::input::MiddleKey;
 
 pub fn show(ctx: &egui::Context, app: &mut EmuApp) {
     egui::TopBottomPanel::top("top").show(ctx, |ui| {
@@ -10,47 +16,59 @@ pub fn show(ctx: &egui::Context, app: &mut EmuApp) {
             }
 
             ui.menu_button("File", |ui| {
-                if ui.button("Attach Disk 1 (SPI1)…").clicked() {
-                    ui.close_menu();
-                    if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        if let Err(e) = app.emu.machine.attach_disk (1, &path) {
-                            app.emu.last_error = Some(format!("Attach disk1 failed: {e:?}"));
-                        } else {
-                            app.emu.disk1_path = Some(path);
-                        }
-                    }
+                ui.label("Attach mode:");
+                let mode = &mut app.emu.disk_mode;
+                ui.radio_value(mode, DiskMode::ReadWrite, "Read/write");
+                ui.radio_value(mode, DiskMode::ReadOnly, "Read-only (write-protected)");
+                ui.radio_value(mode, DiskMode::Overlay, "Overlay in memory");
+                let sidecar = matches!(mode, DiskMode::Sidecar(_));
+                if ui.radio(sidecar, "Overlay in <image>.delta").clicked() && !sidecar {
+                    *mode = DiskMode::Sidecar(PathBuf::new());
                 }
-                if ui.button("Eject Disk 1 (SPI1)…").clicked() {
-                    ui.close_menu();
-                    if let Err(e) = app.emu.machine.eject_disk(1) {
-                        app.emu.last_error = Some(format!("Eject disk1 failed: {e:?}"));
-                    } else {
-                        app.emu.disk1_path = None;
-                    }
+
+                for slot in [1, 2] {
+                    ui.separator();
+                    disk_items(ui, app, slot);
                 }
 
                 ui.separator();
-
-                if ui.button("Attach Disk 2 (SPI2)…").clicked() {
+                if ui.button("Save state…").clicked() {
                     ui.close_menu();
-                    if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        if let Err(e) = app.emu.machine.attach_disk (2, &path) {
-                            app.emu.last_error = Some(format!("Attach disk2 failed: {e:?}"));
-                        } else {
-                            app.emu.disk2_path = Some(path);
-                        }
+                    if let Some(path) = rfd::FileDialog::new().add_filter("Save state", &["state"]).save_file() {
+                        app.save_state(&path);
                     }
                 }
-                if ui.button("Eject Disk 2 (SPI2)…").clicked() {
+                if ui.button("Load state…").clicked() {
                     ui.close_menu();
-                    if let Err(e) = app.emu.machine.eject_disk(2) {
-                        app.emu.last_error = Some(format!("Eject disk2 failed: {e:?}"));
-                    } else {
-                        app.emu.disk2_path = None;
+                    if let Some(path) = rfd::FileDialog::new().add_filter("Save state", &["state"]).pick_file() {
+                        app.load_state(&path);
                     }
                 }
             });
 
+            ui.menu_button("Input", |ui| {
+                let input = &mut app.ui.input;
+                ui.checkbox(&mut input.enabled, "Forward keyboard and mouse");
+                ui.separator();
+                ui.label("Middle button key:");
+                ui.radio_value(&mut input.mouse.middle_key, MiddleKey::Alt, "Alt");
+                ui.radio_value(&mut input.mouse.middle_key, MiddleKey::Ctrl, "Ctrl");
+                ui.radio_value(&mut input.mouse.middle_key, MiddleKey::None, "None");
+                ui.separator();
+                for (label, b) in [
+                    ("Primary", &mut input.mouse.primary),
+                    ("Secondary", &mut input.mouse.secondary),
+                    ("Middle", &mut input.mouse.middle),
+                ] {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{label}:"));
+                        ui.selectable_value(b, 1, "ML");
+                        ui.selectable_value(b, 2, "MM");
+                        ui.selectable_value(b, 3, "MR");
+                    });
+                }
+            });
+
             if ui.button("Step").clicked() {
                 app.step_instructions(1);
                 if app.ui.follow_pc {
@@ -68,15 +86,39 @@ pub fn show(ctx: &egui::Context, app: &mut EmuApp) {
                 }
             }
 
+            if ui.button("Step back").clicked() {
+                app.step_back(1);
+                if app.ui.follow_pc {
+                    app.ui.cursor_pc = Some(app.pc_aligned());
+                    app.ui.disasm_scroll_to_pc = true;
+                }
+            }
+
+            if ui.button("Reverse continue").clicked() {
+                app.reverse_continue();
+                if app.ui.follow_pc {
+                    app.ui.cursor_pc = Some(app.pc_aligned());
+                    app.ui.disasm_scroll_to_pc = true;
+                }
+            }
+
+            let mut record = app.emu.machine.cpu.history.is_some();
+            if ui
+                .checkbox(&mut record, "Record")
+                .on_hover_text("Keep the last million instructions for Step back (runs interpreted)")
+                .changed()
+            {
+                app.set_history(record.then_some(DEFAULT_HISTORY));
+            }
+
             if ui.button("Run to cursor").clicked() {
                 if let Some(target) = app.ui.cursor_pc {
-                    app.emu.run_to_target = Some(target & !3);
-                    app.emu.running = true;
+                    app.run_to(target);
                 }
             }
 
             if ui.button("Clear BPs").clicked() {
-                app.emu.breakpoints.clear();
+                app.emu.machine.cpu.breakpoints.clear();
             }
 
             ui.separator();
@@ -107,3 +149,39 @@ pub fn show(ctx: &egui::Context, app: &mut EmuApp) {
         });
     });
 }
+
+fn disk_items(ui: &mut egui::Ui, app: &mut EmuApp, slot: usize) {
+    if ui.button(format!("Attach Disk {slot} (SPI{slot})…")).clicked() {
+        ui.close_menu();
+        if let Some(path) = rfd::FileDialog::new().pick_file() {
+            if let Err(e) = app.attach_disk(slot, &path) {
+                app.emu.last_error = Some(format!("Attach disk{slot} failed: {e:?}"));
+            }
+        }
+    }
+    if ui.button(format!("Eject Disk {slot} (SPI{slot})…")).clicked() {
+        ui.close_menu();
+        if let Err(e) = app.emu.machine.eject_disk(slot) {
+            app.emu.last_error = Some(format!("Eject disk{slot} failed: {e:?}"));
+        } else if slot == 1 {
+            app.emu.disk1_path = None;
+        } else {
+            app.emu.disk2_path = None;
+        }
+    }
+
+    let Some(overlay) = app.emu.machine.disk_overlay(slot).cloned() else { return };
+    let n = overlay.dirty_blocks();
+    if ui.add_enabled(n > 0, egui::Button::new(format!("Commit Disk {slot} overlay ({n} blocks)"))).clicked() {
+        ui.close_menu();
+        if let Err(e) = overlay.commit() {
+            app.emu.last_error = Some(format!("Commit disk{slot} failed: {e}"));
+        }
+    }
+    if ui.add_enabled(n > 0, egui::Button::new(format!("Discard Disk {slot} overlay"))).clicked() {
+        ui.close_menu();
+        if let Err(e) = overlay.discard() {
+            app.emu.last_error = Some(format!("Discard disk{slot} failed: {e}"));
+        }
+    }
+}
@@ -82,3 +82,143 @@ fn unit_branch_relative_and_link() {
     // instr1 (branch) @ ROM_START+4, efter fetch pc = ROM_START+8
     assert_eq!(cpu.r[15], ROM_START + 8);
 }
+
+#[test]
+fn unit_interrupt_vectors_and_returns() {
+    const STI: u32 = 0xC700_0021;
+    const RTI: u32 = 0xC700_0010;
+
+    // R0=0 (Z=1), STI, then two MOVs the handler must not disturb
+    let prog = [
+        reg(MOV, 0, 0, 0, true, false, false, 0),
+        STI,
+        reg(MOV, 2, 0, 0, true, false, false, 2),
+    ];
+
+    let mut bus = TestBus::new(1024, 512);
+    bus.rom[..prog.len()].copy_from_slice(&prog);
+    // handler at the interrupt vector (byte 4): R1=5 (clears Z), RTI
+    bus.ram[1] = reg(MOV, 1, 0, 0, true, false, false, 5);
+    bus.ram[2] = RTI;
+
+    let mut cpu = Cpu::default();
+    cpu.pc = ROM_START;
+    cpu.step(&mut bus).unwrap();
+    cpu.step(&mut bus).unwrap();
+    assert!(cpu.int_enabled);
+
+    bus.irq = true;
+    cpu.step(&mut bus).unwrap(); // vectors, runs MOV R1
+    assert!(cpu.int_mode);
+    assert_eq!(cpu.r[1], 5);
+    assert!(!cpu.z);
+
+    bus.irq = false;
+    cpu.step(&mut bus).unwrap(); // RTI
+    assert!(!cpu.int_mode);
+    assert_eq!(cpu.pc, ROM_START + 8);
+    assert!(cpu.z);
+
+    cpu.step(&mut bus).unwrap();
+    assert_eq!(cpu.r[2], 2);
+}
+
+#[test]
+fn unit_run_reports_stop_reason_and_fault() {
+    use risc_emulator::bus::BusError;
+    use risc_emulator::cpu::{Fault, RunOutcome, StopReason};
+
+    let load = mem(0, 1, 8, false, false);
+    let prog = [
+        reg(MOV, 1, 0, 0, true, false, false, 0x7000), // R1 = 0x7000 (past RAM)
+        load,                                          // R0 = [R1+8]
+    ];
+
+    let mut bus = TestBus::new(1024, 512);
+    bus.rom[..prog.len()].copy_from_slice(&prog);
+    let mut cpu = Cpu::default();
+    cpu.pc = ROM_START;
+
+    assert_eq!(cpu.run(&mut bus, 1), RunOutcome::new(1, StopReason::Budget));
+
+    let out = cpu.run(&mut bus, 10);
+    let fault = Fault { pc: ROM_START + 4, ir: Some(load), addr: 0x7008, error: BusError::AddressOutOfBounds(0x7008) };
+    assert_eq!(out, RunOutcome::new(0, StopReason::Fault(fault.clone())));
+    assert_eq!(out.into_result(), Err(fault));
+    assert_eq!(cpu.pc, ROM_START + 8);
+
+    cpu.pc = 0x8000;
+    let out = cpu.run(&mut bus, 10);
+    assert_eq!(out.fault().map(|f| (f.pc, f.ir, f.addr)), Some((0x8000, None, 0x8000)));
+}
+
+#[test]
+fn unit_breakpoints_count_hits_ignore_and_temporary() {
+    use risc_emulator::cpu::{RunOutcome, StopReason};
+
+    let prog = [
+        reg(ADD, 0, 0, 0, true, false, false, 1), // loop: R0 += 1
+        br(7, false, true, false, 0, -2),         // B loop
+    ];
+    let mut bus = TestBus::new(1024, 512);
+    bus.rom[..prog.len()].copy_from_slice(&prog);
+    let mut cpu = Cpu::default();
+    cpu.pc = ROM_START;
+
+    // passes the loop head twice, stops on the third visit
+    cpu.breakpoints.insert(ROM_START).ignore = 2;
+    assert_eq!(cpu.run(&mut bus, 100), RunOutcome::new(6, StopReason::Breakpoint(ROM_START)));
+    assert_eq!(cpu.r[0], 3);
+    assert_eq!(cpu.breakpoints.get(ROM_START).map(|b| (b.hits, b.ignore)), Some((3, 0)));
+
+    // continuing steps off the breakpoint before checking again
+    assert_eq!(cpu.run(&mut bus, 100), RunOutcome::new(2, StopReason::Breakpoint(ROM_START)));
+    assert_eq!(cpu.breakpoints.get(ROM_START).unwrap().hits, 4);
+
+    cpu.breakpoints.set_enabled(ROM_START, false);
+    assert_eq!(cpu.run(&mut bus, 10), RunOutcome::new(10, StopReason::Budget));
+    assert_eq!(cpu.breakpoints.get(ROM_START).unwrap().hits, 4);
+
+    cpu.breakpoints.insert_temporary(ROM_START + 4);
+    assert_eq!(cpu.run(&mut bus, 10), RunOutcome::new(1, StopReason::Breakpoint(ROM_START + 4)));
+    assert!(!cpu.breakpoints.contains(ROM_START + 4));
+    assert_eq!(cpu.run(&mut bus, 10), RunOutcome::new(10, StopReason::Budget));
+}
+
+#[test]
+fn unit_condition_expressions() {
+    use risc_emulator::debug::expr::Env;
+    use risc_emulator::debug::{Expr, LogMessage};
+
+    let mut cpu = Cpu::default();
+    cpu.r[0] = 0x1C;
+    cpu.r[1] = (-3i32) as u32;
+    cpu.r[14] = 0x100;
+    cpu.z = true;
+    let view = cpu.view();
+    let mut peek = |a: u32| (a < 0x200).then_some(0x1122_0000 | a);
+    let mut eval = |src: &str| {
+        let e: Expr = src.parse().unwrap();
+        e.eval(&mut Env { cpu: &view, hits: 7, peek: &mut peek })
+    };
+
+    assert_eq!(eval("R0 == 0x1C && Z"), Ok(1));
+    assert_eq!(eval("r0 == 1CH && !Z"), Ok(0));
+    assert_eq!(eval("1 + 2 * 3 == 7 || 0"), Ok(1));
+    assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+    assert_eq!(eval("R1 < 0 && R1 / 2 == -1 && R1 >> 1 == -2"), Ok(1));
+    assert_eq!(eval("hits % 7 == 0"), Ok(1));
+    assert_eq!(eval("[SP + 8]"), Ok(0x1122_0108));
+    assert_eq!(eval("b[R14 + 9]"), Ok(0x01));
+    // `&&` stops before the unreadable address
+    assert_eq!(eval("R0 == 0 && [0x1000] == 1"), Ok(0));
+    assert_eq!(eval("[0x1000]"), Err(0x1000));
+
+    for bad in ["R16", "R0 ==", "(R0", "R0 # 1", "[R0"] {
+        assert!(bad.parse::<Expr>().is_err(), "{bad}");
+    }
+
+    let msg: LogMessage = "key {R0:x} at {SP:x}, R1 = {R1}, {[0x1000]}".parse().unwrap();
+    let text = msg.format(&mut Env { cpu: &view, hits: 1, peek: &mut peek });
+    assert_eq!(text, "key 1C at 100, R1 = -3, <00001000?>");
+}
@@ -0,0 +1,409 @@
+mod sd_card;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use risc_emulator::bus::BusResult;
+use risc_emulator::devices::serial::SerialBackend;
+use risc_emulator::machine::IO_START;
+use risc_emulator::Machine;
+
+#[derive(Debug, Default, Clone)]
+struct Loopback {
+    to_guest: Arc<Mutex<VecDeque<u8>>>,
+    from_guest: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SerialBackend for Loopback {
+    fn try_recv(&mut self) -> Option<u8> {
+        self.to_guest.lock().unwrap().pop_front()
+    }
+
+    fn send(&mut self, byte: u8) -> BusResult<()> {
+        self.from_guest.lock().unwrap().push(byte);
+        Ok(())
+    }
+}
+
+fn machine() -> Machine {
+    Machine::new_for_tests(vec![0xE7FF_FFFF], 0x400, 0x200, 8, 8)
+}
+
+#[test]
+fn serial_status_and_data() {
+    let mut m = machine();
+    let line = Loopback::default();
+    m.attach_serial(Box::new(line.clone()));
+
+    let mut p = 0;
+    let io = &mut m.bus.io;
+    assert_eq!(io.read_word_with_progress(IO_START + 12, &mut p).unwrap(), 2);
+
+    line.to_guest.lock().unwrap().extend(b"ok");
+    assert_eq!(io.read_word_with_progress(IO_START + 12, &mut p).unwrap(), 3);
+    assert_eq!(io.read_word_with_progress(IO_START + 8, &mut p).unwrap(), b'o' as u32);
+    assert_eq!(io.read_word_with_progress(IO_START + 8, &mut p).unwrap(), b'k' as u32);
+    assert_eq!(io.read_word_with_progress(IO_START + 12, &mut p).unwrap(), 2);
+
+    io.write_word(IO_START + 8, 0x141).unwrap();
+    assert_eq!(*line.from_guest.lock().unwrap(), vec![0x41]);
+}
+
+#[test]
+fn serial_byte_store_leaves_received_byte() {
+    use risc_emulator::bus::CpuBus;
+
+    let mut m = machine();
+    let line = Loopback::default();
+    m.attach_serial(Box::new(line.clone()));
+    line.to_guest.lock().unwrap().push_back(b'x');
+
+    // STB to the data port, as PCLink1.Snd does
+    let mut p = 20;
+    m.bus.write_byte_for_cpu(IO_START + 8, b'y', &mut p).unwrap();
+    assert_eq!(*line.from_guest.lock().unwrap(), b"y");
+    let io = &mut m.bus.io;
+    assert_eq!(io.read_word_with_progress(IO_START + 12, &mut p).unwrap(), 3);
+    assert_eq!(io.read_word_with_progress(IO_START + 8, &mut p).unwrap(), b'x' as u32);
+}
+
+#[test]
+fn serial_spec_parsing() {
+    use risc_emulator::devices::serial::SerialSpec;
+
+    assert_eq!("tcp:2323".parse::<SerialSpec>().unwrap(), SerialSpec::Tcp(2323));
+    assert_eq!("pty".parse::<SerialSpec>().unwrap(), SerialSpec::Pty);
+    assert!("file:in".parse::<SerialSpec>().is_err());
+    assert!("tcp:x".parse::<SerialSpec>().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn serial_socket_send_does_not_block_on_a_stalled_client() {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    use risc_emulator::devices::serial::StreamBackend;
+
+    let path = std::env::temp_dir().join(format!("risc-serial-{}.sock", std::process::id()));
+    let mut backend = StreamBackend::unix_socket(&path).unwrap();
+    let mut client = UnixStream::connect(&path).unwrap();
+    let until = Instant::now() + Duration::from_secs(5);
+    let wait_for = |backend: &mut StreamBackend, byte: u8| loop {
+        match backend.try_recv() {
+            Some(b) => break assert_eq!(b, byte),
+            None if Instant::now() < until => std::thread::sleep(Duration::from_millis(1)),
+            None => panic!("no byte from the client"),
+        }
+    };
+    client.write_all(b"a").unwrap();
+    wait_for(&mut backend, b'a');
+
+    // far more than the socket buffers hold, and the client is not reading
+    for i in 0..(1 << 20) {
+        backend.send(i as u8).unwrap();
+    }
+    let mut head = [0u8; 256];
+    client.read_exact(&mut head).unwrap();
+    assert!(head.iter().enumerate().all(|(i, &b)| b == i as u8));
+
+    client.write_all(b"b").unwrap();
+    wait_for(&mut backend, b'b');
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn clipboard_memory_backend_roundtrip() {
+    use risc_emulator::devices::clipboard::{ClipboardDevice, MemoryClipboard};
+
+    let mut m = machine();
+    let host = MemoryClipboard::new();
+    m.attach_clipboard(ClipboardDevice::new(Box::new(host.clone())));
+
+    let mut p = 0;
+    let io = &mut m.bus.io;
+
+    // host -> guest: CRLF counts once and arrives as CR
+    host.set("a\r\nb");
+    assert_eq!(io.read_word_with_progress(IO_START + 40, &mut p).unwrap(), 3);
+    let got: Vec<u32> = (0..3)
+        .map(|_| io.read_word_with_progress(IO_START + 44, &mut p).unwrap())
+        .collect();
+    assert_eq!(got, vec![b'a' as u32, b'\r' as u32, b'b' as u32]);
+
+    // guest -> host
+    io.write_word(IO_START + 40, 3).unwrap();
+    for c in b"x\ry" {
+        io.write_word(IO_START + 44, *c as u32).unwrap();
+    }
+    assert_eq!(host.text(), "x\ny");
+}
+
+#[test]
+fn front_panel_leds_and_switches() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let mut m = machine();
+    let seen = Arc::new(AtomicU32::new(0));
+    let s = seen.clone();
+    m.panel.on_leds_changed(move |v| s.store(v as u32, Ordering::SeqCst));
+
+    let io = &mut m.bus.io;
+    io.write_word(IO_START + 4, 0x81).unwrap();
+    io.write_word(IO_START + 4, 0x81).unwrap();
+    io.write_word(IO_START + 4, 0x03).unwrap();
+    assert_eq!(m.panel.leds(), 0x03);
+    assert_eq!(seen.load(Ordering::SeqCst), 0x03);
+
+    let hist: Vec<(u64, u8)> = m.panel.led_history().iter().map(|c| (c.write, c.value)).collect();
+    assert_eq!(hist, vec![(0, 0x81), (2, 0x03)]);
+
+    m.panel.set_switches(0x5A);
+    m.panel.set_switch(0, true).unwrap();
+    assert!(m.panel.set_switch(32, true).is_err());
+    let mut p = 0;
+    assert_eq!(m.bus.io.read_word_with_progress(IO_START + 4, &mut p).unwrap(), 0x5B);
+}
+
+mod sd {
+    use risc_emulator::devices::disk::*;
+    use risc_emulator::devices::spi::SpiDevice;
+
+    use super::sd_card::{cmd, image, init, write_block, xfer};
+
+    fn read_block(d: &mut dyn SpiDevice) -> [u32; 128] {
+        while xfer(d, 0xFF) != TOKEN_START_BLOCK {}
+        let mut words = [0; 128];
+        for w in words.iter_mut() {
+            *w = xfer(d, 0xFFFF_FFFF);
+        }
+        xfer(d, 0xFF);
+        xfer(d, 0xFF);
+        words
+    }
+
+    #[test]
+    fn crc7_of_boot_commands() {
+        assert_eq!(crc7(&[0x40, 0, 0, 0, 0]) << 1 | 1, 0x95);
+        assert_eq!(crc7(&[0x48, 0, 0, 1, 0xAA]) << 1 | 1, 0x87);
+    }
+
+    #[test]
+    fn sd_init_handshake() {
+        let mut d = Disk::new(None).unwrap();
+        assert!(d.is_idle());
+        assert_eq!(cmd(&mut d, 17, 0, 1), R1_IDLE | R1_ILLEGAL_COMMAND);
+        assert_eq!(cmd(&mut d, 0, 0, 0x01), R1_IDLE | R1_CRC_ERROR);
+
+        assert_eq!(cmd(&mut d, 0, 0, 0x95), R1_IDLE);
+        assert_eq!(cmd(&mut d, 8, 0x1AA, 0x87), R1_IDLE);
+        let r7: Vec<u32> = (0..4).map(|_| xfer(&mut d, 0xFF)).collect();
+        assert_eq!(r7, [0, 0, 1, 0xAA]);
+
+        assert_eq!(cmd(&mut d, 58, 0, 1), R1_IDLE);
+        let ocr: Vec<u32> = (0..4).map(|_| xfer(&mut d, 0xFF)).collect();
+        assert_eq!(ocr, [0x00, 0xFF, 0x80, 0x00]);
+
+        // ACMD41 without HCS keeps an SDHC card busy
+        for _ in 0..4 {
+            assert_eq!(cmd(&mut d, 55, 0, 1), R1_IDLE);
+            assert_eq!(cmd(&mut d, 41, 0, 1), R1_IDLE);
+        }
+        assert_eq!(cmd(&mut d, 0, 0, 0x95), R1_IDLE);
+        assert_eq!(cmd(&mut d, 8, 0x1AA, 0x87), R1_IDLE);
+        loop {
+            cmd(&mut d, 55, 0, 1);
+            if cmd(&mut d, 41, 1 << 30, 1) == 0 {
+                break;
+            }
+        }
+        assert!(!d.is_idle());
+
+        assert_eq!(cmd(&mut d, 58, 0, 1), 0);
+        let ocr = (0..4).fold(0, |acc, _| acc << 8 | xfer(&mut d, 0xFF));
+        assert_eq!(ocr, OCR_READY);
+        assert_eq!(cmd(&mut d, 16, 512, 1), 0);
+        assert_eq!(cmd(&mut d, 16, 1024, 1), R1_PARAMETER_ERROR);
+        assert_eq!(cmd(&mut d, 5, 0, 1), R1_ILLEGAL_COMMAND);
+    }
+
+    #[test]
+    fn sd_single_and_multi_block_io() {
+        let path = image("io", 8);
+        let mut d = Disk::new(Some(&path)).unwrap();
+        assert_eq!(d.offset(), 0);
+        init(&mut d);
+
+        let a: [u32; 128] = std::array::from_fn(|i| 0x0101_0101 * i as u32);
+        let b: [u32; 128] = std::array::from_fn(|i| !(i as u32));
+
+        assert_eq!(cmd(&mut d, 24, 3, 1), 0);
+        assert_eq!(write_block(&mut d, TOKEN_START_BLOCK, &a), DATA_ACCEPTED);
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[3 * 512..3 * 512 + 8], &[0, 0, 0, 0, 1, 1, 1, 1]);
+
+        assert_eq!(cmd(&mut d, 25, 4, 1), 0);
+        assert_eq!(write_block(&mut d, TOKEN_START_MULTI_WRITE, &b), DATA_ACCEPTED);
+        assert_eq!(write_block(&mut d, TOKEN_START_MULTI_WRITE, &a), DATA_ACCEPTED);
+        xfer(&mut d, TOKEN_STOP_TRAN);
+        while xfer(&mut d, 0xFF) != 0xFF {}
+
+        assert_eq!(cmd(&mut d, 17, 4, 1), 0);
+        assert_eq!(read_block(&mut d), b);
+
+        assert_eq!(cmd(&mut d, 18, 3, 1), 0);
+        assert_eq!(read_block(&mut d), a);
+        assert_eq!(read_block(&mut d), b);
+        assert_eq!(read_block(&mut d), a);
+        assert_eq!(cmd(&mut d, 12, 0, 1), 0);
+        assert_eq!(cmd(&mut d, 13, 0, 1), 0);
+
+        // with CRC checking on, a bad data CRC is refused
+        assert_eq!(cmd(&mut d, 59, 1, crc7(&[0x7B, 0, 0, 0, 1]) << 1 | 1), 0);
+        assert_eq!(cmd(&mut d, 24, 5, 1), R1_CRC_ERROR);
+        let frame = [0x58, 0, 0, 0, 5];
+        assert_eq!(cmd(&mut d, 24, 5, crc7(&frame) << 1 | 1), 0);
+        assert_eq!(write_block(&mut d, TOKEN_START_BLOCK, &a), DATA_CRC_ERROR);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sd_reports_commands() {
+        let path = image("cmds", 8);
+        let mut d = Disk::new(Some(&path)).unwrap();
+        assert_eq!(d.take_command(), None);
+        init(&mut d);
+        let c = d.take_command().unwrap();
+        assert_eq!((c.cmd, c.app, c.sector), (41, true, None));
+
+        assert_eq!(cmd(&mut d, 17, 5, 1), 0);
+        let c = d.take_command().unwrap();
+        assert_eq!((c.cmd, c.arg, c.sector), (17, 5, Some(5)));
+        read_block(&mut d);
+        assert_eq!(d.take_command(), None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sd_fs_only_image_offset() {
+        let path = image("fs", 4);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[..4].copy_from_slice(&0x9B1E_A38Du32.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut d = Disk::new(Some(&path)).unwrap();
+        assert_eq!(d.offset(), 0x80002);
+        init(&mut d);
+        assert_eq!(cmd(&mut d, 17, 0x80000, 1), R1_PARAMETER_ERROR);
+        assert_eq!(cmd(&mut d, 17, 0x80002, 1), 0);
+        assert_eq!(read_block(&mut d)[0], 0x9B1E_A38D);
+        // past the end of the image reads as erased
+        assert_eq!(cmd(&mut d, 17, 0x80100, 1), 0);
+        assert_eq!(read_block(&mut d), [0; 128]);
+
+        assert_eq!(cmd(&mut d, 9, 0, 1), 0);
+        let csd = read_block_bytes(&mut d);
+        assert_eq!(csd[0] >> 6, 1);
+        let c_size = (csd[7] as u32 & 0x3F) << 16 | (csd[8] as u32) << 8 | csd[9] as u32;
+        assert!((c_size + 1) * 1024 >= 0x80002 + 4);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sd_read_only_reports_write_protect() {
+        let path = image("ro", 4);
+        let mut d = Disk::with_mode(&path, DiskMode::ReadOnly).unwrap();
+        assert!(d.is_write_protected());
+        init(&mut d);
+
+        assert_eq!(cmd(&mut d, 24, 1, 1), 0);
+        assert_eq!(write_block(&mut d, TOKEN_START_BLOCK, &[7; 128]), DATA_WRITE_ERROR);
+        assert_eq!(cmd(&mut d, 13, 0, 1), 0);
+        assert_eq!(xfer(&mut d, 0xFF), R2_WP_VIOLATION);
+        assert_eq!(cmd(&mut d, 13, 0, 1), 0);
+        assert_eq!(xfer(&mut d, 0xFF), 0);
+
+        assert_eq!(cmd(&mut d, 9, 0, 1), 0);
+        assert_eq!(read_block_bytes(&mut d)[14] & 0x10, 0x10);
+        assert_eq!(std::fs::read(&path).unwrap(), vec![0; 4 * 512]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sd_memory_overlay_commit() {
+        use risc_emulator::Machine;
+
+        let path = image("mem", 4);
+        let mut m = Machine::new_for_tests(vec![0xE7FF_FFFF], 0x400, 0x200, 8, 8);
+        m.attach_disk(1, &path, DiskMode::Overlay).unwrap();
+        let d = m.bus.io.spi[1].as_deref_mut().unwrap();
+        init(d);
+        assert_eq!(cmd(d, 24, 2, 1), 0);
+        assert_eq!(write_block(d, TOKEN_START_BLOCK, &[5; 128]), DATA_ACCEPTED);
+        // ejecting or attaching another image would lose the write
+        assert!(m.eject_disk(1).is_err());
+        assert!(m.attach_disk(1, &path, DiskMode::ReadOnly).is_err());
+        assert_eq!(m.disk_overlay(1).unwrap().dirty_blocks(), 1);
+        m.disk_overlay(1).unwrap().discard().unwrap();
+        m.eject_disk(1).unwrap();
+        assert!(m.disk_overlay(1).is_none());
+
+        let mut d = Disk::with_mode(&path, DiskMode::Overlay).unwrap();
+        let o = d.overlay().unwrap();
+        init(&mut d);
+        assert_eq!(cmd(&mut d, 24, 2, 1), 0);
+        assert_eq!(write_block(&mut d, TOKEN_START_BLOCK, &[0x0403_0201; 128]), DATA_ACCEPTED);
+        assert_eq!(o.dirty_blocks(), 1);
+        assert_eq!(std::fs::read(&path).unwrap(), vec![0; 4 * 512]);
+
+        assert_eq!(cmd(&mut d, 17, 2, 1), 0);
+        assert_eq!(read_block(&mut d), [0x0403_0201; 128]);
+
+        o.commit().unwrap();
+        assert!(!o.is_dirty());
+        assert_eq!(&std::fs::read(&path).unwrap()[1024..1028], &[1, 2, 3, 4]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sd_sidecar_overlay_survives_reopen() {
+        let path = image("side", 4);
+        let delta = DiskMode::sidecar_for(&path);
+        let _ = std::fs::remove_file(&delta);
+
+        let mut d = Disk::with_mode(&path, DiskMode::Sidecar(delta.clone())).unwrap();
+        init(&mut d);
+        assert_eq!(cmd(&mut d, 24, 3, 1), 0);
+        assert_eq!(write_block(&mut d, TOKEN_START_BLOCK, &[9; 128]), DATA_ACCEPTED);
+        drop(d);
+
+        // the default sidecar path is <image>.delta
+        let mut d = Disk::with_mode(&path, "sidecar".parse().unwrap()).unwrap();
+        let o = d.overlay().unwrap();
+        assert_eq!(o.sidecar_path(), Some(delta.clone()));
+        assert_eq!(o.dirty_blocks(), 1);
+        init(&mut d);
+        assert_eq!(cmd(&mut d, 17, 3, 1), 0);
+        assert_eq!(read_block(&mut d), [9; 128]);
+
+        o.discard().unwrap();
+        assert_eq!(cmd(&mut d, 17, 3, 1), 0);
+        assert_eq!(read_block(&mut d), [0; 128]);
+        drop(d);
+        let d = Disk::with_mode(&path, DiskMode::Sidecar(delta.clone())).unwrap();
+        assert!(!d.overlay().unwrap().is_dirty());
+        assert_eq!(std::fs::read(&path).unwrap(), vec![0; 4 * 512]);
+
+        std::fs::remove_file(&delta).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn read_block_bytes(d: &mut Disk) -> Vec<u8> {
+        while xfer(d, 0xFF) != TOKEN_START_BLOCK {}
+        (0..16).map(|_| xfer(d, 0xFF) as u8).collect()
+    }
+}
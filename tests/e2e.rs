@@ -1,3 +1,5 @@
+mod sd_card;
+
 use risc_emulator::{Machine, cpu::Cpu};
 
 use risc_emulator::machine::ROM_START;
@@ -50,7 +52,7 @@ fn e2e_framebuffer_write_updates_damage() {
     let mut m = Machine::new_for_tests(prog, mem_size, display_start, 8, 8);
 
     m.cpu.progress = 1000;
-    m.cpu.run(&mut m.bus, 10).unwrap();
+    m.cpu.run(&mut m.bus, 10).into_result().unwrap();
 
     let dmg = m.bus.reset_damage();
     // Vi skrev mindst én word i fb => damage må være “gyldigt” (x2>=x1 osv.)
@@ -61,3 +63,727 @@ fn e2e_framebuffer_write_updates_damage() {
     assert!(ROM_START > mem_size);
     assert!(IO_START > ROM_START);
 }
+
+#[test]
+fn e2e_virtual_timer_follows_instruction_count() {
+    // R1 = IO_START (-64), then loop: R0 = [R1] (ms counter)
+    let prog = vec![
+        reg(MOV, 1, 0, 0, true, false, true, 0xFFC0),
+        mem(0, 1, 0, false, false),
+        0xE7FF_FFFE,
+    ];
+
+    let mut m = Machine::new_for_tests(prog, 0x400, 0x200, 8, 8);
+
+    // default deterministic clock: 25 000 instructions per ms
+    for _ in 0..110_000 {
+        m.cpu.step(&mut m.bus).unwrap();
+    }
+    assert_eq!(m.cpu.r[0], 4);
+    assert_eq!(m.bus.io.timer.instructions(), 110_000);
+}
+
+#[test]
+fn e2e_decode_cache_sees_code_writes() {
+
+    const ADD: u32 = 8;
+    // word 1 is overwritten with word 6 and executed again
+    let prog = [
+        reg(MOV, 0, 0, 0, true, false, false, 0),
+        reg(ADD, 1, 1, 0, true, false, false, 1),
+        mem(2, 0, 24, false, false),
+        mem(2, 0, 4, true, false),
+        0xE7FF_FFFC,
+        0,
+        reg(ADD, 1, 1, 0, true, false, false, 100),
+    ];
+
+    let mut m = Machine::new_for_tests_in_ram(&prog, 0x400);
+    assert!(m.bus.decode_cache_enabled());
+    for _ in 0..6 {
+        m.cpu.step(&mut m.bus).unwrap();
+    }
+    assert_eq!(m.cpu.r[1], 101);
+
+    // direct RAM pokes need an explicit flush
+    m.bus.ram.write_word_le(4, reg(ADD, 1, 1, 0, true, false, false, 1000)).unwrap();
+    m.bus.flush_decode_cache();
+    m.cpu.pc = 4;
+    m.cpu.step(&mut m.bus).unwrap();
+    assert_eq!(m.cpu.r[1], 1101);
+}
+
+#[test]
+fn e2e_watchpoints_stop_after_access() {
+    use risc_emulator::cpu::{RunOutcome, StopReason};
+    use risc_emulator::debug::{Access, WatchHit, WatchKind};
+    use risc_emulator::translate::ExecMode;
+
+    let prog = vec![
+        reg(MOV, 1, 0, 0, true, false, false, 0x100),
+        reg(MOV, 2, 0, 0, true, false, false, 0x1234),
+        mem(2, 1, 0, true, false),
+        mem(2, 1, 0, true, false), // same value again
+        reg(MOV, 3, 0, 0, true, false, false, 0xAB),
+        mem(3, 1, 1, true, true),
+        mem(4, 1, 2, false, true),
+        0xE7FF_FFFF,
+    ];
+    let hit = |pc: u32, addr, size, access, old, new| WatchHit { pc: ROM_START + 4 * pc, addr, size, access, old, new };
+
+    for exec in [ExecMode::Interpret, ExecMode::Translate] {
+        let mut m = Machine::new_for_tests(prog.clone(), 0x400, 0x200, 8, 8);
+        m.exec = exec;
+        let change = m.bus.watchpoints.add(0x100, 4, WatchKind::Change);
+
+        let out = m.run(100);
+        assert_eq!(out, RunOutcome::new(3, StopReason::Watchpoint(hit(2, 0x100, 4, Access::Write, 0, 0x1234))));
+        // the byte store is reported as one byte, not as the word it rewrites
+        let out = m.run(100);
+        assert_eq!(out, RunOutcome::new(3, StopReason::Watchpoint(hit(5, 0x101, 1, Access::Write, 0x12, 0xAB))));
+        assert_eq!(m.bus.watchpoints.get(change).unwrap().hits, 2);
+
+        m.bus.watchpoints.remove(change);
+        m.bus.watchpoints.add(0x102, 1, WatchKind::Read);
+        let out = m.run(100);
+        assert_eq!(out, RunOutcome::new(1, StopReason::Watchpoint(hit(6, 0x102, 1, Access::Read, 0, 0))));
+        assert_eq!(m.run(100).stop, StopReason::Budget);
+    }
+}
+
+#[test]
+fn e2e_change_watch_ignores_bytes_outside_it() {
+    use risc_emulator::cpu::{RunOutcome, StopReason};
+    use risc_emulator::debug::{Access, WatchHit, WatchKind};
+    use risc_emulator::translate::ExecMode;
+
+    let prog = vec![
+        reg(MOV, 1, 0, 0, true, false, false, 0x100),
+        reg(MOV, 2, 0, 0, true, false, false, 0x1234),
+        mem(2, 1, 0, true, false), // changes the low half only
+        reg(MOV, 2, 0, 0, true, true, false, 0x56),
+        mem(2, 1, 0, true, false),
+        0xE7FF_FFFF,
+    ];
+    for exec in [ExecMode::Interpret, ExecMode::Translate] {
+        let mut m = Machine::new_for_tests(prog.clone(), 0x400, 0x200, 8, 8);
+        m.exec = exec;
+        let id = m.bus.watchpoints.add(0x102, 2, WatchKind::Change);
+        let hit = WatchHit { pc: ROM_START + 16, addr: 0x100, size: 4, access: Access::Write, old: 0x1234, new: 0x56_0000 };
+        assert_eq!(m.run(100), RunOutcome::new(5, StopReason::Watchpoint(hit)));
+        assert_eq!(m.bus.watchpoints.get(id).unwrap().hits, 1);
+    }
+}
+
+#[test]
+fn e2e_device_breaks_report_the_causing_instruction() {
+    use risc_emulator::cpu::StopReason;
+    use risc_emulator::debug::{Access, DeviceBreak, DeviceEvent, DeviceHit};
+
+    let prog = vec![
+        reg(MOV, 1, 0, 0, true, false, true, 0xFFC0), // R1 = IO_START
+        reg(MOV, 2, 0, 0, true, false, false, 0x55),
+        mem(2, 1, 4, true, false),                     // LEDs
+        mem(3, 1, 28, false, false),                   // no key yet
+        mem(3, 1, 28, false, false),
+        reg(MOV, 4, 0, 0, true, false, false, 0x200 + 8 * 4 + 4),
+        mem(2, 4, 0, true, true),                      // framebuffer word (1, 1)
+        0xE7FF_FFFF,
+    ];
+    let mut m = Machine::new_for_tests(prog, 0x400, 0x200, 8, 8);
+    let at = |i: u32, event| StopReason::Device(DeviceHit { pc: ROM_START + 4 * i, event });
+    for b in ["io:4:write", "key:1C", "fb:40,1,8,1", "fb:0,2,256,6"] {
+        m.bus.device_breaks.add(b.parse::<DeviceBreak>().unwrap());
+    }
+
+    assert_eq!(m.run(100).stop, at(2, DeviceEvent::Io { offset: 4, access: Access::Write, value: 0x55 }));
+    m.bus.io.input.keyboard_input(&[0x1C]).unwrap();
+    // the first read takes the key
+    assert_eq!(m.run(100).stop, at(3, DeviceEvent::Key(0x1C)));
+    assert_eq!(m.run(100).stop, at(6, DeviceEvent::Framebuffer { x: 32, y: 1 }));
+    assert_eq!(m.run(100).stop, StopReason::Budget);
+    let hits: Vec<u64> = m.bus.device_breaks.iter().map(|b| b.hits).collect();
+    assert_eq!(hits, [1, 1, 1, 0]);
+}
+
+#[test]
+fn e2e_breakpoint_after_idle_read_is_not_skipped() {
+    use risc_emulator::cpu::{RunOutcome, StopReason};
+    use risc_emulator::translate::ExecMode;
+
+    const ADD: u32 = 8;
+    let mut prog = vec![reg(MOV, 2, 0, 0, true, false, true, 0xFFC0)];
+    // a run counts as idle after 20 timer reads
+    prog.extend([mem(4, 2, 0, false, false); 20]);
+    prog.extend([reg(ADD, 6, 6, 0, true, false, false, 1), 0xE7FF_FFFF]);
+    let at = ROM_START + 4 * 21;
+
+    for exec in [ExecMode::Interpret, ExecMode::Translate] {
+        let mut m = Machine::new_for_tests(prog.clone(), 0x400, 0x200, 8, 8);
+        m.exec = exec;
+        m.cpu.breakpoints.insert(at);
+        assert_eq!(m.run(1000), RunOutcome::new(21, StopReason::Breakpoint(at)));
+        assert_eq!(m.cpu.r[6], 0);
+        assert_eq!(m.cpu.breakpoints.get(at).unwrap().hits, 1);
+    }
+}
+
+#[test]
+fn e2e_conditional_breakpoints_and_logpoints() {
+    use risc_emulator::cpu::StopReason;
+    use risc_emulator::debug::BreakSpec;
+    use risc_emulator::translate::ExecMode;
+
+    const ADD: u32 = 8;
+    let prog = vec![
+        reg(MOV, 1, 0, 0, true, false, false, 0x100),
+        reg(ADD, 0, 0, 0, true, false, false, 1), // loop: R0 += 1
+        mem(0, 1, 0, true, false),                // [0x100] := R0
+        0xE7FF_FFFD,                              // B loop
+    ];
+    let head = ROM_START + 4;
+
+    for exec in [ExecMode::Interpret, ExecMode::Translate] {
+        let mut m = Machine::new_for_tests(prog.clone(), 0x400, 0x200, 8, 8);
+        m.exec = exec;
+        let spec: BreakSpec = format!("{head:X} if [0x100] == 5 && R0 == 5").parse().unwrap();
+        spec.apply(m.cpu.breakpoints.insert(spec.addr));
+        let log: BreakSpec = format!("{:X} if hits % 2 == 0 log n={{R0}} b={{b[R1]:x}}", head + 8).parse().unwrap();
+        log.apply(m.cpu.breakpoints.insert(log.addr));
+
+        let out = m.run(1000);
+        assert_eq!(out.stop, StopReason::Breakpoint(head));
+        assert_eq!((m.cpu.r[0], m.cpu.breakpoints.get(head).unwrap().hits), (5, 6));
+        assert_eq!(m.cpu.breakpoints.take_log(), [format!("{:08X} n=2 b=2", head + 8), format!("{:08X} n=4 b=4", head + 8)]);
+
+        // a logpoint never stops the run
+        m.cpu.breakpoints.remove(head);
+        assert_eq!(m.run(30).stop, StopReason::Budget);
+        assert_eq!(m.cpu.breakpoints.take_log().len(), 5);
+    }
+}
+
+mod translate {
+    use super::*;
+    use risc_emulator::cpu::{RunOutcome, StopReason};
+    use risc_emulator::translate::ExecMode;
+
+    const ADD: u32 = 8;
+    const SUB: u32 = 9;
+
+    fn machine(prog: &[u32], exec: ExecMode) -> Machine {
+        let mut m = Machine::new_for_tests_in_ram(prog, 0x1000);
+        m.exec = exec;
+        m
+    }
+
+    // counts R1 down from 1000, storing the timer each round, then writes
+    // word 11 over word 3 and stops at the breakpoint on word 9
+    fn program() -> Vec<u32> {
+        vec![
+            reg(MOV, 1, 0, 0, true, false, false, 1000),
+            reg(MOV, 2, 0, 0, true, false, true, 0xFFC0),
+            reg(MOV, 3, 0, 0, true, false, false, 0x400),
+            mem(4, 2, 0, false, false),
+            mem(4, 3, 0, true, false),
+            reg(SUB, 1, 1, 0, true, false, false, 1),
+            0xE9FF_FFFC, // BNE -4
+            mem(5, 0, 4 * 11, false, false),
+            mem(5, 0, 4 * 3, true, false),
+            reg(ADD, 6, 6, 0, true, false, false, 1),
+            0xE7FF_FFFF,
+            reg(ADD, 7, 7, 0, true, false, false, 1),
+        ]
+    }
+
+    #[test]
+    fn e2e_translated_matches_interpreter() {
+        let mut runs = Vec::new();
+        for exec in [ExecMode::Interpret, ExecMode::Translate, ExecMode::Lockstep] {
+            let mut m = machine(&program(), exec);
+            m.cpu.breakpoints.insert(4 * 9);
+            let mut exits = Vec::new();
+            while exits.last().is_none_or(|e: &RunOutcome| e.stop == StopReason::Idle) {
+                exits.push(m.run(20_000));
+            }
+            // back to the loop, now running the patched word
+            m.cpu.pc = 4 * 3;
+            m.cpu.r[1] = 10;
+            exits.push(m.run(100));
+            runs.push((exits, m.cpu.view().r, m.bus.io.timer.instructions(), m.bus.peek_word_le(0x400).unwrap()));
+        }
+        let (exits, r, instructions, _) = &runs[0];
+        // the 20th timer read ends the first run
+        assert_eq!(exits[0], RunOutcome::new(3 + 19 * 4 + 1, StopReason::Idle));
+        assert_eq!(exits[exits.len() - 2].stop, StopReason::Breakpoint(4 * 9));
+        assert_eq!(exits.last(), Some(&RunOutcome::new(10 * 4 + 2, StopReason::Breakpoint(4 * 9))));
+        assert_eq!(r[7], 10);
+        assert_eq!(*instructions, 3 + 1000 * 4 + 2 + 10 * 4 + 2);
+        assert_eq!(runs[1], runs[0]);
+        assert_eq!(runs[2], runs[0]);
+    }
+
+    #[test]
+    fn e2e_breakpoint_set_inside_translated_block() {
+        let prog = [
+            reg(ADD, 1, 1, 0, true, false, false, 1),
+            reg(ADD, 2, 2, 0, true, false, false, 2),
+            reg(ADD, 3, 3, 0, true, false, false, 3),
+            0xE7FF_FFFC, // B 0
+        ];
+        let mut runs = Vec::new();
+        for exec in [ExecMode::Interpret, ExecMode::Translate] {
+            let mut m = machine(&prog, exec);
+            let first = m.run(40);
+            // the loop has been translated as one block by now
+            m.cpu.breakpoints.insert(4 * 2).ignore = 2;
+            let second = m.run(1000);
+            let hits = m.cpu.breakpoints.get(4 * 2).unwrap().hits;
+            m.cpu.breakpoints.remove(4 * 2);
+            let third = m.run(1000);
+            runs.push((first, second, hits, third, m.cpu.view().r));
+        }
+        let (_, second, hits, third, _) = &runs[0];
+        assert_eq!(*second, RunOutcome::new(2 + 2 * 4, StopReason::Breakpoint(4 * 2)));
+        assert_eq!(*hits, 3);
+        assert_eq!(third.stop, StopReason::Budget);
+        assert_eq!(runs[1], runs[0]);
+    }
+}
+
+#[test]
+fn e2e_step_back_and_reverse_continue() {
+    use risc_emulator::cpu::{RunOutcome, StopReason};
+    use risc_emulator::debug::{Access, WatchHit, WatchKind};
+    use risc_emulator::translate::ExecMode;
+
+    const ADD: u32 = 8;
+    const MUL: u32 = 10;
+    let prog = vec![
+        reg(MOV, 1, 0, 0, true, false, false, 0x100),
+        reg(MOV, 2, 0, 0, true, false, false, 0),
+        reg(ADD, 2, 2, 0, true, false, false, 1), // loop
+        mem(2, 1, 0, true, false),
+        mem(2, 1, 5, true, true),
+        reg(MUL, 3, 2, 2, false, false, false, 0),
+        0xE7FF_FFFB,
+    ];
+    let at = |i: u32| ROM_START + 4 * i;
+
+    for exec in [ExecMode::Interpret, ExecMode::Translate] {
+        let mut m = Machine::new_for_tests(prog.clone(), 0x400, 0x200, 8, 8);
+        m.exec = exec;
+        m.set_history(Some(1000));
+        // two instructions, then three times round the loop
+        m.run(17);
+        let state = |m: &mut Machine| {
+            let v = m.cpu.view();
+            let ram = (m.bus.peek_word_le(0x100).unwrap(), m.bus.peek_word_le(0x104).unwrap());
+            (v.pc, v.r, v.h, v.n, v.z, v.c, v.v, ram, m.bus.io.timer.instructions())
+        };
+        let saved = state(&mut m);
+        assert_eq!(saved.1[2], 3);
+
+        m.run(23);
+        assert_ne!(state(&mut m), saved);
+        for _ in 0..23 {
+            assert!(m.step_back());
+        }
+        assert_eq!(state(&mut m), saved);
+
+        // backwards to the byte store, the last instruction of the second
+        // round with a breakpoint
+        m.cpu.breakpoints.insert(at(4));
+        assert_eq!(m.reverse_continue(100), RunOutcome::new(3, StopReason::Breakpoint(at(4))));
+        assert_eq!(m.bus.peek_word_le(0x104).unwrap(), 0x0200);
+        m.cpu.breakpoints.clear();
+
+        // to before the stores that watchpoints report
+        let word = m.bus.watchpoints.add(0x100, 4, WatchKind::Write);
+        let hit = |pc, addr, size, old, new| WatchHit { pc: at(pc), addr, size, access: Access::Write, old, new };
+        assert_eq!(m.reverse_continue(100), RunOutcome::new(1, StopReason::Watchpoint(hit(3, 0x100, 4, 2, 3))));
+        assert_eq!(m.bus.peek_word_le(0x100).unwrap(), 2);
+        m.bus.watchpoints.remove(word);
+        m.bus.watchpoints.add(0x105, 1, WatchKind::Change);
+        assert_eq!(m.reverse_continue(100), RunOutcome::new(4, StopReason::Watchpoint(hit(4, 0x105, 1, 1, 2))));
+        // counted on the way forward only
+        assert_eq!(m.bus.watchpoints.iter().next().unwrap().hits, 0);
+        m.bus.watchpoints.clear();
+
+        let out = m.reverse_continue(1000);
+        assert_eq!(out.stop, StopReason::HistoryStart);
+        assert_eq!(state(&mut m), (ROM_START, [0; 16], 0, false, false, false, false, (0, 0), 0));
+        assert!(!m.step_back());
+    }
+
+    // only the last `limit` instructions are kept
+    let mut m = Machine::new_for_tests(prog, 0x400, 0x200, 8, 8);
+    m.set_history(Some(5));
+    m.run(12);
+    for _ in 0..5 {
+        assert!(m.step_back());
+    }
+    assert!(!m.step_back());
+    assert_eq!(m.cpu.pc, at(2));
+}
+
+#[test]
+fn e2e_trace_records_round_trip() {
+    use std::sync::{Arc, Mutex};
+
+    use risc_emulator::debug::{MemAccess, TraceReader, TraceRecord, TraceWriter, Tracer};
+    use risc_emulator::translate::ExecMode;
+
+    #[derive(Debug, Default)]
+    struct Collect(Vec<TraceRecord>);
+
+    impl Tracer for Collect {
+        fn record(&mut self, rec: &TraceRecord) {
+            self.0.push(*rec);
+        }
+    }
+
+    let prog = vec![
+        reg(MOV, 1, 0, 0, true, false, false, 0x100),
+        reg(MOV, 2, 0, 0, true, false, false, 0x1234),
+        mem(2, 1, 0, true, false),
+        mem(3, 1, 1, false, true),
+        0xF700_0000, // BL +0
+        0xE7FF_FFFF,
+    ];
+    let mut m = Machine::new_for_tests(prog.clone(), 0x400, 0x200, 8, 8);
+    m.exec = ExecMode::Translate;
+    let seen = Arc::new(Mutex::new(Collect::default()));
+    let file = Arc::new(Mutex::new(TraceWriter::new(Vec::new()).unwrap()));
+    m.cpu.tracers.push(seen.clone());
+    m.cpu.tracers.push(file.clone());
+    m.run(7);
+
+    let at = |i: u32| ROM_START + 4 * i;
+    let rec = |i: u32, dest, flags, mem| TraceRecord { pc: at(i), ir: prog[i as usize], dest, flags, mem };
+    let access = |addr, value, store, byte| Some(MemAccess { addr, value, store, byte });
+    let expected = vec![
+        rec(0, Some((1, 0x100)), 0, None),
+        rec(1, Some((2, 0x1234)), 0, None),
+        rec(2, None, 0, access(0x100, 0x1234, true, false)),
+        rec(3, Some((3, 0x12)), 0, access(0x101, 0x12, false, true)),
+        // the link register is written through set_reg, so N follows R15
+        rec(4, Some((15, at(5))), 8, None),
+        rec(5, None, 8, None),
+        rec(5, None, 8, None),
+    ];
+    assert_eq!(seen.lock().unwrap().0, expected);
+
+    let mut file = file.lock().unwrap();
+    file.finish().unwrap();
+    assert_eq!(file.records(), 7);
+    let back: Vec<_> = TraceReader::new(file.get_ref().as_slice()).unwrap().map(Result::unwrap).collect();
+    assert_eq!(back, expected);
+    // a cut-off last record is an error, not a silent end
+    let bytes = file.get_ref();
+    let mut cut = TraceReader::new(&bytes[..bytes.len() - 1]).unwrap();
+    assert!(cut.by_ref().take(6).all(|r| r.is_ok()));
+    assert!(cut.next().unwrap().is_err());
+    // so is a lone byte of a record head
+    let mut stray = bytes.clone();
+    stray.push(0);
+    let mut cut = TraceReader::new(stray.as_slice()).unwrap();
+    assert!(cut.by_ref().take(7).all(|r| r.is_ok()));
+    assert!(cut.next().unwrap().is_err());
+}
+
+#[test]
+fn e2e_profile_counts_and_call_stacks() {
+    use std::sync::{Arc, Mutex};
+
+    use risc_emulator::debug::{OpClass, Profiler};
+
+    let prog = vec![
+        reg(MOV, 0, 0, 0, true, false, false, 2),
+        0xF700_0003, // BL f
+        reg(9, 0, 0, 0, true, false, false, 1), // SUB R0, R0, 1
+        0xE9FF_FFFD, // BNE back to the BL
+        0xE7FF_FFFF, // B self
+        // f:
+        reg(10, 1, 0, 0, false, false, false, 0), // MUL R1, R0, R0
+        0xC700_000F, // B R15
+    ];
+    let mut m = Machine::new_for_tests(prog, 0x400, 0x200, 8, 8);
+    let p = Arc::new(Mutex::new(Profiler::new()));
+    m.cpu.tracers.push(p.clone());
+    m.run(13);
+
+    let p = p.lock().unwrap();
+    let at = |i: u32| ROM_START + 4 * i;
+    assert_eq!(p.total(), 13);
+    assert_eq!(p.pc(at(4)).count, 2);
+    assert_eq!((p.pc(at(1)).taken, p.pc(at(1)).not_taken), (2, 0));
+    assert_eq!((p.pc(at(3)).taken, p.pc(at(3)).not_taken), (1, 1));
+    assert_eq!((p.mix(OpClass::Alu), p.mix(OpClass::Mul), p.mix(OpClass::Branch)), (3, 2, 8));
+
+    let procs = p.procedures();
+    assert_eq!(procs.len(), 1);
+    assert_eq!((procs[0].entry, procs[0].own, procs[0].total), (at(5), 4, 4));
+
+    let mut folded = Vec::new();
+    p.write_folded(&mut folded, &|entry| if entry == at(5) { "f".into() } else { format!("{entry:08X}") }).unwrap();
+    assert_eq!(String::from_utf8(folded).unwrap(), "(root) 9\n(root);f 4\n");
+}
+
+#[test]
+fn e2e_call_tracer_writes_chrome_events_with_symbols() {
+    use std::sync::{Arc, Mutex};
+
+    use risc_emulator::bus::Bus;
+    use risc_emulator::debug::{CallTracer, Symbols, Timebase};
+
+    // the program of e2e_profile_counts_and_call_stacks: two calls of f
+    let prog = vec![
+        reg(MOV, 0, 0, 0, true, false, false, 2),
+        0xF700_0003,
+        reg(9, 0, 0, 0, true, false, false, 1),
+        0xE9FF_FFFD,
+        0xE7FF_FFFF,
+        reg(10, 1, 0, 0, false, false, false, 0),
+        0xC700_000F,
+    ];
+    let mut m = Machine::new_for_tests(prog, 0x400, 0x200, 8, 8);
+    let at = |i: u32| ROM_START + 4 * i;
+
+    // a module list as Oberon keeps it: root at 20, descriptor at 0x100
+    // (name, next at 32, code at 56, end of code at 60, commands at 64)
+    m.bus.write_word(20, 0x100).unwrap();
+    m.bus.write_word(0x100, u32::from_le_bytes(*b"Demo")).unwrap();
+    m.bus.write_word(0x104, 0).unwrap();
+    m.bus.write_word(0x100 + 32, 0).unwrap();
+    m.bus.write_word(0x100 + 56, at(0)).unwrap();
+    m.bus.write_word(0x100 + 60, at(7)).unwrap();
+    m.bus.write_word(0x100 + 64, 0x180).unwrap();
+    m.bus.write_word(0x180, u32::from_le_bytes(*b"Run\0")).unwrap();
+    m.bus.write_word(0x184, 20).unwrap();
+    m.bus.write_word(0x188, 0).unwrap();
+
+    let mut symbols = Symbols::new();
+    assert_eq!(symbols.read_modules(&mut |a| m.bus.peek_word_le(a).ok()), 1);
+    assert_eq!(symbols.name(at(5)), "Demo.Run");
+    assert_eq!(symbols.name(at(2)), "Demo+8");
+    symbols.parse_map("# host symbols\nFFFFF814 f\n").unwrap();
+    assert_eq!(symbols.name(at(5)), "f");
+    assert_eq!(symbols.name(at(9)), "f+10");
+
+    let calls = Arc::new(Mutex::new(CallTracer::new(Timebase::Instructions, 0)));
+    m.cpu.tracers.push(calls.clone());
+    m.run(8);
+
+    // the second call is still open and is closed at the end
+    let mut json = Vec::new();
+    calls.lock().unwrap().write_json(&mut json, &symbols).unwrap();
+    let ev = |ph: &str, ts: u32| format!("{{\"name\":\"f\",\"ph\":\"{ph}\",\"ts\":{ts},\"pid\":1,\"tid\":1}}");
+    let expected = format!("[\n{},\n{},\n{},\n{}\n]\n", ev("B", 2), ev("E", 4), ev("B", 7), ev("E", 8));
+    assert_eq!(String::from_utf8(json).unwrap(), expected);
+}
+
+#[test]
+fn e2e_coverage_bitmaps_merge_and_lcov() {
+    use std::sync::{Arc, Mutex};
+
+    use risc_emulator::debug::{AddrRange, Coverage, Symbols};
+
+    let prog = [
+        reg(MOV, 0, 0, 0, true, false, false, 2),
+        reg(9, 0, 0, 0, true, false, false, 1), // SUB R0, R0, 1
+        0xE9FF_FFFE, // BNE back to the SUB
+        0xE100_0001, // BEQ +1
+        0xE000_0000, // BMI +0, skipped by the BEQ
+        0xE7FF_FFFF, // B self
+    ];
+    let run = |pc: u32, n: u32| {
+        let mut m = Machine::new_for_tests_in_ram(&prog, 0x400);
+        m.cpu.pc = pc;
+        let cov = Arc::new(Mutex::new(Coverage::new(m.bus.mem_size)));
+        m.cpu.tracers.push(cov.clone());
+        m.run(n);
+        let mut cov = cov.lock().unwrap().clone();
+        cov.scan(&mut |a| m.bus.peek_word_le(a).ok(), &Symbols::new());
+        cov
+    };
+    let units = [("prog".to_string(), AddrRange { first: 0, last: 4 * prog.len() as u32 - 1 })];
+    assert_eq!("0X0-0x17".parse::<AddrRange>(), Ok(units[0].1));
+
+    let mut a = run(0, 8);
+    assert!(a.executed(12) && !a.executed(16));
+    assert_eq!(a.directions(8), (true, true));
+    assert_eq!(a.directions(12), (true, false));
+    let r = &a.report(&units)[0];
+    assert_eq!((r.instructions, r.executed, r.branches, r.directions), (6, 5, 3, 3));
+
+    // a second run that only reaches the BMI
+    let b = Coverage::from_bytes(&run(16, 2).to_bytes()).unwrap();
+    a.merge(&b).unwrap();
+    let r = &a.report(&units)[0];
+    assert_eq!((r.executed, r.directions), (6, 4));
+    assert!(a.merge(&Coverage::new(0x800)).is_err());
+
+    let mut lcov = Vec::new();
+    a.write_lcov(&mut lcov, &units).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    assert!(lcov.starts_with("TN:\nSF:prog\nDA:1,1\n"));
+    assert!(lcov.contains("DA:3,1\nBRDA:3,0,0,1\nBRDA:3,0,1,1\n"));
+    assert!(lcov.contains("DA:5,1\nBRDA:5,0,0,0\nBRDA:5,0,1,1\n"));
+    assert!(lcov.ends_with("BRF:6\nBRH:4\nLF:6\nLH:6\nend_of_record\n"));
+}
+
+#[test]
+fn e2e_trace_sees_taken_conditional_bl() {
+    use std::sync::{Arc, Mutex};
+
+    use risc_emulator::debug::{CallEvent, CallStack, TraceRecord, Tracer};
+
+    #[derive(Debug, Default)]
+    struct Collect(Vec<TraceRecord>);
+
+    impl Tracer for Collect {
+        fn record(&mut self, rec: &TraceRecord) {
+            self.0.push(*rec);
+        }
+    }
+
+    let prog = vec![
+        reg(MOV, 0, 0, 0, true, false, false, 0), // Z set
+        0xF900_0000, // BLNE +0, not taken
+        0xF100_0001, // BLEQ +1, taken; writing R15 clears Z
+        0xE7FF_FFFF,
+        0xE7FF_FFFF,
+    ];
+    let mut m = Machine::new_for_tests(prog, 0x400, 0x200, 8, 8);
+    let seen = Arc::new(Mutex::new(Collect::default()));
+    m.cpu.tracers.push(seen.clone());
+    m.run(4);
+
+    let at = |i: u32| ROM_START + 4 * i;
+    let recs = &seen.lock().unwrap().0;
+    assert_eq!(recs.iter().map(|r| r.pc).collect::<Vec<_>>(), [at(0), at(1), at(2), at(4)]);
+    assert_eq!(recs[1].dest, None);
+    assert_eq!(recs[2].dest, Some((15, at(3))));
+
+    let mut calls = Vec::new();
+    let mut stack = CallStack::new();
+    for rec in recs {
+        stack.update(rec, &mut |ev| calls.push(ev));
+    }
+    assert_eq!(calls, [CallEvent::Call(at(4))]);
+}
+
+#[test]
+fn e2e_symbols_follow_modules_root() {
+    use risc_emulator::bus::Bus;
+    use risc_emulator::debug::Symbols;
+
+    let mut m = Machine::new_for_tests(vec![0xE7FF_FFFF], 0x1000, 0x800, 8, 8);
+    let mut put = |addr: u32, words: &[u32]| {
+        for (i, w) in words.iter().enumerate() {
+            m.bus.write_word(addr + 4 * i as u32, *w).unwrap();
+        }
+    };
+    let name = |s: &str| -> [u32; 8] {
+        let mut b = [0u8; 32];
+        b[..s.len()].copy_from_slice(s.as_bytes());
+        std::array::from_fn(|i| u32::from_le_bytes(b[4 * i..4 * i + 4].try_into().unwrap()))
+    };
+    // descriptor: name, next, key, num, size, refcnt, data, code, imp, cmd
+    let mut desc = |addr: u32, n: &str, next: u32, data: u32, code: u32, end: u32, cmd: u32| {
+        put(addr, &name(n));
+        put(addr + 32, &[next, 0, 0, 0, 0, data, code, end, cmd]);
+    };
+
+    // the inner core as the boot loader leaves it: Modules, then Files
+    desc(0x100, "Modules", 0x180, 0x200, 0x240, 0x260, 0x260);
+    desc(0x180, "Files", 0, 0x2A0, 0x2A0, 0x2C0, 0x2C0);
+    // App, loaded later, in front of Modules.root only
+    desc(0x300, "App", 0x100, 0x350, 0x360, 0x380, 0x380);
+    put(20, &[0x100]);
+    put(0x260, &[0]);
+    put(0x2C0, &[0]);
+    put(0x380, &[u32::from_le_bytes(*b"Run\0"), 8, 0]);
+    // data of Modules: a type descriptor, then root and M
+    put(0x200, &[0x50, 0xFFFF_FFFF, 0, 0, 0x20, 0xFFFF_FFFF, 0, 0]);
+    put(0x220, &[0x300, 0x100, 0x1234, 0]);
+
+    let mut symbols = Symbols::new();
+    assert_eq!(symbols.read_modules(&mut |a| m.bus.peek_word_le(a).ok()), 3);
+    let names: Vec<_> = symbols.modules().iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["App", "Modules", "Files"]);
+    assert_eq!(symbols.name(0x368), "App.Run");
+    assert_eq!(symbols.name(0x364), "App+4");
+    assert_eq!(symbols.name(0x244), "Modules+4");
+}
+
+#[test]
+fn e2e_save_state_round_trip() {
+    use risc_emulator::devices::disk::{DiskMode, DATA_ACCEPTED, TOKEN_START_BLOCK};
+    use sd_card::{cmd, image, init, write_block, xfer};
+
+    let path = image("state", 4);
+    let mut m = Machine::new_for_tests(vec![0xE7FF_FFFF, 0x1234_5678], 0x400, 0x200, 8, 8);
+    m.attach_disk(1, &path, DiskMode::Overlay).unwrap();
+    let d = m.bus.io.spi[1].as_deref_mut().unwrap();
+    init(d);
+    assert_eq!(cmd(d, 24, 2, 1), 0);
+    let block: [u32; 128] = std::array::from_fn(|i| i as u32 * 3);
+    assert_eq!(write_block(d, TOKEN_START_BLOCK, &block), DATA_ACCEPTED);
+    // save in the middle of reading it back
+    assert_eq!(cmd(d, 17, 2, 1), 0);
+    while xfer(d, 0xFF) != TOKEN_START_BLOCK {}
+    assert_eq!(xfer(d, 0xFFFF_FFFF), 0);
+
+    m.run(1000);
+    m.cpu.r[3] = 0xCAFE;
+    m.cpu.c = true;
+    m.bus.ram.write_word_le(0x100, 0xDEAD_BEEF).unwrap();
+    m.bus.io.input.keyboard_input(&[0x1C]).unwrap();
+    m.panel.set_switches(5);
+    let state = m.state_bytes().unwrap();
+
+    let mut m2 = Machine::new_for_tests(vec![0; 4], 0x800, 0x400, 8, 8);
+    assert!(m2.restore_state(b"RISCSAVE").is_err());
+    assert!(m2.restore_state(&state[..state.len() - 1]).is_err());
+    assert_eq!(m2.bus.mem_size, 0x800);
+
+    let file = std::env::temp_dir().join(format!("risc-sd-{}.state", std::process::id()));
+    std::fs::write(&file, &state).unwrap();
+    m2.load_state(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!((m2.cpu.pc, m2.cpu.r, m2.cpu.c), (m.cpu.pc, m.cpu.r, true));
+    assert_eq!(m2.bus.mem_size, 0x400);
+    assert_eq!(m2.bus.peek_word_le(0x100).unwrap(), 0xDEAD_BEEF);
+    assert_eq!(m2.bus.peek_word_le(ROM_START + 4).unwrap(), 0x1234_5678);
+    assert_eq!(m2.bus.io.timer.instructions(), 1000);
+    assert!(m2.bus.io.input.key_pending());
+    assert_eq!(m2.panel.switches(), 5);
+    assert_eq!(m2.disk_image(1), m.disk_image(1));
+    assert_eq!(m2.disk_overlay(1).unwrap().dirty_blocks(), 1);
+
+    // the transfer goes on where it was
+    let d = m2.bus.io.spi[1].as_deref_mut().unwrap();
+    let rest: Vec<u32> = (1..128).map(|_| xfer(d, 0xFFFF_FFFF)).collect();
+    assert_eq!(rest, block[1..]);
+
+    // loading would drop the block written since
+    m2.cpu.r[3] = 1;
+    assert!(m2.restore_state(&state).is_err());
+    assert_eq!(m2.cpu.r[3], 1);
+    m2.disk_overlay(1).unwrap().discard().unwrap();
+    m2.restore_state(&state).unwrap();
+    assert_eq!(m2.cpu.r[3], 0xCAFE);
+    std::fs::remove_file(&path).unwrap();
+
+    // a ROM in RAM or past the end of the address space
+    let rom_start = state.windows(4).position(|w| w == b"ROM ").unwrap() + 8;
+    let mut m3 = Machine::new_for_tests(vec![0; 4], 0x800, 0x400, 8, 8);
+    for start in [0x100u32, 0xFFFF_FFFC] {
+        let mut bad = state.clone();
+        bad[rom_start..rom_start + 4].copy_from_slice(&start.to_le_bytes());
+        assert!(m3.restore_state(&bad).is_err());
+        assert_eq!(m3.bus.mem_size, 0x800);
+    }
+}
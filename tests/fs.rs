@@ -0,0 +1,305 @@
+use std::path::PathBuf;
+
+use risc_emulator::fs::image::{Image, FS_ONLY_OFFSET};
+use risc_emulator::fs::layout::*;
+use risc_emulator::fs::{DirPage, FileSystem, FsError};
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("risc-fs-{}-{name}.img", std::process::id()))
+}
+
+fn read_at(path: &std::path::Path, pos: u64, len: usize) -> Vec<u8> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut f = std::fs::File::open(path).unwrap();
+    f.seek(SeekFrom::Start(pos)).unwrap();
+    let mut buf = vec![0; len];
+    f.read_exact(&mut buf).unwrap();
+    buf
+}
+
+/// Filesystem-only image holding just an empty root page.
+fn fs_only(name: &str) -> PathBuf {
+    let path = temp(name);
+    std::fs::write(&path, DirPage::default().encode()).unwrap();
+    path
+}
+
+/// Depth of every leaf, checking page fill on the way.
+fn check_tree(img: &mut Image, adr: u32, depth: usize, leaves: &mut Vec<usize>) {
+    let page = DirPage::decode(&img.read_sector(adr).unwrap(), adr).unwrap();
+    if adr != DIR_ROOT_ADR {
+        assert!(page.entries.len() >= DIR_PG_MIN, "page {adr} underfull");
+    }
+    assert!(page.entries.windows(2).all(|w| w[0].name < w[1].name));
+    if page.p0 == 0 {
+        assert!(page.entries.iter().all(|e| e.p == 0));
+        leaves.push(depth);
+        return;
+    }
+    check_tree(img, page.p0, depth + 1, leaves);
+    for e in &page.entries {
+        check_tree(img, e.p, depth + 1, leaves);
+    }
+}
+
+fn assert_balanced(fs: &mut FileSystem) {
+    let mut leaves = Vec::new();
+    check_tree(fs.image(), DIR_ROOT_ADR, 0, &mut leaves);
+    assert!(leaves.windows(2).all(|w| w[0] == w[1]), "leaves at depths {leaves:?}");
+}
+
+#[test]
+fn fs_put_get_sizes() {
+    let path = fs_only("sizes");
+    let mut fs = FileSystem::open(&path).unwrap();
+    assert_eq!(fs.image().offset(), FS_ONLY_OFFSET);
+
+    let first = SECTOR_SIZE - HEADER_SIZE;
+    let big: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let files: Vec<(&str, Vec<u8>)> = vec![
+        ("Empty.Txt", vec![]),
+        ("Small.Mod", b"MODULE Small; END Small.".to_vec()),
+        ("Exact.Bin", vec![0xAB; first]),
+        ("Two.Bin", vec![0xCD; first + 1]),
+        ("Big.rsc", big.clone()),
+    ];
+    for (name, data) in &files {
+        fs.write_file(name, data).unwrap();
+    }
+    for (name, data) in &files {
+        assert_eq!(&fs.read_file(name).unwrap(), data, "{name}");
+    }
+
+    // Files.Mod keeps a full last sector as bleng = SECTOR_SIZE
+    let adr = fs.search("Exact.Bin").unwrap().unwrap();
+    let h = fs.header(adr).unwrap();
+    assert_eq!((h.aleng, h.bleng), (0, SECTOR_SIZE as u32));
+    // 200000 bytes need index sectors
+    let adr = fs.search("Big.rsc").unwrap().unwrap();
+    let h = fs.header(adr).unwrap();
+    assert!(h.aleng as usize >= SEC_TAB_SIZE && h.ext[0] != 0);
+
+    let names: Vec<_> = fs.entries().unwrap().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, ["Big.rsc", "Empty.Txt", "Exact.Bin", "Small.Mod", "Two.Bin"]);
+    let list = fs.list().unwrap();
+    assert_eq!(list[0].length, big.len());
+
+    // new sectors come after the reserved boot area
+    assert_eq!(fs.sector_map().unwrap().alloc().unwrap() % SECTOR_MULT, 0);
+    assert!(fs.search("Empty.Txt").unwrap().unwrap() >= RESERVED_SECTORS * SECTOR_MULT);
+
+    assert!(matches!(fs.write_file("1bad", b""), Err(FsError::InvalidName(_))));
+    assert!(matches!(fs.read_file("Missing"), Err(FsError::NotFound(_))));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fs_directory_btree_grows_and_shrinks() {
+    let path = fs_only("tree");
+    let mut fs = FileSystem::open(&path).unwrap();
+
+    let names: Vec<String> = (0..400).map(|i| format!("F{:04}.Mod", (i * 37) % 400)).collect();
+    for n in &names {
+        fs.write_file(n, n.as_bytes()).unwrap();
+    }
+    assert_eq!(fs.entries().unwrap().len(), 400);
+    assert!(fs.dir_pages().unwrap().len() > 1);
+    assert_balanced(&mut fs);
+    for n in &names {
+        assert_eq!(fs.read_file(n).unwrap(), n.as_bytes());
+    }
+
+    for n in names.iter().step_by(2) {
+        fs.delete(n).unwrap();
+    }
+    fs.rename("F0001.Mod", "Renamed.Mod").unwrap();
+    assert_balanced(&mut fs);
+    assert_eq!(fs.entries().unwrap().len(), 200);
+    assert_eq!(fs.read_file("Renamed.Mod").unwrap(), b"F0001.Mod");
+    assert!(fs.search("F0001.Mod").unwrap().is_none());
+    let adr = fs.search("Renamed.Mod").unwrap().unwrap();
+    let h = fs.header(adr).unwrap();
+    assert_eq!(h.name, "Renamed.Mod");
+
+    // replacing keeps one entry
+    fs.write_file("Renamed.Mod", b"new").unwrap();
+    assert_eq!(fs.read_file("Renamed.Mod").unwrap(), b"new");
+    assert_eq!(fs.entries().unwrap().len(), 200);
+
+    for e in fs.entries().unwrap() {
+        fs.delete(&e.name).unwrap();
+    }
+    assert_eq!(fs.dir_pages().unwrap(), [DIR_ROOT_ADR]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fs_full_image_layout() {
+    let path = temp("full");
+    let f = std::fs::File::create(&path).unwrap();
+    // sparse: the file system starts 256 MB into the card
+    f.set_len((FS_ONLY_OFFSET as u64 + 2) * 512).unwrap();
+    let mut img = Image::with_offset(f, 0);
+    img.write_sector(DIR_ROOT_ADR, &DirPage::default().encode()).unwrap();
+    drop(img);
+
+    let mut fs = FileSystem::open(&path).unwrap();
+    assert_eq!(fs.image().offset(), 0);
+    fs.write_file("System.Tool", b"System.Directory").unwrap();
+    let adr = fs.search("System.Tool").unwrap().unwrap();
+    let pos = fs.image().position(adr).unwrap();
+    assert_eq!(pos, (0x80000 + 2 * (adr / SECTOR_MULT) as u64) * 512);
+
+    let bytes = std::fs::read(&path).unwrap();
+    let at = pos as usize;
+    assert_eq!(word(&bytes, at), HEADER_MARK);
+    assert_eq!(&bytes[at + HEADER_SIZE..at + HEADER_SIZE + 6], b"System");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fs_rejects_non_images() {
+    let path = temp("none");
+    std::fs::write(&path, vec![0u8; 4096]).unwrap();
+    assert!(matches!(FileSystem::open(&path), Err(FsError::NoFileSystem)));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fs_dates() {
+    let d = encode_date(2024, 3, 15, 13, 45, 30);
+    assert_eq!(decode_date(d), (2024, 3, 15, 13, 45, 30));
+    assert!(decode_date(now()).0 >= 2024);
+}
+
+#[test]
+fn fs_create_images() {
+    use risc_emulator::devices::disk::Disk;
+    use risc_emulator::fs::Layout;
+
+    let boot: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+    for (layout, name, offset) in [(Layout::FsOnly, "mk-fs", FS_ONLY_OFFSET), (Layout::Full, "mk-full", 0)] {
+        let path = temp(name);
+        let _ = std::fs::remove_file(&path);
+        let mut fs = FileSystem::create(&path, layout, 1024, false).unwrap();
+        fs.install_boot_file(&boot).unwrap();
+        drop(fs);
+        assert!(FileSystem::create(&path, layout, 1024, false).is_err());
+
+        // the card sees the boot file at SD block 0x80004
+        assert_eq!(Disk::new(Some(&path)).unwrap().offset(), offset);
+        let at = (0x80004 - offset) as u64 * 512;
+        assert_eq!(read_at(&path, at, boot.len()), boot);
+
+        let mut fs = FileSystem::open(&path).unwrap();
+        assert_eq!(fs.image().offset(), offset);
+        assert!(fs.entries().unwrap().is_empty());
+        fs.write_file("Hello.Text", b"hi").unwrap();
+        assert_eq!(fs.read_file("Hello.Text").unwrap(), b"hi");
+        // the boot file is not disturbed by allocation
+        assert_eq!(read_at(&path, at, boot.len()), boot);
+
+        assert!(fs.install_boot_file(&vec![0; MAX_BOOT_SIZE + 1]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+    assert!(FileSystem::create(&temp("mk-small"), Layout::FsOnly, 10, true).is_err());
+}
+
+mod fsck {
+    use super::*;
+    use risc_emulator::fs::fsck::{FsckOptions, Problem};
+    use risc_emulator::fs::FileHeader;
+
+    const REPAIR: FsckOptions = FsckOptions { repair: true, recover_lost: false };
+
+    fn edit_header(fs: &mut FileSystem, name: &str, f: impl FnOnce(&mut FileHeader)) -> FileHeader {
+        let adr = fs.search(name).unwrap().unwrap();
+        let mut s = fs.image().read_sector(adr).unwrap();
+        let mut h = FileHeader::decode(&s, adr).unwrap();
+        f(&mut h);
+        h.encode_into(&mut s);
+        fs.image().write_sector(adr, &s).unwrap();
+        h
+    }
+
+    fn sample(name: &str) -> (PathBuf, FileSystem) {
+        let path = fs_only(name);
+        let mut fs = FileSystem::open(&path).unwrap();
+        for (i, n) in ["A.Bin", "B.Bin", "C.Bin"].iter().enumerate() {
+            fs.write_file(n, &vec![i as u8 + 1; 5000]).unwrap();
+        }
+        (path, fs)
+    }
+
+    #[test]
+    fn fsck_clean_image() {
+        let (path, mut fs) = sample("ck-clean");
+        fs.write_file("A.Bin", b"second version").unwrap();
+        let r = fs.fsck(FsckOptions::default()).unwrap();
+        assert!(r.is_clean(), "{:?}", r.problems);
+        assert_eq!((r.files, r.dir_pages, r.stale_headers), (3, 1, 1));
+        assert!(!r.repaired);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fsck_cross_link_and_dangling_sectors() {
+        let (path, mut fs) = sample("ck-links");
+        let a = edit_header(&mut fs, "A.Bin", |_| {});
+        edit_header(&mut fs, "B.Bin", |h| h.sec[2] = a.sec[1]);
+        edit_header(&mut fs, "C.Bin", |h| {
+            h.sec[3] = 5 * SECTOR_MULT;
+            h.name = "Wrong".into();
+        });
+
+        let r = fs.fsck(FsckOptions::default()).unwrap();
+        assert!(r.problems.iter().any(|p| matches!(p, Problem::CrossLinked { adr, .. } if *adr == a.sec[1])));
+        assert!(r.problems.iter().any(|p| matches!(p, Problem::BadSector { index: 3, .. })));
+        assert!(r.problems.iter().any(|p| matches!(p, Problem::NameMismatch { .. })));
+        assert_eq!(r.problems.len(), 3, "{:?}", r.problems);
+
+        let r = fs.fsck(REPAIR).unwrap();
+        assert!(r.repaired);
+        assert!(fs.fsck(FsckOptions::default()).unwrap().is_clean());
+
+        assert_eq!(fs.read_file("A.Bin").unwrap(), vec![1; 5000]);
+        // B got its own copy of A's sector
+        let b = fs.read_file("B.Bin").unwrap();
+        let first = SECTOR_SIZE - HEADER_SIZE;
+        assert_eq!(b[first + SECTOR_SIZE], 1);
+        assert_eq!(b[0], 2);
+        // C is cut before the bad sector
+        assert_eq!(fs.read_file("C.Bin").unwrap(), vec![3; first + 2 * SECTOR_SIZE]);
+        let adr = fs.search("C.Bin").unwrap().unwrap();
+        assert_eq!(fs.header(adr).unwrap().name, "C.Bin");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fsck_recovers_files_below_a_bad_page() {
+        let path = fs_only("ck-page");
+        let mut fs = FileSystem::open(&path).unwrap();
+        for i in 0..100 {
+            fs.write_file(&format!("File{i:03}.Txt"), format!("{i}").as_bytes()).unwrap();
+        }
+        let pages = fs.dir_pages().unwrap();
+        let victim = pages[1];
+        let mut s = fs.image().read_sector(victim).unwrap();
+        put_word(&mut s, 0, 0);
+        fs.image().write_sector(victim, &s).unwrap();
+
+        let r = fs.fsck(FsckOptions::default()).unwrap();
+        assert!(matches!(r.problems[0], Problem::BadPage { adr, .. } if adr == victim));
+        let lost = r.problems.iter().filter(|p| matches!(p, Problem::LostFile { .. })).count();
+        assert!(lost >= DIR_PG_MIN);
+        assert_eq!(r.files + lost, 100);
+
+        let r = fs.fsck(FsckOptions { repair: true, recover_lost: true }).unwrap();
+        assert!(r.repaired);
+        assert!(fs.fsck(FsckOptions::default()).unwrap().is_clean());
+        assert_eq!(fs.entries().unwrap().len(), 100);
+        assert_eq!(fs.read_file("File042.Txt").unwrap(), b"42");
+        assert_balanced(&mut fs);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
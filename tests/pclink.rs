@@ -0,0 +1,120 @@
+use std::collections::{HashMap, VecDeque};
+
+use risc_emulator::bus::{BusError, BusResult};
+use risc_emulator::pclink::{self, Link, ACK, BLOCK_LEN, NAK, REC, REQ, SND};
+
+enum State {
+    Idle,
+    Name { code: u8, name: Vec<u8> },
+    BlockLen { name: String, data: Vec<u8> },
+    Block { name: String, data: Vec<u8>, len: usize, left: usize },
+    // guest -> host: waiting for the ACK of a block; `rest` is None after the last one
+    Sending { rest: Option<Vec<u8>> },
+}
+
+/// Byte-level stand-in for the PCLink1 task on the guest.
+struct FakeOberon {
+    files: HashMap<String, Vec<u8>>,
+    output: VecDeque<u8>,
+    state: State,
+}
+
+impl FakeOberon {
+    fn new() -> Self {
+        Self { files: HashMap::new(), output: VecDeque::new(), state: State::Idle }
+    }
+
+    fn send_block(&mut self, data: Vec<u8>) -> State {
+        let n = data.len().min(BLOCK_LEN);
+        self.output.push_back(n as u8);
+        self.output.extend(&data[..n]);
+        State::Sending { rest: (n == BLOCK_LEN).then(|| data[n..].to_vec()) }
+    }
+
+    fn block_done(&mut self, name: String, data: Vec<u8>, len: usize) -> State {
+        self.output.push_back(ACK);
+        if len < BLOCK_LEN {
+            self.files.insert(name, data);
+            self.output.push_back(ACK);
+            State::Idle
+        } else {
+            State::BlockLen { name, data }
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.state = match std::mem::replace(&mut self.state, State::Idle) {
+            State::Idle => match b {
+                REQ => {
+                    self.output.push_back(ACK);
+                    State::Idle
+                }
+                SND | REC => State::Name { code: b, name: Vec::new() },
+                _ => State::Idle,
+            },
+            State::Name { code, mut name } if b != 0 => {
+                name.push(b);
+                State::Name { code, name }
+            }
+            State::Name { code, name } => {
+                let name = String::from_utf8(name).unwrap();
+                if code == REC {
+                    self.output.push_back(ACK);
+                    State::BlockLen { name, data: Vec::new() }
+                } else if let Some(data) = self.files.get(&name).cloned() {
+                    self.output.push_back(ACK);
+                    self.send_block(data)
+                } else {
+                    self.output.push_back(NAK);
+                    State::Idle
+                }
+            }
+            State::BlockLen { name, data } if b == 0 => self.block_done(name, data, 0),
+            State::BlockLen { name, data } => {
+                State::Block { name, data, len: b as usize, left: b as usize }
+            }
+            State::Block { name, mut data, len, left } => {
+                data.push(b);
+                if left == 1 {
+                    self.block_done(name, data, len)
+                } else {
+                    State::Block { name, data, len, left: left - 1 }
+                }
+            }
+            State::Sending { rest } => match rest {
+                Some(rest) if b == ACK => self.send_block(rest),
+                _ => State::Idle,
+            },
+        };
+    }
+}
+
+impl Link for FakeOberon {
+    fn send(&mut self, bytes: &[u8]) -> BusResult<()> {
+        for &b in bytes {
+            self.byte(b);
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self) -> BusResult<u8> {
+        self.output.pop_front().ok_or_else(|| BusError::Device("no reply".into()))
+    }
+}
+
+#[test]
+fn pclink_push_then_pull_roundtrip() {
+    let mut guest = FakeOberon::new();
+    pclink::ping(&mut guest).unwrap();
+
+    for len in [0usize, 10, BLOCK_LEN, 2 * BLOCK_LEN + 7] {
+        let data: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+        pclink::push(&mut guest, "Test.Mod", &data).unwrap();
+        assert_eq!(guest.files["Test.Mod"], data);
+        assert_eq!(pclink::pull(&mut guest, "Test.Mod").unwrap(), data);
+        assert!(guest.output.is_empty());
+    }
+
+    assert!(pclink::pull(&mut guest, "Missing.Mod").is_err());
+    assert!(pclink::push(&mut guest, "bad name", b"x").is_err());
+}
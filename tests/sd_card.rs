@@ -0,0 +1,51 @@
+// SPI side of an SD card, for tests that drive a `Disk` directly.
+
+use risc_emulator::devices::disk::R1_IDLE;
+use risc_emulator::devices::spi::SpiDevice;
+
+pub fn xfer(d: &mut dyn SpiDevice, v: u32) -> u32 {
+    d.write_data(v).unwrap();
+    d.read_data().unwrap()
+}
+
+/// Sends a command frame and returns R1 (CRC 0x01 = "don't care").
+pub fn cmd(d: &mut dyn SpiDevice, cmd: u8, arg: u32, crc: u8) -> u32 {
+    xfer(d, 0x40 | cmd as u32);
+    for b in arg.to_be_bytes() {
+        xfer(d, b as u32);
+    }
+    xfer(d, crc as u32);
+    (0..8).map(|_| xfer(d, 0xFF)).find(|&r| r < 0x80).expect("no R1")
+}
+
+pub fn init(d: &mut dyn SpiDevice) {
+    assert_eq!(cmd(d, 0, 0, 0x95), R1_IDLE);
+    assert_eq!(cmd(d, 8, 0x1AA, 0x87), R1_IDLE);
+    let mut polls = 0;
+    loop {
+        assert_eq!(cmd(d, 55, 0, 1), R1_IDLE);
+        polls += 1;
+        if cmd(d, 41, 1 << 30, 1) == 0 {
+            break;
+        }
+    }
+    assert_eq!(polls, 2);
+}
+
+pub fn image(name: &str, blocks: usize) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("risc-sd-{}-{name}.img", std::process::id()));
+    std::fs::write(&path, vec![0u8; blocks * 512]).unwrap();
+    path
+}
+
+pub fn write_block(d: &mut dyn SpiDevice, token: u32, words: &[u32; 128]) -> u32 {
+    xfer(d, token);
+    for &w in words {
+        xfer(d, w);
+    }
+    xfer(d, 0xFF);
+    xfer(d, 0xFF);
+    let resp = (0..8).map(|_| xfer(d, 0xFF)).find(|&r| r != 0xFF).unwrap();
+    while xfer(d, 0xFF) != 0xFF {}
+    resp & 0x1F
+}
@@ -7,11 +7,12 @@ pub const ROM_START: u32 = 0xFFFF_F800;
 pub struct TestBus {
     pub ram: Vec<u32>, // word addressed
     pub rom: Vec<u32>, // word addressed
+    pub irq: bool,
 }
 
 impl TestBus {
     pub fn new(ram_words: usize, rom_words: usize) -> Self {
-        Self { ram: vec![0; ram_words], rom: vec![0; rom_words] }
+        Self { ram: vec![0; ram_words], rom: vec![0; rom_words], irq: false }
     }
 
     fn read_word_raw(&self, addr: u32) -> BusResult<u32> {
@@ -48,4 +49,8 @@ impl CpuBus for TestBus {
     fn write_word(&mut self, addr: u32, value: u32) -> BusResult<()> {
         self.write_word_raw(addr & !3, value)
     }
+
+    fn irq_pending(&mut self) -> bool {
+        self.irq
+    }
 }